@@ -33,25 +33,78 @@
 // Software Foundation.
 //
 
-use crate::{FlashChip, FlashromError, ROMWriteSpecifics};
-
-use std::process::Command;
+use crate::transcript::{Entry, Transcript};
+use crate::{ErrorKind, FlashChip, FlashromError, ROMWriteSpecifics, WpStatus};
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often a running flashrom is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Messages in flashrom's stderr indicating a failure that may not recur,
+/// usually from external programmers that intermittently fail to initialize.
+const TRANSIENT_ERRORS: &[&str] = &[
+    "Programmer initialization failed",
+    "Could not open USB device",
+    "Failed to claim interface",
+];
+
+/// Messages in flashrom's stderr indicating an operation was refused because
+/// the flash is write protected, from both the internal programmer and the
+/// kernel SPI drivers.
+const WP_ERRORS: &[&str] = &[
+    "Block protection could not be disabled",
+    "is write protected",
+    "is read-only",
+    "Operation not permitted",
+];
+
+/// Messages in flashrom's stderr indicating an image was refused because its
+/// size doesn't match the flash, from older and newer versions.
+const SIZE_ERRORS: &[&str] = &[
+    "doesn't match the flash chip's size",
+    "doesn't match the expected size",
+];
+
+/// How long dut-control may take before servod is assumed to be wedged.
+const DUT_CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Messages in dut-control's stderr indicating that servod isn't running.
+const SERVOD_UNAVAILABLE_ERRORS: &[&str] = &["Connection refused", "No servod scratch entry"];
 
 #[derive(Default)]
 pub struct FlashromOpt<'a> {
-    pub wp_opt: WPOpt,
+    pub wp_opt: WPOpt<'a>,
     pub io_opt: IOOpt<'a>,
 
     pub layout: Option<&'a str>, // -l <file>
     pub image: Option<&'a str>,  // -i <name>
+    /// Further regions to operate on along with `image`, each given its own -i.
+    pub images: Vec<&'a str>, // -i <name> ...
+    /// Take the layout from the Intel Flash Descriptor, so `image` names one of
+    /// its regions (such as bios, me or gbe). Mutually exclusive with `layout`.
+    pub ifd: bool, // --ifd
 
     pub flash_name: bool, // --flash-name
-    pub verbose: bool,    // -V
+    pub flash_size: bool, // --flash-size
+    pub verbose: u8,      // -V, repeated for more detail
+    pub progress: bool,   // --progress
+    /// Have flashrom write its own verbose log to this file.
+    pub output_log: Option<&'a str>, // -o <file>
 }
 
 #[derive(Default)]
-pub struct WPOpt {
+pub struct WPOpt<'a> {
     pub range: Option<(i64, i64)>, // --wp-range x0 x1
+    pub region: Option<&'a str>,   // --wp-region <name>
     pub status: bool,              // --wp-status
     pub list: bool,                // --wp-list
     pub enable: bool,              // --wp-enable
@@ -65,12 +118,118 @@ pub struct IOOpt<'a> {
     pub verify: Option<&'a str>,            // -v <file>
     pub erase: bool,                        // -E
     pub region: Option<(&'a str, &'a str)>, // --image
+    pub write_verify: WriteVerify,          // --noverify / --noverify-all
+}
+
+/// How much of the flash flashrom verifies itself after writing it.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum WriteVerify {
+    /// Verify the whole flash, flashrom's default.
+    #[default]
+    All,
+    /// Only verify the regions included in the write (--noverify-all).
+    Included,
+    /// Don't verify at all (--noverify), for when a separate verify follows.
+    Off,
+}
+
+impl std::str::FromStr for WriteVerify {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(WriteVerify::All),
+            "included" => Ok(WriteVerify::Included),
+            "off" => Ok(WriteVerify::Off),
+            _ => Err(format!("unknown write verification {:?}", s)),
+        }
+    }
 }
 
-#[derive(PartialEq, Debug)]
+/// Which of flashrom's output streams to capture; the other is discarded.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Capture {
+    #[default]
+    Both,
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug)]
 pub struct FlashromCmd {
     pub path: String,
     pub fc: FlashChip,
+    /// Lines of --flash-size and --flash-name output matching this are ignored,
+    /// for programmers that print output the parsers mistake for the answer.
+    pub probe_filter: Option<Regex>,
+    /// Log the arguments encoded for every operation before running flashrom.
+    pub trace_args: bool,
+    /// Record the output of every flashrom invocation, or replay it instead of
+    /// running flashrom.
+    pub transcript: Option<Transcript>,
+    /// Kill any flashrom invocation that runs longer than this.
+    pub max_runtime: Option<Duration>,
+    /// How many times to retry an invocation that failed transiently.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub retry_delay: Duration,
+    /// The first size successfully read by get_size, which is returned from
+    /// then on rather than probing the chip again.
+    pub size: OnceCell<i64>,
+    /// A programmer to pass to `-p` verbatim instead of the one for `fc`, which
+    /// still decides how the chip is treated otherwise.
+    pub programmer: Option<String>,
+    /// Extra `key=value` programmer parameters, appended in order to the
+    /// programmer's own in the `-p` argument.
+    pub programmer_params: Vec<String>,
+    /// How flashrom verifies its writes.
+    pub write_verify: WriteVerify,
+    /// Have flashrom report progress on reads, writes and erases, and log it.
+    pub progress: bool,
+    /// Pass -V this many times to every invocation, at least, so that flashrom's
+    /// detailed logs appear in the debug output.
+    pub verbosity: u8,
+    /// Log that flashrom is still running this often while waiting for it.
+    pub heartbeat: Option<Duration>,
+    /// Keep flashrom's own log of every invocation in this directory, in a
+    /// file named after the time and operation.
+    pub flashrom_log: Option<String>,
+}
+
+/// Remove lines matching `filter` from flashrom output.
+fn filter_lines<'s>(stdout: &'s str, filter: Option<&Regex>) -> Cow<'s, str> {
+    match filter {
+        None => Cow::Borrowed(stdout),
+        Some(re) => Cow::Owned(
+            stdout
+                .lines()
+                .filter(|line| !re.is_match(line))
+                .map(|line| format!("{}\n", line))
+                .collect(),
+        ),
+    }
+}
+
+/// Check that a layout region name can be passed to flashrom as a single,
+/// unambiguous argument.
+///
+/// Region names are joined with other text into flashrom arguments (such as
+/// `--image name:path`), so only a conservative set of characters is allowed.
+fn check_region_name(name: &str) -> Result<(), FlashromError> {
+    if name.is_empty() {
+        return Err("Layout region name is empty".into());
+    }
+    match name
+        .chars()
+        .find(|&c| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.'))
+    {
+        None => Ok(()),
+        Some(c) => Err(format!(
+            "Layout region name {:?} contains {:?}; only ASCII letters, digits, '_', '-' and '.' are allowed",
+            name, c
+        )
+        .into()),
+    }
 }
 
 /// Attempt to determine the Flash size given stdout from `flashrom --flash-size`
@@ -84,9 +243,16 @@ fn flashrom_extract_size(stdout: &str) -> Result<i64, FlashromError> {
         .last()
         .map(str::parse::<i64>)
     {
-        None => return Err("Found no purely-numeric lines in flashrom output".into()),
+        None => {
+            return Err(FlashromError::parse(
+                "Found no purely-numeric lines in flashrom output",
+            ))
+        }
         Some(Err(e)) => {
-            return Err(format!("Failed to parse flashrom size output as integer: {}", e).into())
+            return Err(FlashromError::parse(format!(
+                "Failed to parse flashrom size output as integer: {}",
+                e
+            )))
         }
         Some(Ok(sz)) => Ok(sz),
     }
@@ -96,18 +262,193 @@ impl FlashromCmd {
     fn dispatch(
         &self,
         fropt: FlashromOpt,
+        capture: Capture,
+        debug_name: &str,
+    ) -> Result<(String, String), FlashromError> {
+        self.dispatch_with_retries(
+            fropt,
+            capture,
+            debug_name,
+            self.retries,
+            self.retry_delay,
+            None,
+        )
+        .and_then(flashrom_result)
+    }
+
+    /// Run flashrom as `dispatch` does, but call `on_stderr` with each line of
+    /// its stderr as it is printed rather than only returning it at exit.
+    ///
+    /// flashrom separates progress updates with carriage returns, so those end
+    /// lines too.
+    fn dispatch_streaming(
+        &self,
+        fropt: FlashromOpt,
+        capture: Capture,
+        debug_name: &str,
+        on_stderr: &mut dyn FnMut(&str),
+    ) -> Result<(String, String), FlashromError> {
+        self.dispatch_with_retries(
+            fropt,
+            capture,
+            debug_name,
+            self.retries,
+            self.retry_delay,
+            Some(on_stderr),
+        )
+        .and_then(flashrom_result)
+    }
+
+    /// Run a long operation, with flashrom's progress logged if enabled.
+    fn dispatch_with_progress(
+        &self,
+        mut fropt: FlashromOpt,
+        capture: Capture,
         debug_name: &str,
     ) -> Result<(String, String), FlashromError> {
-        let params = flashrom_decode_opts(fropt);
-        flashrom_dispatch(self.path.as_str(), &params, self.fc, debug_name)
+        if !self.progress {
+            return self.dispatch(fropt, capture, debug_name);
+        }
+        fropt.progress = true;
+        let mut last = None;
+        self.dispatch_streaming(fropt, capture, debug_name, &mut |line| {
+            if let Some(percent) = parse_progress(line) {
+                // Only log whole-percent changes, as flashrom reports far more often.
+                let whole = percent as u32;
+                if last != Some(whole) {
+                    info!("{}(): {}%", debug_name, whole);
+                    last = Some(whole);
+                }
+            }
+        })
+    }
+
+    /// Run flashrom, retrying up to `retries` times if it fails with one of
+    /// `TRANSIENT_ERRORS`. Waits `backoff` before the first retry and twice as
+    /// long before each one after.
+    ///
+    /// Returns the record of the last attempt whatever its exit status, for
+    /// callers that need flashrom's output even when it fails.
+    fn dispatch_with_retries(
+        &self,
+        fropt: FlashromOpt,
+        capture: Capture,
+        debug_name: &str,
+        retries: u32,
+        backoff: Duration,
+        mut on_stderr: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Entry, FlashromError> {
+        for name in fropt.image.iter().chain(&fropt.images) {
+            check_region_name(name)?;
+        }
+        if let Some((name, _)) = fropt.io_opt.region {
+            check_region_name(name)?;
+        }
+        if fropt.ifd && fropt.layout.is_some() {
+            return Err("--ifd and a layout file cannot both be given".into());
+        }
+        if let Some(name) = fropt.wp_opt.region {
+            if fropt.wp_opt.range.is_some() {
+                return Err("Write protect range and region cannot both be given".into());
+            }
+            check_region_name(name)?;
+        }
+        // Unless the caller chose a log file, each attempt logs to a new one.
+        let log_dir = match fropt.output_log {
+            None => self.flashrom_log.as_deref(),
+            Some(_) => None,
+        };
+        let fropt = FlashromOpt {
+            verbose: fropt.verbose.max(self.verbosity),
+            ..fropt
+        };
+        let base_params = flashrom_decode_opts(fropt);
+
+        let mut delay = backoff;
+        let mut attempt = 0;
+        loop {
+            let mut params = base_params.clone();
+            if let Some(dir) = log_dir {
+                params.push("-o".to_string());
+                params.push(flashrom_log_path(dir, debug_name, attempt));
+            }
+            if self.trace_args {
+                info!("{}() encoded flashrom args: {:?}", debug_name, params);
+            }
+            let on_line = on_stderr.as_mut().map(|f| &mut **f as &mut dyn FnMut(&str));
+            let entry = self.run_or_replay(&params, capture, debug_name, on_line)?;
+            if attempt < retries && is_transient(&entry) {
+                attempt += 1;
+                warn!(
+                    "{}() failed transiently; retry {} of {} in {:?}",
+                    debug_name, attempt, retries, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                continue;
+            }
+            return Ok(entry);
+        }
+    }
+
+    fn run_or_replay(
+        &self,
+        params: &[String],
+        capture: Capture,
+        debug_name: &str,
+        on_stderr: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Entry, FlashromError> {
+        let replayed = match &self.transcript {
+            Some(t) => t.next(debug_name)?,
+            None => None,
+        };
+        let entry = match replayed {
+            Some(entry) => {
+                info!("Replaying {}() from transcript", debug_name);
+                if let Some(on_line) = on_stderr {
+                    entry.stderr.split(&['\r', '\n'][..]).for_each(on_line);
+                }
+                entry
+            }
+            None => {
+                let _heartbeat = self
+                    .heartbeat
+                    .map(|interval| Heartbeat::start(debug_name, interval));
+                let entry = run_flashrom(
+                    self.path.as_str(),
+                    params,
+                    &programmer_arg(self.fc, self.programmer.as_deref(), &self.programmer_params),
+                    capture,
+                    self.max_runtime,
+                    debug_name,
+                    on_stderr,
+                )?;
+                if let Some(t) = &self.transcript {
+                    t.append(&entry)?;
+                }
+                entry
+            }
+        };
+        Ok(entry)
     }
 }
 
 impl crate::Flashrom for FlashromCmd {
     fn get_size(&self) -> Result<i64, FlashromError> {
-        let (stdout, _) =
-            flashrom_dispatch(self.path.as_str(), &["--flash-size"], self.fc, "get_size")?;
-        flashrom_extract_size(&stdout)
+        if let Some(size) = self.size.get() {
+            return Ok(*size);
+        }
+        let size = self.probe_size()?;
+        Ok(*self.size.get_or_init(|| size))
+    }
+
+    fn probe_size(&self) -> Result<i64, FlashromError> {
+        let opts = FlashromOpt {
+            flash_size: true,
+            ..Default::default()
+        };
+        let (stdout, _) = self.dispatch(opts, Capture::Both, "get_size")?;
+        flashrom_extract_size(&filter_lines(&stdout, self.probe_filter.as_ref()))
     }
 
     fn name(&self) -> Result<(String, String), FlashromError> {
@@ -121,17 +462,53 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        let (stdout, _) = self.dispatch(opts, "name")?;
-        match extract_flash_name(&stdout) {
-            None => Err("Didn't find chip vendor/name in flashrom output".into()),
+        let (stdout, _) = self.dispatch(opts, Capture::Both, "name")?;
+        match extract_flash_name(&filter_lines(&stdout, self.probe_filter.as_ref())) {
+            None => Err(FlashromError::parse(
+                "Didn't find chip vendor/name in flashrom output",
+            )),
             Some((vendor, name)) => Ok((vendor.into(), name.into())),
         }
     }
 
+    fn probe_chips(&self) -> Result<Vec<String>, FlashromError> {
+        // With no operation flashrom only probes. It fails if the chip matches
+        // several definitions, but still lists them, so parse before checking.
+        let entry = self.dispatch_with_retries(
+            FlashromOpt::default(),
+            Capture::Both,
+            "probe_chips",
+            self.retries,
+            self.retry_delay,
+            None,
+        )?;
+        let output = format!("{}\n{}", entry.stdout, entry.stderr);
+        let chips = extract_probed_chips(&filter_lines(&output, self.probe_filter.as_ref()));
+        if chips.is_empty() {
+            flashrom_result(entry)?;
+        }
+        Ok(chips)
+    }
+
+    fn voltage(&self) -> Result<String, FlashromError> {
+        let opts = FlashromOpt {
+            flash_name: true,
+            verbose: 1,
+            ..Default::default()
+        };
+
+        let (stdout, stderr) = self.dispatch(opts, Capture::Both, "voltage")?;
+        extract_voltage(&stdout)
+            .or_else(|| extract_voltage(&stderr))
+            .map(String::from)
+            .ok_or_else(|| "flashrom didn't report a voltage".into())
+    }
+
     fn write_file_with_layout(&self, rws: &ROMWriteSpecifics) -> Result<bool, FlashromError> {
         let opts = FlashromOpt {
             io_opt: IOOpt {
                 write: rws.write_file,
+                write_verify: self.write_verify,
                 ..Default::default()
             },
 
@@ -141,10 +518,26 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "write_file_with_layout")?;
+        self.dispatch(opts, Capture::Stderr, "write_file_with_layout")?;
         Ok(true)
     }
 
+    fn write_ifd_region(&self, path: &str, region: &str) -> Result<(), FlashromError> {
+        let opts = FlashromOpt {
+            io_opt: IOOpt {
+                write: Some(path),
+                write_verify: self.write_verify,
+                ..Default::default()
+            },
+            ifd: true,
+            image: Some(region),
+            ..Default::default()
+        };
+
+        self.dispatch(opts, Capture::Stderr, "write_ifd_region")?;
+        Ok(())
+    }
+
     fn wp_range(&self, range: (i64, i64), wp_enable: bool) -> Result<bool, FlashromError> {
         let opts = FlashromOpt {
             wp_opt: WPOpt {
@@ -155,7 +548,27 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "wp_range")?;
+        self.dispatch(opts, Capture::Both, "wp_range")?;
+        Ok(true)
+    }
+
+    fn wp_region(
+        &self,
+        layout_file: &str,
+        region: &str,
+        wp_enable: bool,
+    ) -> Result<bool, FlashromError> {
+        let opts = FlashromOpt {
+            wp_opt: WPOpt {
+                region: Some(region),
+                enable: wp_enable,
+                ..Default::default()
+            },
+            layout: Some(layout_file),
+            ..Default::default()
+        };
+
+        self.dispatch(opts, Capture::Both, "wp_region")?;
         Ok(true)
     }
 
@@ -168,7 +581,7 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        let (stdout, _) = self.dispatch(opts, "wp_list")?;
+        let (stdout, _) = self.dispatch(opts, Capture::Both, "wp_list")?;
         if stdout.len() == 0 {
             return Err(
                 "wp_list isn't supported on platforms using the Linux kernel SPI driver wp_list"
@@ -190,11 +603,24 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        let (stdout, _) = self.dispatch(opts, "wp_status")?;
+        let (stdout, _) = self.dispatch(opts, Capture::Both, "wp_status")?;
         let s = std::format!("write protect is {}abled", status);
         Ok(stdout.contains(&s))
     }
 
+    fn wp_get_state(&self) -> Result<WpStatus, FlashromError> {
+        let opts = FlashromOpt {
+            wp_opt: WPOpt {
+                status: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let (stdout, _) = self.dispatch(opts, Capture::Both, "wp_get_state")?;
+        parse_wp_status(&stdout).map_err(|e| FlashromError::parse(e.to_string()))
+    }
+
     fn wp_toggle(&self, en: bool) -> Result<bool, FlashromError> {
         let status = if en { "en" } else { "dis" };
 
@@ -216,7 +642,7 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "wp_toggle")?;
+        self.dispatch(opts, Capture::Both, "wp_toggle")?;
 
         match self.wp_status(true) {
             Ok(_ret) => {
@@ -236,7 +662,7 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "read_into_file")?;
+        self.dispatch_with_progress(opts, Capture::Both, "read_into_file")?;
         Ok(())
     }
 
@@ -249,7 +675,7 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        let (stdout, _) = self.dispatch(opts, "read_region_into_file")?;
+        self.dispatch(opts, Capture::Both, "read_region_into_file")?;
         Ok(())
     }
 
@@ -257,12 +683,13 @@ impl crate::Flashrom for FlashromCmd {
         let opts = FlashromOpt {
             io_opt: IOOpt {
                 write: Some(path),
+                write_verify: self.write_verify,
                 ..Default::default()
             },
             ..Default::default()
         };
 
-        self.dispatch(opts, "write_from_file")?;
+        self.dispatch_with_progress(opts, Capture::Stderr, "write_from_file")?;
         Ok(())
     }
 
@@ -275,7 +702,29 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "verify_from_file")?;
+        self.dispatch_with_progress(opts, Capture::Both, "verify_from_file")?;
+        Ok(())
+    }
+
+    fn verify_file_with_layout(
+        &self,
+        layout_file: &str,
+        region: &str,
+        path: &str,
+    ) -> Result<(), FlashromError> {
+        let opts = FlashromOpt {
+            io_opt: IOOpt {
+                verify: Some(path),
+                ..Default::default()
+            },
+
+            layout: Some(layout_file),
+            image: Some(region),
+
+            ..Default::default()
+        };
+
+        self.dispatch(opts, Capture::Both, "verify_file_with_layout")?;
         Ok(())
     }
 
@@ -288,7 +737,12 @@ impl crate::Flashrom for FlashromCmd {
             ..Default::default()
         };
 
-        self.dispatch(opts, "erase")?;
+        self.dispatch_with_progress(opts, Capture::Stderr, "erase")?;
+        Ok(())
+    }
+
+    fn reinit_programmer(&self) -> Result<(), FlashromError> {
+        // Every operation runs a new flashrom, which initialises the programmer.
         Ok(())
     }
 
@@ -305,7 +759,10 @@ fn flashrom_decode_opts(opts: FlashromOpt) -> Vec<String> {
     // -------------------------------------
 
     // wp_opt
-    if opts.wp_opt.range.is_some() {
+    if let Some(region) = opts.wp_opt.region {
+        params.push("--wp-region".to_string());
+        params.push(region.to_string());
+    } else if opts.wp_opt.range.is_some() {
         let (x0, x1) = opts.wp_opt.range.unwrap();
         params.push("--wp-range".to_string());
         params.push(hex_range_string(x0, x1));
@@ -331,6 +788,11 @@ fn flashrom_decode_opts(opts: FlashromOpt) -> Vec<String> {
     } else if opts.io_opt.write.is_some() {
         params.push("-w".to_string());
         params.push(opts.io_opt.write.unwrap().to_string());
+        match opts.io_opt.write_verify {
+            WriteVerify::All => {}
+            WriteVerify::Included => params.push("--noverify-all".to_string()),
+            WriteVerify::Off => params.push("--noverify".to_string()),
+        }
     } else if opts.io_opt.verify.is_some() {
         params.push("-v".to_string());
         params.push(opts.io_opt.verify.unwrap().to_string());
@@ -343,66 +805,468 @@ fn flashrom_decode_opts(opts: FlashromOpt) -> Vec<String> {
         params.push("-l".to_string());
         params.push(opts.layout.unwrap().to_string());
     }
-    if opts.image.is_some() {
+    if opts.ifd {
+        params.push("--ifd".to_string());
+    }
+    for image in opts.image.iter().chain(&opts.images) {
         params.push("-i".to_string());
-        params.push(opts.image.unwrap().to_string());
+        params.push(image.to_string());
     }
 
     if opts.flash_name {
         params.push("--flash-name".to_string());
     }
-    if opts.verbose {
+    if opts.flash_size {
+        params.push("--flash-size".to_string());
+    }
+    for _ in 0..opts.verbose {
         params.push("-V".to_string());
     }
+    if opts.progress {
+        params.push("--progress".to_string());
+    }
+    if let Some(path) = opts.output_log {
+        params.push("-o".to_string());
+        params.push(path.to_string());
+    }
 
     params
 }
 
-fn flashrom_dispatch<S: AsRef<str>>(
+/// Return a path in `dir` for flashrom's log of the `debug_name` operation,
+/// suffixed with the retry number if `attempt` isn't the first.
+///
+/// flashrom truncates the log file it is given, so each invocation gets its own
+/// file, prefixed with the time in milliseconds to keep them in order.
+fn flashrom_log_path(dir: &str, debug_name: &str, attempt: u32) -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = match attempt {
+        0 => format!("{}-{}.log", millis, debug_name),
+        n => format!("{}-{}.retry{}.log", millis, debug_name, n),
+    };
+    Path::new(dir).join(name).to_string_lossy().into_owned()
+}
+
+/// Return the `-p` argument for `fc`, or `programmer` in its place if given,
+/// with `params` appended, joined with commas as flashrom expects.
+fn programmer_arg<S: AsRef<str>>(fc: FlashChip, programmer: Option<&str>, params: &[S]) -> String {
+    let mut arg = programmer.unwrap_or(FlashChip::to(fc)).to_string();
+    for (i, param) in params.iter().enumerate() {
+        let sep = if i > 0 || arg.contains(':') { ',' } else { ':' };
+        arg.push(sep);
+        arg.push_str(param.as_ref());
+    }
+    arg
+}
+
+/// Run flashrom with the given parameters, returning a record of what it did.
+///
+/// Streams not selected by `capture` are discarded and recorded empty; without
+/// stderr, failures cannot report flashrom's error messages. With `on_stderr`,
+/// stderr is always captured and each line is passed to it as it arrives.
+fn run_flashrom<S: AsRef<str>>(
     path: &str,
     params: &[S],
-    fc: FlashChip,
+    programmer: &str,
+    capture: Capture,
+    max_runtime: Option<Duration>,
     debug_name: &str,
-) -> Result<(String, String), FlashromError> {
+    on_stderr: Option<&mut dyn FnMut(&str)>,
+) -> Result<Entry, FlashromError> {
     // from man page:
     //  ' -p, --programmer <name>[:parameter[,parameter[,parameter]]] '
-    let mut args: Vec<&str> = vec!["-p", FlashChip::to(fc)];
+    let mut args: Vec<&str> = vec!["-p", programmer];
     args.extend(params.iter().map(S::as_ref));
 
-    info!("flashrom_dispatch() running: {} {:?}", path, args);
+    // --trace-args logs the arguments at info level before dispatching.
+    debug!("flashrom_dispatch() running: {} {:?}", path, args);
 
-    let output = match Command::new(path).args(&args).output() {
+    let stream = |captured: bool| {
+        if captured {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    };
+    let mut child = match Command::new(path)
+        .args(&args)
+        .stdout(stream(capture != Capture::Stderr))
+        .stderr(stream(capture != Capture::Stdout || on_stderr.is_some()))
+        .spawn()
+    {
         Ok(x) => x,
         Err(e) => return Err(format!("Failed to run flashrom: {}", e).into()),
     };
+    let stdout = drain(child.stdout.take());
+    let (stderr, status) = match on_stderr {
+        None => {
+            let stderr = drain(child.stderr.take());
+            (stderr, wait_timeout(&mut child, max_runtime))
+        }
+        Some(on_line) => {
+            let (tx, rx) = mpsc::channel();
+            let stderr = drain_lines(child.stderr.take(), tx);
+            (
+                stderr,
+                wait_streaming(&mut child, max_runtime, &rx, on_line),
+            )
+        }
+    };
+
+    let status = match status {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            // The drain threads are left behind rather than joined, since
+            // anything flashrom spawned may still hold the pipes open.
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FlashromError::timeout(format!(
+                "{}() timed out after {:?}; flashrom was killed",
+                debug_name,
+                max_runtime.unwrap_or_default()
+            )));
+        }
+        Err(e) => return Err(format!("Failed to wait for flashrom: {}", e).into()),
+    };
+    let join = |h: JoinHandle<Vec<u8>>| h.join().unwrap_or_default();
+
+    Ok(Entry {
+        op: debug_name.into(),
+        args: args.into_iter().map(String::from).collect(),
+        status: status.code(),
+        stdout: String::from_utf8_lossy(&join(stdout)).into(),
+        stderr: String::from_utf8_lossy(&join(stderr)).into(),
+    })
+}
+
+/// Logs that an operation is still running at a fixed interval from another
+/// thread, until dropped.
+struct Heartbeat {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
 
-    let stdout = String::from_utf8_lossy(output.stdout.as_slice());
-    let stderr = String::from_utf8_lossy(output.stderr.as_slice());
-    debug!("{}()'stdout: {}.", debug_name, stdout);
-    debug!("{}()'stderr: {}.", debug_name, stderr);
-
-    if !output.status.success() {
-        // There is two cases on failure;
-        //  i. ) A bad exit code,
-        //  ii.) A SIG killed us.
-        match output.status.code() {
-            Some(code) => {
-                return Err(format!("{}\nExited with error code: {}", stderr, code).into());
+impl Heartbeat {
+    fn start(debug_name: &str, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let name = debug_name.to_string();
+        let start = Instant::now();
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                info!(
+                    "{}() still running ({}s elapsed)",
+                    name,
+                    start.elapsed().as_secs()
+                );
             }
-            None => return Err("Process terminated by a signal".into()),
+        });
+        Heartbeat {
+            stop,
+            thread: Some(thread),
         }
     }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read all of a child's output pipe on another thread, so the child can't
+/// block on a full pipe while it is being waited for.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
 
-    Ok((stdout.into(), stderr.into()))
+/// Read all of a child's output pipe on another thread as `drain` does, also
+/// sending each line to `lines` as soon as it is complete.
+fn drain_lines<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    lines: mpsc::Sender<String>,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let mut line_start = 0;
+        let mut chunk = [0u8; 4096];
+        if let Some(mut pipe) = pipe {
+            loop {
+                let n = match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                buf.extend_from_slice(&chunk[..n]);
+                while let Some(end) = buf[line_start..]
+                    .iter()
+                    .position(|&b| b == b'\n' || b == b'\r')
+                {
+                    let line = &buf[line_start..line_start + end];
+                    let _ = lines.send(String::from_utf8_lossy(line).into_owned());
+                    line_start += end + 1;
+                }
+            }
+        }
+        if line_start < buf.len() {
+            let _ = lines.send(String::from_utf8_lossy(&buf[line_start..]).into_owned());
+        }
+        buf
+    })
+}
+
+/// Wait for `child` to exit as `wait_timeout` does, passing the lines received
+/// from `lines` to `on_line` in the meantime.
+fn wait_streaming(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    lines: &mpsc::Receiver<String>,
+    on_line: &mut dyn FnMut(&str),
+) -> io::Result<Option<ExitStatus>> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        match lines.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => on_line(&line),
+            // The pipe is closed, so only the exit status is left to wait for.
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return wait_timeout(
+                    child,
+                    deadline.map(|d| d.saturating_duration_since(Instant::now())),
+                );
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+        if let Some(status) = child.try_wait()? {
+            // Pass on whatever was printed before exiting.
+            while let Ok(line) = lines.recv_timeout(POLL_INTERVAL) {
+                on_line(&line);
+            }
+            return Ok(Some(status));
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Ok(None);
+        }
+    }
 }
 
-pub fn dut_ctrl_toggle_wp(en: bool) -> Result<(Vec<u8>, Vec<u8>), FlashromError> {
-    let args = if en {
-        ["fw_wp_en:off", "fw_wp:on"]
+/// Parse the percentage from a line of flashrom's progress output, such as
+/// `Reading flash... 42% complete` or `[WRITE] 4096 of 8192 bytes (50.0%)`.
+fn parse_progress(line: &str) -> Option<f32> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re =
+        RE.get_or_init(|| Regex::new(r"(\d{1,3}(?:\.\d+)?)\s*%").expect("progress regex is valid"));
+    let percent: f32 = re.captures_iter(line).last()?[1].parse().ok()?;
+    if percent <= 100.0 {
+        Some(percent)
     } else {
-        ["fw_wp_en:on", "fw_wp:off"]
+        None
+    }
+}
+
+/// Wait for `child` to exit, giving up and returning None after `timeout`.
+fn wait_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let deadline = match timeout {
+        Some(t) => Instant::now() + t,
+        None => return child.wait().map(Some),
     };
-    dut_ctrl(&args)
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Parse the output of --wp-status, in either the legacy form:
+///
+/// ```text
+/// WP: write protect is enabled.
+/// WP: write protect range: start=0x00000000, len=0x00800000
+/// ```
+///
+/// or the form used by newer flashrom:
+///
+/// ```text
+/// Protection range: start=0x00000000 length=0x00800000 (all)
+/// Protection mode: hardware
+/// ```
+///
+/// The legacy form has no mode, so it is reported as "hardware" when enabled
+/// since that is the only mode legacy flashrom enables.
+fn parse_wp_status(stdout: &str) -> Result<WpStatus, FlashromError> {
+    let hex = |s: &str| -> Result<i64, FlashromError> {
+        let s = s.trim_end_matches(',');
+        i64::from_str_radix(s.trim_start_matches("0x"), 16)
+            .map_err(|e| format!("Bad number {:?} in --wp-status output: {}", s, e).into())
+    };
+
+    let mut enabled = None;
+    let mut range = None;
+    let mut mode = None;
+    for line in stdout.lines() {
+        let line = line.trim_start_matches("WP:").trim();
+        if line.starts_with("write protect is enabled") {
+            enabled = Some(true);
+        } else if line.starts_with("write protect is disabled") {
+            enabled = Some(false);
+        } else if let Some(rest) = line
+            .strip_prefix("write protect range:")
+            .or_else(|| line.strip_prefix("Protection range:"))
+        {
+            let mut start = None;
+            let mut len = None;
+            for field in rest.split_whitespace() {
+                if let Some(v) = field.strip_prefix("start=") {
+                    start = Some(hex(v)?);
+                } else if let Some(v) = field
+                    .strip_prefix("len=")
+                    .or_else(|| field.strip_prefix("length="))
+                {
+                    len = Some(hex(v)?);
+                }
+            }
+            match (start, len) {
+                (Some(start), Some(len)) => range = Some((start, len)),
+                _ => return Err(format!("Unparseable protection range {:?}", line).into()),
+            }
+        } else if let Some(m) = line.strip_prefix("Protection mode:") {
+            mode = Some(m.trim().to_string());
+        }
+    }
+
+    let range = range.ok_or("No protection range in --wp-status output")?;
+    match (enabled, mode) {
+        (_, Some(mode)) => Ok(WpStatus {
+            enabled: mode != "disabled",
+            range,
+            mode,
+        }),
+        (Some(enabled), None) => Ok(WpStatus {
+            enabled,
+            range,
+            mode: if enabled { "hardware" } else { "disabled" }.into(),
+        }),
+        (None, None) => Err("No write protect status in --wp-status output".into()),
+    }
+}
+
+/// True if flashrom failed in a way that may succeed if retried.
+fn is_transient(entry: &Entry) -> bool {
+    matches!(entry.status, Some(code) if code != 0)
+        && TRANSIENT_ERRORS.iter().any(|e| entry.stderr.contains(e))
+}
+
+/// Work out why flashrom exited with status `code`, from what it printed.
+fn classify_failure(code: i32, stderr: &str) -> ErrorKind {
+    // Checked first, as failing to initialise can print messages that are also
+    // in WP_ERRORS, such as "Operation not permitted" when not run as root.
+    if TRANSIENT_ERRORS.iter().any(|e| stderr.contains(e)) {
+        ErrorKind::ProgrammerInit
+    } else if WP_ERRORS.iter().any(|e| stderr.contains(e)) {
+        ErrorKind::WriteProtected
+    } else if SIZE_ERRORS.iter().any(|e| stderr.contains(e)) {
+        ErrorKind::SizeMismatch
+    } else {
+        ErrorKind::NonZeroExit {
+            code,
+            stderr: stderr.into(),
+        }
+    }
+}
+
+/// Turn a record of running flashrom into its (stdout, stderr), or an error if
+/// it failed.
+fn flashrom_result(entry: Entry) -> Result<(String, String), FlashromError> {
+    debug!("{}()'stdout: {}.", entry.op, entry.stdout);
+    debug!("{}()'stderr: {}.", entry.op, entry.stderr);
+
+    // There is two cases on failure;
+    //  i. ) A bad exit code,
+    //  ii.) A SIG killed us.
+    match entry.status {
+        Some(0) => Ok((entry.stdout, entry.stderr)),
+        Some(code) => Err(FlashromError::new(
+            classify_failure(code, &entry.stderr),
+            format!("{}\nExited with error code: {}", entry.stderr, code),
+        )),
+        None => Err("Process terminated by a signal".into()),
+    }
+}
+
+pub fn dut_ctrl_toggle_wp(en: bool) -> Result<(), FlashromError> {
+    if en {
+        dut_ctrl_set(&[("fw_wp_en", "off"), ("fw_wp", "on")])
+    } else {
+        dut_ctrl_set(&[("fw_wp_en", "on"), ("fw_wp", "off")])
+    }
+}
+
+/// Return whether servo is asserting the DUT's hardware write protect.
+pub fn dut_ctrl_get_wp() -> Result<bool, FlashromError> {
+    parse_on_off("fw_wp", &dut_ctrl_get("fw_wp")?)
+}
+
+/// Set each (control, value) pair of servo controls, in order.
+pub fn dut_ctrl_set(settings: &[(&str, &str)]) -> Result<(), FlashromError> {
+    let args: Vec<String> = settings
+        .iter()
+        .map(|(control, value)| format!("{}:{}", control, value))
+        .collect();
+    dut_ctrl(&args.iter().map(String::as_str).collect::<Vec<_>>())?;
+    Ok(())
+}
+
+/// Read the current value of a servo control, such as fw_wp_state.
+pub fn dut_ctrl_get(control: &str) -> Result<String, FlashromError> {
+    let (stdout, _) = dut_ctrl(&[control])?;
+    parse_dut_ctrl(&String::from_utf8_lossy(&stdout))?
+        .into_iter()
+        .find(|(c, _)| c == control)
+        .map(|(_, value)| value)
+        .ok_or_else(|| FlashromError::parse(format!("dut-control didn't report {}", control)))
+}
+
+/// Parse dut-control's report of control values, one `control:value` per line.
+fn parse_dut_ctrl(output: &str) -> Result<Vec<(String, String)>, FlashromError> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once(':') {
+            Some((control, value)) => Ok((control.into(), value.into())),
+            None => Err(FlashromError::parse(format!(
+                "Unexpected dut-control output: {:?}",
+                line
+            ))),
+        })
+        .collect()
+}
+
+/// Interpret the value of an on/off servo control.
+fn parse_on_off(control: &str, value: &str) -> Result<bool, FlashromError> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(FlashromError::parse(format!(
+            "Unexpected dut-control {} value: {:?}",
+            control, value
+        ))),
+    }
 }
 
 pub fn dut_ctrl_servo_type() -> Result<(Vec<u8>, Vec<u8>), FlashromError> {
@@ -411,23 +1275,73 @@ pub fn dut_ctrl_servo_type() -> Result<(Vec<u8>, Vec<u8>), FlashromError> {
 }
 
 fn dut_ctrl(args: &[&str]) -> Result<(Vec<u8>, Vec<u8>), FlashromError> {
-    let output = match Command::new("dut-control").args(args).output() {
-        Ok(x) => x,
-        Err(e) => return Err(format!("Failed to run dut-control: {}", e).into()),
-    };
-    if !output.status.success() {
-        // There is two cases on failure;
-        //  i. ) A bad exit code,
-        //  ii.) A SIG killed us.
-        match output.status.code() {
-            Some(code) => {
-                return Err(format!("Exited with error code: {}", code).into());
-            }
-            None => return Err("Process terminated by a signal".into()),
+    run_dut_ctrl("dut-control", args, DUT_CONTROL_TIMEOUT)
+}
+
+/// Run `program` as dut-control with `args`, killing it after `timeout` since
+/// it can hang indefinitely if servod is wedged.
+fn run_dut_ctrl(
+    program: &str,
+    args: &[&str],
+    timeout: Duration,
+) -> Result<(Vec<u8>, Vec<u8>), FlashromError> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run dut-control: {}", e))?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = match wait_timeout(&mut child, Some(timeout)) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FlashromError::timeout(format!(
+                "dut-control {} timed out after {:?}; servod may be wedged, try restarting it",
+                args.join(" "),
+                timeout
+            )));
         }
+        Err(e) => return Err(format!("Failed to wait for dut-control: {}", e).into()),
+    };
+    let join = |h: JoinHandle<Vec<u8>>| h.join().unwrap_or_default();
+    let (stdout, stderr) = (join(stdout), join(stderr));
+
+    // There is two cases on failure;
+    //  i. ) A bad exit code,
+    //  ii.) A SIG killed us.
+    match status.code() {
+        Some(0) => Ok((stdout, stderr)),
+        Some(code) => Err(dut_ctrl_failure(code, &String::from_utf8_lossy(&stderr))),
+        None => Err("dut-control was terminated by a signal".into()),
     }
+}
 
-    Ok((output.stdout, output.stderr))
+/// Describe dut-control exiting with status `code`, telling the user to start
+/// servod if that is why it failed.
+fn dut_ctrl_failure(code: i32, stderr: &str) -> FlashromError {
+    if SERVOD_UNAVAILABLE_ERRORS.iter().any(|e| stderr.contains(e)) {
+        return FlashromError::new(
+            ErrorKind::ServodUnavailable,
+            format!(
+                "dut-control couldn't reach servod; start servod for this DUT and retry\n{}",
+                stderr.trim_end()
+            ),
+        );
+    }
+    FlashromError::new(
+        ErrorKind::NonZeroExit {
+            code,
+            stderr: stderr.into(),
+        },
+        format!(
+            "dut-control failed: {}\nExited with error code: {}",
+            stderr.trim_end(),
+            code
+        ),
+    )
 }
 
 fn hex_range_string(s: i64, l: i64) -> String {
@@ -457,10 +1371,69 @@ fn extract_flash_name(stdout: &str) -> Option<(&str, &str)> {
     None
 }
 
+/// Get the names of the chips flashrom probed, either from the line listing
+/// every matching definition when detection is ambiguous:
+///
+/// ```text
+/// Multiple flash chip definitions match the detected chip(s): "MX25L6405", "MX25L6406E"
+/// ```
+///
+/// or otherwise from the 'Found vendor flash chip "name" ...' lines.
+fn extract_probed_chips(output: &str) -> Vec<String> {
+    let quoted =
+        |s: &str| -> Vec<String> { s.split('"').skip(1).step_by(2).map(String::from).collect() };
+
+    if let Some(line) = output
+        .lines()
+        .find(|l| l.starts_with("Multiple flash chip definitions match"))
+    {
+        let (_, names) = line.split_once(':').unwrap_or_default();
+        return quoted(names);
+    }
+
+    let mut chips = Vec::new();
+    for line in output.lines() {
+        if !(line.starts_with("Found ") && line.contains(" flash chip \"")) {
+            continue;
+        }
+        if let Some(name) = quoted(line).into_iter().next() {
+            if !chips.contains(&name) {
+                chips.push(name);
+            }
+        }
+    }
+    chips
+}
+
+/// Find a voltage or voltage range on a line mentioning voltage in verbose
+/// probe output, such as 'Setting SPI voltage to 3.500 V' from dediprog or
+/// 'Chip voltage range: 1650-1950 mV'.
+fn extract_voltage(output: &str) -> Option<&str> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r"(?i)voltage\D*(\d+(?:\.\d+)?(?:\s*-\s*\d+(?:\.\d+)?)?\s*m?V)\b")
+            .expect("voltage regex is valid")
+    });
+    re.captures(output)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+}
+
 #[cfg(test)]
 mod tests {
     use super::flashrom_decode_opts;
-    use super::{FlashromOpt, IOOpt, WPOpt};
+    use super::{FlashromOpt, IOOpt, WPOpt, WriteVerify};
+
+    /// Write an executable shell script with `body` into `dir`, replacing any
+    /// written before, and return its path.
+    fn write_script(dir: &tempfile::TempDir, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.path().join("script.sh");
+        std::fs::write(&path, body).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().into()
+    }
 
     #[test]
     fn decode_wp_opt() {
@@ -485,21 +1458,38 @@ mod tests {
         );
         test_wp_opt(
             WPOpt {
-                list: true,
+                region: Some("TOP_QUAD"),
+                enable: true,
                 ..Default::default()
             },
-            &["--wp-list"],
+            &["--wp-region", "TOP_QUAD", "--wp-enable"],
         );
+        // Only one of range and region is ever passed.
         test_wp_opt(
             WPOpt {
-                enable: true,
+                range: Some((0, 1234)),
+                region: Some("TOP_QUAD"),
                 ..Default::default()
             },
-            &["--wp-enable"],
+            &["--wp-region", "TOP_QUAD"],
         );
         test_wp_opt(
             WPOpt {
-                disable: true,
+                list: true,
+                ..Default::default()
+            },
+            &["--wp-list"],
+        );
+        test_wp_opt(
+            WPOpt {
+                enable: true,
+                ..Default::default()
+            },
+            &["--wp-enable"],
+        );
+        test_wp_opt(
+            WPOpt {
+                disable: true,
                 ..Default::default()
             },
             &["--wp-disable"],
@@ -525,6 +1515,13 @@ mod tests {
             },
             &["-r", "foo.bin"],
         );
+        test_io_opt(
+            IOOpt {
+                region: Some(("RO_VPD", "vpd.bin")),
+                ..Default::default()
+            },
+            &["--image", "RO_VPD:vpd.bin", "-r"],
+        );
         test_io_opt(
             IOOpt {
                 write: Some("bar.bin"),
@@ -532,6 +1529,22 @@ mod tests {
             },
             &["-w", "bar.bin"],
         );
+        test_io_opt(
+            IOOpt {
+                write: Some("bar.bin"),
+                write_verify: WriteVerify::Included,
+                ..Default::default()
+            },
+            &["-w", "bar.bin", "--noverify-all"],
+        );
+        test_io_opt(
+            IOOpt {
+                write: Some("bar.bin"),
+                write_verify: WriteVerify::Off,
+                ..Default::default()
+            },
+            &["-w", "bar.bin", "--noverify"],
+        );
         test_io_opt(
             IOOpt {
                 verify: Some("/tmp/baz.bin"),
@@ -548,6 +1561,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_verify_with_layout() {
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                io_opt: IOOpt {
+                    verify: Some("golden.bin"),
+                    ..Default::default()
+                },
+                layout: Some("layout.file"),
+                image: Some("TOP_QUAD"),
+                ..Default::default()
+            }),
+            &["-v", "golden.bin", "-l", "layout.file", "-i", "TOP_QUAD"]
+        );
+    }
+
     #[test]
     fn decode_misc() {
         //use Default::default;
@@ -567,19 +1596,705 @@ mod tests {
             &["-i", "TestImage"]
         );
 
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                layout: Some("TestLayout"),
+                image: Some("RO_SECTION"),
+                images: vec!["RW_SECTION_A"],
+                ..Default::default()
+            }),
+            &["-l", "TestLayout", "-i", "RO_SECTION", "-i", "RW_SECTION_A"]
+        );
+
         assert_eq!(
             flashrom_decode_opts(FlashromOpt {
                 flash_name: true,
-                verbose: true,
+                verbose: 1,
                 ..Default::default()
             }),
             &["--flash-name", "-V"]
         );
+
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                verbose: 3,
+                ..Default::default()
+            }),
+            &["-V", "-V", "-V"]
+        );
+
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                flash_size: true,
+                ..Default::default()
+            }),
+            &["--flash-size"]
+        );
+
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                flash_name: true,
+                output_log: Some("/tmp/flashrom.log"),
+                ..Default::default()
+            }),
+            &["--flash-name", "-o", "/tmp/flashrom.log"]
+        );
+
+        assert_eq!(
+            flashrom_decode_opts(FlashromOpt {
+                io_opt: IOOpt {
+                    write: Some("image.bin"),
+                    ..Default::default()
+                },
+                ifd: true,
+                image: Some("me"),
+                ..Default::default()
+            }),
+            &["-w", "image.bin", "--ifd", "-i", "me"]
+        );
+    }
+
+    #[test]
+    fn check_region_name() {
+        use super::check_region_name;
+
+        assert!(check_region_name("BOTTOM_QUAD").is_ok());
+        assert!(check_region_name("RW_SECTION_A").is_ok());
+        assert!(check_region_name("FW-MAIN.1").is_ok());
+        assert!(check_region_name("").is_err());
+        assert!(check_region_name("TOP QUAD").is_err());
+        assert!(check_region_name("TOP\tQUAD").is_err());
+        assert!(check_region_name("A:B").is_err());
+        assert!(check_region_name("A;rm").is_err());
+    }
+
+    #[test]
+    fn dispatch_rejects_conflicts() {
+        use super::{Capture, FlashromCmd, FlashromOpt, WPOpt};
+        use crate::FlashChip;
+        use std::time::Duration;
+
+        let cmd = FlashromCmd {
+            path: "/nonexistent/flashrom".into(),
+            fc: FlashChip::HOST,
+            probe_filter: None,
+            trace_args: false,
+            transcript: None,
+            max_runtime: None,
+            retries: 0,
+            retry_delay: Duration::default(),
+            size: Default::default(),
+            programmer: None,
+            programmer_params: Vec::new(),
+            write_verify: Default::default(),
+            progress: false,
+            verbosity: 0,
+            heartbeat: None,
+            flashrom_log: None,
+        };
+        let opts = FlashromOpt {
+            wp_opt: WPOpt {
+                range: Some((0, 0x1000)),
+                region: Some("TOP_QUAD"),
+                enable: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = cmd.dispatch(opts, Capture::Both, "wp_region").unwrap_err();
+        assert!(err.to_string().contains("cannot both"), "{}", err);
+
+        let opts = FlashromOpt {
+            layout: Some("layout.file"),
+            ifd: true,
+            image: Some("me"),
+            ..Default::default()
+        };
+        let err = cmd.dispatch(opts, Capture::Both, "ifd").unwrap_err();
+        assert!(err.to_string().contains("cannot both"), "{}", err);
+    }
+
+    #[test]
+    fn replay_transcript() {
+        use super::FlashromCmd;
+        use crate::transcript::Transcript;
+        use crate::{FlashChip, Flashrom};
+        use std::time::Duration;
+
+        let cmd = FlashromCmd {
+            path: "/nonexistent/flashrom".into(),
+            fc: FlashChip::HOST,
+            probe_filter: None,
+            trace_args: false,
+            max_runtime: None,
+            retries: 0,
+            retry_delay: Duration::default(),
+            size: Default::default(),
+            programmer: None,
+            programmer_params: Vec::new(),
+            write_verify: Default::default(),
+            progress: false,
+            verbosity: 0,
+            heartbeat: None,
+            flashrom_log: None,
+            transcript: Some(
+                Transcript::replay(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/testdata/host_transcript.jsonl"
+                ))
+                .unwrap(),
+            ),
+        };
+
+        assert_eq!(cmd.get_size().unwrap(), 8 << 20);
+        assert_eq!(cmd.name().unwrap(), ("Winbond".into(), "W25Q64DW".into()));
+        assert!(cmd.wp_list().unwrap().contains("length=0x00800000"));
+        assert!(cmd.wp_status(false).unwrap());
+        assert!(cmd.erase().is_err());
+        // Replay is strictly in order, and runs out.
+        assert!(cmd.name().is_err());
+    }
+
+    #[test]
+    fn get_size_cached() {
+        use super::FlashromCmd;
+        use crate::transcript::Transcript;
+        use crate::{FlashChip, Flashrom};
+        use std::time::Duration;
+
+        let cmd = FlashromCmd {
+            path: "/nonexistent/flashrom".into(),
+            fc: FlashChip::HOST,
+            probe_filter: None,
+            trace_args: false,
+            max_runtime: None,
+            retries: 0,
+            retry_delay: Duration::default(),
+            size: Default::default(),
+            programmer: None,
+            programmer_params: Vec::new(),
+            write_verify: Default::default(),
+            progress: false,
+            verbosity: 0,
+            heartbeat: None,
+            flashrom_log: None,
+            transcript: Some(
+                Transcript::replay(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/testdata/host_transcript.jsonl"
+                ))
+                .unwrap(),
+            ),
+        };
+
+        assert_eq!(cmd.get_size().unwrap(), 8 << 20);
+        // A second dispatch would consume the next entry, which isn't get_size.
+        assert_eq!(cmd.get_size().unwrap(), 8 << 20);
+        assert_eq!(cmd.name().unwrap(), ("Winbond".into(), "W25Q64DW".into()));
+    }
+
+    #[test]
+    fn parse_wp_status_legacy() {
+        use super::parse_wp_status;
+        use crate::WpStatus;
+
+        assert_eq!(
+            parse_wp_status(
+                "WP: status: 0x80\n\
+                 WP: status.srp0: 1\n\
+                 WP: write protect is enabled.\n\
+                 WP: write protect range: start=0x00400000, len=0x00400000\n"
+            ),
+            Ok(WpStatus {
+                enabled: true,
+                range: (0x400000, 0x400000),
+                mode: "hardware".into(),
+            })
+        );
+        assert_eq!(
+            parse_wp_status(
+                "WP: write protect is disabled.\n\
+                 WP: write protect range: start=0x00000000, len=0x00000000\n"
+            ),
+            Ok(WpStatus {
+                enabled: false,
+                range: (0, 0),
+                mode: "disabled".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_wp_status_range_lines() {
+        use super::parse_wp_status;
+        use crate::WpStatus;
+
+        assert_eq!(
+            parse_wp_status(
+                "Protection range: start=0x00000000 length=0x00800000 (all)\n\
+                 Protection mode: hardware\n"
+            ),
+            Ok(WpStatus {
+                enabled: true,
+                range: (0, 0x800000),
+                mode: "hardware".into(),
+            })
+        );
+        assert_eq!(
+            parse_wp_status(
+                "Protection range: start=0x00000000 length=0x00000000 (none)\n\
+                 Protection mode: disabled\n"
+            )
+            .map(|s| s.enabled),
+            Ok(false)
+        );
+        assert!(parse_wp_status("Protection mode: hardware\n").is_err());
+        assert!(parse_wp_status("Protection range: start=0x0\nProtection mode: hardware").is_err());
+    }
+
+    #[test]
+    fn dispatch_capture() {
+        use super::{flashrom_result, run_flashrom, Capture};
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(&dir, "#!/bin/sh\necho out\necho err >&2\n");
+
+        let run = |capture| {
+            run_flashrom::<&str>(&script, &[], "host", capture, None, "capture_test", None)
+                .and_then(flashrom_result)
+                .unwrap()
+        };
+        assert_eq!(run(Capture::Both), ("out\n".into(), "err\n".into()));
+        assert_eq!(run(Capture::Stdout), ("out\n".into(), "".into()));
+        assert_eq!(run(Capture::Stderr), ("".into(), "err\n".into()));
+    }
+
+    #[test]
+    fn dut_ctrl_timeout() {
+        use super::run_dut_ctrl;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(
+            &dir,
+            "#!/bin/sh\n[ \"$1\" = hang ] && sleep 5\necho \"$1\"\n",
+        );
+
+        let timeout = Duration::from_millis(200);
+        assert_eq!(
+            run_dut_ctrl(&script, &["fw_wp:on"], timeout).unwrap(),
+            (b"fw_wp:on\n".to_vec(), Vec::new())
+        );
+        assert!(run_dut_ctrl(&script, &["hang"], timeout)
+            .unwrap_err()
+            .is_timeout());
+    }
+
+    #[test]
+    fn dut_ctrl_failure() {
+        use super::dut_ctrl_failure;
+        use crate::ErrorKind;
+
+        let err = dut_ctrl_failure(
+            1,
+            "ERROR:dut-control:[Errno 111] Connection refused\n\
+             ERROR:dut-control:Is servod running?\n",
+        );
+        assert_eq!(err.kind(), &ErrorKind::ServodUnavailable);
+        assert!(err.to_string().contains("start servod"), "{}", err);
+
+        let err = dut_ctrl_failure(1, "No control named fw_wp_en\n");
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::NonZeroExit {
+                code: 1,
+                stderr: "No control named fw_wp_en\n".into()
+            }
+        );
+    }
+
+    #[test]
+    fn dispatch_streaming() {
+        use super::{flashrom_result, run_flashrom, Capture};
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(
+            &dir,
+            "#!/bin/sh\necho out\nprintf 'Reading... 0%%\\rReading... 50%%\\r' >&2\n\
+             printf 'Reading... 100%%\\ndone' >&2\n",
+        );
+
+        let mut lines = Vec::new();
+        let (stdout, stderr) = run_flashrom::<&str>(
+            &script,
+            &[],
+            "host",
+            Capture::Stdout,
+            None,
+            "streaming_test",
+            Some(&mut |line: &str| lines.push(line.to_string())),
+        )
+        .and_then(flashrom_result)
+        .unwrap();
+        assert_eq!(
+            lines,
+            ["Reading... 0%", "Reading... 50%", "Reading... 100%", "done"]
+        );
+        assert_eq!(stdout, "out\n");
+        assert_eq!(
+            stderr,
+            "Reading... 0%\rReading... 50%\rReading... 100%\ndone"
+        );
+    }
+
+    #[test]
+    fn parse_progress() {
+        use super::parse_progress;
+
+        assert_eq!(parse_progress("Reading flash... 42% complete"), Some(42.0));
+        assert_eq!(
+            parse_progress("[WRITE] 4096 of 8192 bytes (50.0%)"),
+            Some(50.0)
+        );
+        assert_eq!(
+            parse_progress("Erasing and writing flash chip... 100%"),
+            Some(100.0)
+        );
+        assert_eq!(parse_progress("Reading flash... done."), None);
+        assert_eq!(parse_progress("Found chip at 0x0 (120%)"), None);
+    }
+
+    #[test]
+    fn dispatch_retries_transient() {
+        use super::{Capture, FlashromCmd, FlashromOpt};
+        use crate::FlashChip;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(&dir, "#!/bin/sh\n");
+        let count = dir.path().join("count");
+        let count = count.to_str().unwrap();
+        let cmd = FlashromCmd {
+            path: script,
+            fc: FlashChip::HOST,
+            probe_filter: None,
+            trace_args: false,
+            transcript: None,
+            max_runtime: None,
+            retries: 0,
+            retry_delay: Duration::default(),
+            size: Default::default(),
+            programmer: None,
+            programmer_params: Vec::new(),
+            write_verify: Default::default(),
+            progress: false,
+            verbosity: 0,
+            heartbeat: None,
+            flashrom_log: None,
+        };
+        // Fails with `message` until it has been run `succeed_on` times,
+        // returning whether dispatch succeeded and how many times it ran.
+        let run = |message: &str, succeed_on: usize, retries| {
+            write_script(
+                &dir,
+                &format!(
+                    "#!/bin/sh\necho x >> {count}\n\
+                     [ $(wc -l < {count}) -ge {succeed_on} ] && exit 0\n\
+                     echo '{message}' >&2\nexit 1\n"
+                ),
+            );
+            let _ = std::fs::remove_file(count);
+
+            let result = cmd
+                .dispatch_with_retries(
+                    FlashromOpt::default(),
+                    Capture::Both,
+                    "retry_test",
+                    retries,
+                    Duration::from_millis(1),
+                    None,
+                )
+                .and_then(super::flashrom_result);
+            let runs = std::fs::read_to_string(count).unwrap().lines().count();
+            (result.is_ok(), runs)
+        };
+
+        let transient = "Error: Programmer initialization failed";
+        // Successful runs return immediately.
+        assert_eq!(run(transient, 1, 3), (true, 1));
+        assert_eq!(run(transient, 3, 3), (true, 3));
+        // Retries are limited.
+        assert_eq!(run(transient, 5, 3), (false, 4));
+        assert_eq!(run(transient, 2, 0), (false, 1));
+        // Permanent failures are not retried.
+        assert_eq!(run("Error: Invalid argument", 2, 3), (false, 1));
+    }
+
+    #[test]
+    fn probe_failures_classified() {
+        use super::FlashromCmd;
+        use crate::{ErrorKind, FlashChip, Flashrom};
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(
+            &dir,
+            "#!/bin/sh\necho 'Error: Programmer initialization failed' >&2\nexit 1\n",
+        );
+        let cmd = FlashromCmd {
+            path: script,
+            fc: FlashChip::HOST,
+            probe_filter: None,
+            trace_args: false,
+            transcript: None,
+            max_runtime: None,
+            retries: 0,
+            retry_delay: Duration::default(),
+            size: Default::default(),
+            programmer: None,
+            programmer_params: Vec::new(),
+            write_verify: Default::default(),
+            progress: false,
+            verbosity: 0,
+            heartbeat: None,
+            flashrom_log: None,
+        };
+
+        // Failures are classified from stderr even though only stdout is parsed.
+        assert_eq!(
+            cmd.get_size().unwrap_err().kind(),
+            &ErrorKind::ProgrammerInit
+        );
+        assert_eq!(cmd.name().unwrap_err().kind(), &ErrorKind::ProgrammerInit);
+    }
+
+    #[test]
+    fn programmer_arg() {
+        use super::programmer_arg;
+        use crate::FlashChip;
+
+        assert_eq!(
+            programmer_arg::<&str>(FlashChip::DEDIPROG, None, &[]),
+            "dediprog"
+        );
+        assert_eq!(
+            programmer_arg::<&str>(FlashChip::HOST, None, &[]),
+            "internal"
+        );
+        assert_eq!(
+            programmer_arg(FlashChip::HOST, None, &["laptop=force"]),
+            "internal:laptop=force"
+        );
+        assert_eq!(
+            programmer_arg(FlashChip::DEDIPROG, None, &["spispeed=12M", "voltage=1.8V"]),
+            "dediprog:spispeed=12M,voltage=1.8V"
+        );
+        // Parameters follow any the programmer already has.
+        assert_eq!(
+            programmer_arg(FlashChip::SERVO, None, &["serial=1234"]),
+            "ft2232_spi:type=servo-v2,serial=1234"
+        );
+        // An override replaces the chip's programmer entirely.
+        assert_eq!(
+            programmer_arg::<&str>(FlashChip::HOST, Some("linux_spi:dev=/dev/spidev0.0"), &[]),
+            "linux_spi:dev=/dev/spidev0.0"
+        );
+        assert_eq!(
+            programmer_arg(FlashChip::HOST, Some("ch341a_spi"), &["spispeed=1M"]),
+            "ch341a_spi:spispeed=1M"
+        );
+    }
+
+    #[test]
+    fn heartbeat_stops_on_drop() {
+        use super::Heartbeat;
+        use std::time::{Duration, Instant};
+
+        // Dropping stops and joins the thread without waiting out the interval.
+        let start = Instant::now();
+        drop(Heartbeat::start("heartbeat_test", Duration::from_secs(60)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let heartbeat = Heartbeat::start("heartbeat_test", Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(50));
+        drop(heartbeat);
+    }
+
+    #[test]
+    fn dispatch_timeout() {
+        use super::{flashrom_result, run_flashrom, Capture};
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = write_script(&dir, "#!/bin/sh\necho out\nsleep \"$3\"\n");
+
+        let run = |secs| {
+            run_flashrom(
+                &script,
+                &[secs],
+                "host",
+                Capture::Both,
+                Some(Duration::from_millis(500)),
+                "timeout_test",
+                None,
+            )
+        };
+        let err = run("10").unwrap_err();
+        assert!(err.is_timeout());
+        // Output is still captured when flashrom finishes in time.
+        let (stdout, _) = run("0").and_then(flashrom_result).unwrap();
+        assert_eq!(stdout, "out\n");
+        // Ordinary failures are not timeouts.
+        assert!(!flashrom_result(run("x").unwrap()).unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn flashrom_result_kinds() {
+        use super::flashrom_result;
+        use crate::transcript::Entry;
+        use crate::ErrorKind;
+
+        let failed = |status, stderr: &str| {
+            flashrom_result(Entry {
+                op: "test".into(),
+                args: Vec::new(),
+                status,
+                stdout: String::new(),
+                stderr: stderr.into(),
+            })
+            .unwrap_err()
+        };
+
+        let err = failed(Some(2), "Error: no chip\n");
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::NonZeroExit {
+                code: 2,
+                stderr: "Error: no chip\n".into()
+            }
+        );
+        // The message is unchanged for reports.
+        assert_eq!(
+            err.to_string(),
+            "Error: no chip\n\nExited with error code: 2"
+        );
+        assert_eq!(
+            failed(Some(1), "Error: Programmer initialization failed.\n").kind(),
+            &ErrorKind::ProgrammerInit
+        );
+        assert_eq!(failed(None, "").kind(), &ErrorKind::Other);
+    }
+
+    #[test]
+    fn classify_write_protect() {
+        use super::classify_failure;
+        use crate::ErrorKind;
+
+        // Internal programmer with status register protection in effect.
+        assert_eq!(
+            classify_failure(
+                1,
+                "Block protection could not be disabled!\n\
+                 Erasing and writing flash chip... FAILED!\n"
+            ),
+            ErrorKind::WriteProtected
+        );
+        assert_eq!(
+            classify_failure(
+                1,
+                "Erasing flash chip... Region 0x000000-0x7fffff is write protected.\n\
+                 FAILED!\n"
+            ),
+            ErrorKind::WriteProtected
+        );
+        // Kernel SPI drivers refuse the erase in an ioctl.
+        assert_eq!(
+            classify_failure(
+                1,
+                "linux_mtd_erase: ioctl: Operation not permitted\n\
+                 Erase/write failed\n"
+            ),
+            ErrorKind::WriteProtected
+        );
+        assert_eq!(
+            classify_failure(1, "/dev/mtd0 is read-only\n"),
+            ErrorKind::WriteProtected
+        );
+        // Other failures are not mistaken for write protection.
+        assert_eq!(
+            classify_failure(1, "Error: Programmer initialization failed.\n"),
+            ErrorKind::ProgrammerInit
+        );
+        // The internal programmer run without root.
+        assert_eq!(
+            classify_failure(
+                1,
+                "ERROR: Could not get I/O privileges (Operation not permitted).\n\
+                 You need to be root.\n\
+                 Error: Programmer initialization failed.\n"
+            ),
+            ErrorKind::ProgrammerInit
+        );
+        assert_eq!(
+            classify_failure(3, "No EEPROM/flash device found.\n"),
+            ErrorKind::NonZeroExit {
+                code: 3,
+                stderr: "No EEPROM/flash device found.\n".into()
+            }
+        );
+    }
+
+    #[test]
+    fn classify_size_mismatch() {
+        use super::classify_failure;
+        use crate::ErrorKind;
+
+        assert_eq!(
+            classify_failure(
+                1,
+                "Error: Image size (8392704 B) doesn't match the flash chip's size (8388608 B)!\n"
+            ),
+            ErrorKind::SizeMismatch
+        );
+        assert_eq!(
+            classify_failure(
+                1,
+                "Error: Image size (8392704 B) doesn't match the expected size (8388608 B)!\n"
+            ),
+            ErrorKind::SizeMismatch
+        );
+    }
+
+    #[test]
+    fn parse_dut_ctrl() {
+        use super::{parse_dut_ctrl, parse_on_off};
+
+        let pair = |c: &str, v: &str| (c.to_string(), v.to_string());
+        assert_eq!(parse_dut_ctrl("fw_wp:on\n"), Ok(vec![pair("fw_wp", "on")]));
+        assert_eq!(
+            parse_dut_ctrl("fw_wp_en:on\nfw_wp_state:force_off\n\n"),
+            Ok(vec![
+                pair("fw_wp_en", "on"),
+                pair("fw_wp_state", "force_off")
+            ])
+        );
+        // Only the first colon separates the control from its value.
+        assert_eq!(
+            parse_dut_ctrl("serialname:C1903:4\n"),
+            Ok(vec![pair("serialname", "C1903:4")])
+        );
+        assert_eq!(parse_dut_ctrl(""), Ok(vec![]));
+        assert!(parse_dut_ctrl("No control named fw_wp\n").is_err());
+
+        assert_eq!(parse_on_off("fw_wp", "on"), Ok(true));
+        assert_eq!(parse_on_off("fw_wp", "off"), Ok(false));
+        assert!(parse_on_off("fw_wp", "maybe").is_err());
     }
 
     #[test]
     fn flashrom_extract_size() {
         use super::flashrom_extract_size;
+        use crate::FlashromError;
 
         assert_eq!(
             flashrom_extract_size(
@@ -590,12 +2305,102 @@ mod tests {
             Ok(8388608)
         );
 
+        assert_eq!(
+            flashrom_extract_size(
+                "Found Winbond flash chip \"W25Q128.V\" (16384 kB, SPI) on ch341a_spi.\n\
+                 16777216\n"
+            ),
+            Ok(16777216)
+        );
+
         assert_eq!(
             flashrom_extract_size("There was a catastrophic error."),
-            Err("Found no purely-numeric lines in flashrom output".into())
+            Err(FlashromError::parse(
+                "Found no purely-numeric lines in flashrom output"
+            ))
         );
     }
 
+    #[test]
+    fn filter_lines() {
+        use super::{filter_lines, flashrom_extract_size};
+        use regex::Regex;
+
+        // A programmer that reports a status code after the size
+        let stdout = "Opened servo-v2 adapter\n\
+                      8388608\n\
+                      0\n";
+        assert_eq!(filter_lines(stdout, None), stdout);
+        assert_eq!(flashrom_extract_size(stdout), Ok(0));
+
+        let filter = Regex::new("^0$").unwrap();
+        assert_eq!(
+            filter_lines(stdout, Some(&filter)),
+            "Opened servo-v2 adapter\n8388608\n"
+        );
+        assert_eq!(
+            flashrom_extract_size(&filter_lines(stdout, Some(&filter))),
+            Ok(8388608)
+        );
+    }
+
+    #[test]
+    fn flashrom_log_path() {
+        use super::flashrom_log_path;
+
+        let first = flashrom_log_path("/logs", "read", 0);
+        let retry = flashrom_log_path("/logs", "read", 2);
+        assert!(first.starts_with("/logs/"), "{}", first);
+        assert!(first.ends_with("-read.log"), "{}", first);
+        assert!(retry.ends_with("-read.retry2.log"), "{}", retry);
+    }
+
+    #[test]
+    fn extract_voltage() {
+        use super::extract_voltage;
+
+        assert_eq!(
+            extract_voltage(
+                "Found Winbond flash chip \"W25Q64.V\" (8192 kB, SPI) on dediprog.\n\
+                 Setting SPI voltage to 3.500 V\n"
+            ),
+            Some("3.500 V")
+        );
+        assert_eq!(
+            extract_voltage("Chip voltage range: 1650-1950 mV\n"),
+            Some("1650-1950 mV")
+        );
+        assert_eq!(
+            extract_voltage("vendor=\"Winbond\" name=\"W25Q64DW\"\n"),
+            None
+        );
+        assert_eq!(extract_voltage("Voltage: unknown, 8192 kB\n"), None);
+    }
+
+    #[test]
+    fn extract_probed_chips() {
+        use super::extract_probed_chips;
+
+        assert_eq!(
+            extract_probed_chips(
+                "Found Winbond flash chip \"W25Q128.V\" (16384 kB, SPI) on ch341a_spi.\n\
+                 No operations were specified.\n"
+            ),
+            ["W25Q128.V"]
+        );
+        assert_eq!(
+            extract_probed_chips(
+                "Found Macronix flash chip \"MX25L6405\" (8192 kB, SPI) on dediprog.\n\
+                 Found Macronix flash chip \"MX25L6406E/MX25L6408E\" (8192 kB, SPI) on dediprog.\n\
+                 Multiple flash chip definitions match the detected chip(s): \
+                 \"MX25L6405\", \"MX25L6406E/MX25L6408E\"\n\
+                 Please specify which chip definition to use with the -c <chipname> option.\n"
+            ),
+            ["MX25L6405", "MX25L6406E/MX25L6408E"]
+        );
+        assert!(extract_probed_chips("No EEPROM/flash device found.\n").is_empty());
+    }
+
     #[test]
     fn extract_flash_name() {
         use super::extract_flash_name;
@@ -609,6 +2414,23 @@ mod tests {
             Some(("Winbond", "W25Q64DW"))
         );
 
+        assert_eq!(
+            extract_flash_name(
+                "Found GigaDevice flash chip \"GD25Q127C/GD25Q128E\" (16384 kB, SPI) on linux_spi.\n\
+                 vendor=\"GigaDevice\" name=\"GD25Q127C/GD25Q128E\"\n"
+            ),
+            Some(("GigaDevice", "GD25Q127C/GD25Q128E"))
+        );
+
+        // Hardware sequencing hides the chip behind an opaque definition.
+        assert_eq!(
+            extract_flash_name(
+                "Found Programmer flash chip \"Opaque flash chip\" (16384 kB, Programmer-specific) on internal.\r\n\
+                 vendor=\"Programmer\" name=\"Opaque flash chip\"\r\n"
+            ),
+            Some(("Programmer", "Opaque flash chip"))
+        );
+
         assert_eq!(
             extract_flash_name(
                 "vendor name is TEST\n\
@@ -34,16 +34,21 @@
 
 use libflashrom::{Chip, Programmer};
 
-use std::{cell::RefCell, convert::TryFrom, fs};
+use std::{
+    cell::{RefCell, RefMut},
+    convert::TryFrom,
+    fs,
+};
 
-use crate::{FlashChip, FlashromError, ROMWriteSpecifics};
+use crate::{ErrorKind, FlashChip, FlashromError, ROMWriteSpecifics, WpStatus};
 
 #[derive(Debug)]
 pub struct FlashromLib {
     // RefCell required here to keep Flashrom trait immutable.
     // Cant make Flashrom methods mut because WriteProtectState
     // and TestEnv both keep a reference.
-    pub flashrom: RefCell<Chip>,
+    // None only if re-initialising the programmer failed.
+    pub flashrom: RefCell<Option<Chip>>,
     pub fc: FlashChip,
 }
 
@@ -53,21 +58,36 @@ impl FlashromLib {
         let (programmer, options) = FlashChip::to_split(fc);
         let flashrom = Chip::new(Programmer::new(programmer, options).unwrap(), None).unwrap();
         FlashromLib {
-            flashrom: RefCell::new(flashrom),
+            flashrom: RefCell::new(Some(flashrom)),
             fc,
         }
     }
+
+    fn chip(&self) -> Result<RefMut<'_, Chip>, FlashromError> {
+        RefMut::filter_map(self.flashrom.borrow_mut(), Option::as_mut)
+            .map_err(|_| "The programmer could not be re-initialised".into())
+    }
 }
 
 impl crate::Flashrom for FlashromLib {
     fn get_size(&self) -> Result<i64, FlashromError> {
-        Ok(self.flashrom.borrow().get_size() as i64)
+        Ok(self.chip()?.get_size() as i64)
     }
 
     fn name(&self) -> Result<(String, String), FlashromError> {
         Ok(("not".to_string(), "implemented".to_string()))
     }
 
+    fn probe_chips(&self) -> Result<Vec<String>, FlashromError> {
+        // Creating the chip already failed if detection was ambiguous.
+        let (_, name) = self.name()?;
+        Ok(vec![name])
+    }
+
+    fn voltage(&self) -> Result<String, FlashromError> {
+        Err("Voltage is not reported through libflashrom".into())
+    }
+
     fn wp_range(&self, range: (i64, i64), wp_enable: bool) -> Result<bool, FlashromError> {
         let mut cfg = libflashrom::WriteProtectCfg::new()?;
         let start = usize::try_from(range.0).unwrap();
@@ -78,19 +98,37 @@ impl crate::Flashrom for FlashromLib {
         } else {
             libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_DISABLED
         });
-        self.flashrom.borrow_mut().set_wp(&cfg)?;
+        self.chip()?.set_wp(&cfg)?;
         Ok(true)
     }
 
+    fn wp_region(
+        &self,
+        layout_file: &str,
+        region: &str,
+        wp_enable: bool,
+    ) -> Result<bool, FlashromError> {
+        let buf = fs::read(layout_file).map_err(|error| error.to_string())?;
+        let buf = String::from_utf8(buf).unwrap();
+        let mut layout: libflashrom::Layout = buf
+            .parse()
+            .map_err(|e: Box<dyn std::error::Error>| e.to_string())?;
+        let range = layout.get_region_range(region)?;
+        self.wp_range((range.start as i64, range.len() as i64), wp_enable)
+    }
+
     fn wp_list(&self) -> Result<String, FlashromError> {
-        let ranges = self.flashrom.borrow_mut().get_wp_ranges()?;
-        Ok(format!("{:?}", ranges))
+        let ranges = self.chip()?.get_wp_ranges()?;
+        // Match the layout of `flashrom --wp-list` so callers can parse either.
+        Ok(ranges
+            .iter()
+            .map(|r| format!("start={:#010x} length={:#010x}\n", r.start, r.len()))
+            .collect())
     }
 
     fn wp_status(&self, en: bool) -> Result<bool, FlashromError> {
         let ret = self
-            .flashrom
-            .borrow_mut()
+            .chip()?
             .get_wp()
             .map_err(|e| format!("{:?}", e))?
             .get_mode();
@@ -101,6 +139,23 @@ impl crate::Flashrom for FlashromLib {
         }
     }
 
+    fn wp_get_state(&self) -> Result<WpStatus, FlashromError> {
+        let cfg = self.chip()?.get_wp().map_err(|e| format!("{:?}", e))?;
+        let range = cfg.get_range();
+        let mode = cfg.get_mode();
+        Ok(WpStatus {
+            enabled: mode != libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_DISABLED,
+            range: (range.start as i64, range.len() as i64),
+            mode: match mode {
+                libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_DISABLED => "disabled",
+                libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_HARDWARE => "hardware",
+                libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_POWER_CYCLE => "power_cycle",
+                libflashrom::flashrom_wp_mode::FLASHROM_WP_MODE_PERMANENT => "permanent",
+            }
+            .into(),
+        })
+    }
+
     fn wp_toggle(&self, en: bool) -> Result<bool, FlashromError> {
         // TODO why does the cmd impl not do this?
         // for cmd, range is only set for enable
@@ -109,23 +164,38 @@ impl crate::Flashrom for FlashromLib {
     }
 
     fn read_into_file(&self, path: &str) -> Result<(), FlashromError> {
-        let buf = self.flashrom.borrow_mut().image_read(None)?;
+        let buf = self.chip()?.image_read(None)?;
         fs::write(path, buf).map_err(|error| error.to_string())?;
         Ok(())
     }
 
+    fn read_to_vec(&self, _scratch: &str) -> Result<Vec<u8>, FlashromError> {
+        Ok(self.chip()?.image_read(None)?)
+    }
+
     fn read_region_into_file(&self, path: &str, region: &str) -> Result<(), FlashromError> {
-        let mut layout = self.flashrom.borrow_mut().layout_read_fmap_from_rom()?;
+        let mut layout = self.chip()?.layout_read_fmap_from_rom()?;
         layout.include_region(region)?;
         let range = layout.get_region_range(region)?;
-        let buf = self.flashrom.borrow_mut().image_read(None)?;
+        let buf = self.chip()?.image_read(None)?;
         fs::write(path, &buf[range]).map_err(|error| error.to_string())?;
         Ok(())
     }
 
     fn write_from_file(&self, path: &str) -> Result<(), FlashromError> {
         let mut buf = fs::read(path).map_err(|error| error.to_string())?;
-        self.flashrom.borrow_mut().image_write(&mut buf, None)?;
+        let size = self.get_size()?;
+        if buf.len() as i64 != size {
+            return Err(FlashromError::new(
+                ErrorKind::SizeMismatch,
+                format!(
+                    "Image size ({} B) doesn't match the flash chip's size ({} B)",
+                    buf.len(),
+                    size
+                ),
+            ));
+        }
+        self.chip()?.image_write(&mut buf, None)?;
         Ok(())
     }
 
@@ -137,20 +207,48 @@ impl crate::Flashrom for FlashromLib {
             .map_err(|e: Box<dyn std::error::Error>| e.to_string())?;
         layout.include_region(rws.name_file.unwrap())?;
         let mut buf = fs::read(rws.write_file.unwrap()).map_err(|error| error.to_string())?;
-        self.flashrom
-            .borrow_mut()
-            .image_write(&mut buf, Some(layout))?;
+        self.chip()?.image_write(&mut buf, Some(layout))?;
         Ok(true)
     }
 
     fn verify_from_file(&self, path: &str) -> Result<(), FlashromError> {
         let buf = fs::read(path).map_err(|error| error.to_string())?;
-        self.flashrom.borrow_mut().image_verify(&buf, None)?;
+        self.chip()?.image_verify(&buf, None)?;
+        Ok(())
+    }
+
+    fn verify_file_with_layout(
+        &self,
+        layout_file: &str,
+        region: &str,
+        path: &str,
+    ) -> Result<(), FlashromError> {
+        let buf = fs::read(layout_file).map_err(|error| error.to_string())?;
+        let buf = String::from_utf8(buf).unwrap();
+        let mut layout: libflashrom::Layout = buf
+            .parse()
+            .map_err(|e: Box<dyn std::error::Error>| e.to_string())?;
+        layout.include_region(region)?;
+        let buf = fs::read(path).map_err(|error| error.to_string())?;
+        self.chip()?.image_verify(&buf, Some(layout))?;
         Ok(())
     }
 
     fn erase(&self) -> Result<(), FlashromError> {
-        self.flashrom.borrow_mut().erase()?;
+        self.chip()?.erase()?;
+        Ok(())
+    }
+
+    fn reinit_programmer(&self) -> Result<(), FlashromError> {
+        let mut chip = self.flashrom.borrow_mut();
+        // Shut the programmer down before initialising it again.
+        *chip = None;
+        let (programmer, options) = FlashChip::to_split(self.fc);
+        let programmer = Programmer::new(programmer, options)
+            .map_err(|e| format!("Failed to re-initialise the programmer: {:?}", e))?;
+        let flashrom = Chip::new(programmer, None)
+            .map_err(|e| format!("Failed to probe the chip again: {:?}", e))?;
+        *chip = Some(flashrom);
         Ok(())
     }
 
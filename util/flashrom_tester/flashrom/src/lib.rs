@@ -37,13 +37,19 @@
 extern crate log;
 
 mod cmd;
+#[cfg(feature = "libflashrom")]
 mod flashromlib;
+pub mod transcript;
 
 use std::{error, fmt};
 
-pub use cmd::{dut_ctrl_toggle_wp, FlashromCmd};
+pub use cmd::{
+    dut_ctrl_get, dut_ctrl_get_wp, dut_ctrl_set, dut_ctrl_toggle_wp, FlashromCmd, WriteVerify,
+};
+#[cfg(feature = "libflashrom")]
 pub use flashromlib::FlashromLib;
 
+#[cfg(feature = "libflashrom")]
 pub use libflashrom::{
     flashrom_log_level, FLASHROM_MSG_DEBUG, FLASHROM_MSG_DEBUG2, FLASHROM_MSG_ERROR,
     FLASHROM_MSG_INFO, FLASHROM_MSG_SPEW, FLASHROM_MSG_WARN,
@@ -58,6 +64,16 @@ pub enum FlashChip {
 }
 
 impl FlashChip {
+    /// Every variant, in the same order as `CLI_NAMES`.
+    pub const ALL: [FlashChip; 4] = [
+        FlashChip::HOST,
+        FlashChip::EC,
+        FlashChip::SERVO,
+        FlashChip::DEDIPROG,
+    ];
+    /// The names accepted by `from`, for use as command line choices.
+    pub const CLI_NAMES: [&'static str; 4] = ["host", "ec", "servo", "dediprog"];
+
     pub fn from(s: &str) -> Result<FlashChip, &str> {
         let r = match s {
             "ec" => Ok(FlashChip::EC),
@@ -68,11 +84,23 @@ impl FlashChip {
         };
         return r;
     }
+
+    /// Return the name `from` accepts for `fc`.
+    pub fn to_cli(fc: FlashChip) -> &'static str {
+        match fc {
+            FlashChip::EC => "ec",
+            FlashChip::HOST => "host",
+            FlashChip::SERVO => "servo",
+            FlashChip::DEDIPROG => "dediprog",
+        }
+    }
+
+    /// Return the flashrom programmer string for `fc`.
     pub fn to(fc: FlashChip) -> &'static str {
         let r = match fc {
             FlashChip::EC => "ec",
-            FlashChip::HOST => "host",
-            FlashChip::SERVO => "ft2231_spi:type=servo-v2",
+            FlashChip::HOST => "internal",
+            FlashChip::SERVO => "ft2232_spi:type=servo-v2",
             FlashChip::DEDIPROG => "dediprog",
         };
         return r;
@@ -97,9 +125,63 @@ impl FlashChip {
     }
 }
 
+/// What kind of failure a `FlashromError` is, so callers can act on the cause
+/// without matching on message text.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ErrorKind {
+    /// Anything not covered below, including failures to run flashrom at all.
+    Other,
+    /// flashrom exited with a failure status, printing `stderr`.
+    NonZeroExit { code: i32, stderr: String },
+    /// flashrom was killed for taking too long.
+    Timeout,
+    /// flashrom's output couldn't be understood.
+    ParseFailure,
+    /// The programmer couldn't be initialized.
+    ProgrammerInit,
+    /// The operation was refused because the flash is write protected.
+    WriteProtected,
+    /// The image to write or verify isn't the same size as the flash.
+    SizeMismatch,
+    /// dut-control couldn't reach servod, which probably isn't running.
+    ServodUnavailable,
+    /// The backend doesn't support the operation.
+    Unsupported,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct FlashromError {
     msg: String,
+    kind: ErrorKind,
+}
+
+impl FlashromError {
+    pub fn new<T: Into<String>>(kind: ErrorKind, msg: T) -> Self {
+        FlashromError {
+            msg: msg.into(),
+            kind,
+        }
+    }
+
+    /// An error for a flashrom invocation that was killed for taking too long.
+    pub fn timeout<T: Into<String>>(msg: T) -> Self {
+        Self::new(ErrorKind::Timeout, msg)
+    }
+
+    /// An error for flashrom output that couldn't be understood.
+    pub fn parse<T: Into<String>>(msg: T) -> Self {
+        Self::new(ErrorKind::ParseFailure, msg)
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// True if flashrom was killed for taking too long, rather than failing by
+    /// itself; such operations may succeed if retried.
+    pub fn is_timeout(&self) -> bool {
+        self.kind == ErrorKind::Timeout
+    }
 }
 
 impl fmt::Display for FlashromError {
@@ -115,10 +197,20 @@ where
     T: Into<String>,
 {
     fn from(msg: T) -> Self {
-        FlashromError { msg: msg.into() }
+        Self::new(ErrorKind::Other, msg)
     }
 }
 
+/// Write protect configuration as reported by flashrom --wp-status.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WpStatus {
+    pub enabled: bool,
+    /// The protected (start, len), in the same form as `wp_range` takes.
+    pub range: (i64, i64),
+    /// Protection mode, such as "disabled" or "hardware".
+    pub mode: String,
+}
+
 pub struct ROMWriteSpecifics<'a> {
     pub layout_file: Option<&'a str>,
     pub write_file: Option<&'a str>,
@@ -129,39 +221,154 @@ pub trait Flashrom {
     /// Returns the size of the flash in bytes.
     fn get_size(&self) -> Result<i64, FlashromError>;
 
+    /// Returns the size of the flash in bytes, probing the chip even if
+    /// `get_size` would answer from a cache.
+    fn probe_size(&self) -> Result<i64, FlashromError> {
+        self.get_size()
+    }
+
     /// Returns the vendor name and the flash name.
     fn name(&self) -> Result<(String, String), FlashromError>;
 
+    /// Returns the names of every chip definition matching the detected chip,
+    /// of which there is more than one when detection is ambiguous.
+    fn probe_chips(&self) -> Result<Vec<String>, FlashromError>;
+
+    /// Returns the chip or programmer voltage, if flashrom reports one.
+    fn voltage(&self) -> Result<String, FlashromError>;
+
     /// Write only a region of the flash.
     fn write_file_with_layout(&self, rws: &ROMWriteSpecifics) -> Result<bool, FlashromError>;
 
+    /// Write only the `region` of the Intel Flash Descriptor from the same
+    /// region of the file specified by `path`.
+    fn write_ifd_region(&self, _path: &str, _region: &str) -> Result<(), FlashromError> {
+        Err(FlashromError::new(
+            ErrorKind::Unsupported,
+            "Intel Flash Descriptor regions are not supported",
+        ))
+    }
+
     /// Set write protect status for a range.
     fn wp_range(&self, range: (i64, i64), wp_enable: bool) -> Result<bool, FlashromError>;
 
+    /// Set write protect status for the `region` of `layout_file`.
+    fn wp_region(
+        &self,
+        layout_file: &str,
+        region: &str,
+        wp_enable: bool,
+    ) -> Result<bool, FlashromError>;
+
     /// Read the write protect regions for the flash.
     fn wp_list(&self) -> Result<String, FlashromError>;
 
     /// Return true if the flash write protect status matches `en`.
     fn wp_status(&self, en: bool) -> Result<bool, FlashromError>;
 
+    /// Return the write protect status including the protected range.
+    fn wp_get_state(&self) -> Result<WpStatus, FlashromError>;
+
     /// Set write protect status.
     fn wp_toggle(&self, en: bool) -> Result<bool, FlashromError>;
 
     /// Read the whole flash to the file specified by `path`.
     fn read_into_file(&self, path: &str) -> Result<(), FlashromError>;
 
+    /// Read the whole flash into memory. Backends that can only read into a
+    /// file use the one at `scratch`, removing it once it has been read.
+    fn read_to_vec(&self, scratch: &str) -> Result<Vec<u8>, FlashromError> {
+        self.read_into_file(scratch)?;
+        let data = std::fs::read(scratch)
+            .map_err(|e| format!("I/O error reading back {}: {:#}", scratch, e));
+        if let Err(e) = std::fs::remove_file(scratch) {
+            debug!("Failed to remove {}: {}", scratch, e);
+        }
+        Ok(data?)
+    }
+
     /// Read only a region of the flash.
     fn read_region_into_file(&self, path: &str, region: &str) -> Result<(), FlashromError>;
 
     /// Write the whole flash to the file specified by `path`.
     fn write_from_file(&self, path: &str) -> Result<(), FlashromError>;
 
+    /// Write the whole flash from `path` as `write_from_file` does, unless it
+    /// already holds that image. Returns whether anything was written.
+    ///
+    /// The flash is read back to compare, through a scratch file next to `path`
+    /// for backends that need one.
+    fn write_from_file_changed(&self, path: &str) -> Result<bool, FlashromError> {
+        let image =
+            std::fs::read(path).map_err(|e| format!("I/O error reading {}: {:#}", path, e))?;
+        let current = self.read_to_vec(&format!("{}.current", path))?;
+        if current == image {
+            return Ok(false);
+        }
+        self.write_from_file(path)?;
+        Ok(true)
+    }
+
     /// Verify the whole flash against the file specified by `path`.
     fn verify_from_file(&self, path: &str) -> Result<(), FlashromError>;
 
+    /// Write the whole flash from `path`, then verify it against the same file
+    /// separately from any verification the write itself does.
+    fn write_and_verify(&self, path: &str) -> Result<(), FlashromError> {
+        self.write_from_file(path)?;
+        self.verify_from_file(path)
+    }
+
+    /// Verify only the `region` of `layout_file` against the same region of the
+    /// file specified by `path`.
+    fn verify_file_with_layout(
+        &self,
+        layout_file: &str,
+        region: &str,
+        path: &str,
+    ) -> Result<(), FlashromError>;
+
     /// Erase the whole flash.
     fn erase(&self) -> Result<(), FlashromError>;
 
+    /// Shut the programmer down and initialise it again, so the next operation
+    /// sees it freshly initialised.
+    fn reinit_programmer(&self) -> Result<(), FlashromError>;
+
     /// Return true if the hardware write protect of this flash can be controlled.
     fn can_control_hw_wp(&self) -> bool;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FlashChip;
+
+    #[test]
+    fn backends_are_flashrom_objects() {
+        use super::{Flashrom, FlashromCmd};
+
+        // Fails to compile if Flashrom stops being object safe or a backend
+        // stops implementing it.
+        let _: fn(FlashromCmd) -> Box<dyn Flashrom> = |cmd| Box::new(cmd);
+        #[cfg(feature = "libflashrom")]
+        let _: fn(super::FlashromLib) -> Box<dyn Flashrom> = |lib| Box::new(lib);
+    }
+
+    #[test]
+    fn flash_chip_cli_round_trip() {
+        for (fc, name) in FlashChip::ALL.iter().zip(FlashChip::CLI_NAMES.iter()) {
+            assert_eq!(FlashChip::to_cli(*fc), *name);
+            assert_eq!(FlashChip::from(FlashChip::to_cli(*fc)), Ok(*fc));
+        }
+        assert!(FlashChip::from("servo-v2").is_err());
+    }
+
+    #[test]
+    fn flash_chip_programmer() {
+        assert_eq!(
+            FlashChip::to_split(FlashChip::SERVO),
+            ("ft2232_spi", Some("type=servo-v2"))
+        );
+        assert_eq!(FlashChip::to_split(FlashChip::DEDIPROG), ("dediprog", None));
+    }
+}
@@ -0,0 +1,188 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! Recording and replaying the output of flashrom invocations.
+//!
+//! A transcript file holds one JSON object per line for each invocation of
+//! flashrom, in the order they were made:
+//!
+//! ```text
+//! {"op":"get_size","args":["-p","internal","--flash-size"],"status":0,"stdout":"8388608\n","stderr":""}
+//! ```
+//!
+//! - `op` names the operation that ran flashrom, such as `get_size` or `wp_list`.
+//! - `args` are the arguments passed to flashrom.
+//! - `status` is its exit code, or null if it was killed by a signal.
+//! - `stdout` and `stderr` are its output. A stream that the operation did not
+//!   capture is recorded as empty.
+//!
+//! Replay matches entries to operations by `op` alone and in order, since the
+//! arguments include paths that differ between runs. Files that flashrom
+//! would have read or written are not part of the transcript. This means
+//! replay can only drive operations whose results come from flashrom's output.
+
+use crate::FlashromError;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+
+/// One invocation of flashrom.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub op: String,
+    pub args: Vec<String>,
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Entry {
+    fn to_json(&self) -> Value {
+        json!({
+            "op": self.op,
+            "args": self.args,
+            "status": self.status,
+            "stdout": self.stdout,
+            "stderr": self.stderr,
+        })
+    }
+
+    fn from_json(v: &Value) -> Option<Entry> {
+        let string = |key: &str| v.get(key)?.as_str().map(String::from);
+        Some(Entry {
+            op: string("op")?,
+            args: v
+                .get("args")?
+                .as_array()?
+                .iter()
+                .map(|a| a.as_str().map(String::from))
+                .collect::<Option<_>>()?,
+            status: match v.get("status")? {
+                Value::Null => None,
+                s => Some(s.as_i64()? as i32),
+            },
+            stdout: string("stdout")?,
+            stderr: string("stderr")?,
+        })
+    }
+}
+
+/// Where the output of flashrom invocations goes to or comes from.
+#[derive(Debug)]
+pub enum Transcript {
+    /// Run flashrom, appending each invocation to a file.
+    Record(RefCell<File>),
+    /// Answer invocations from previously recorded entries without running
+    /// flashrom.
+    Replay(RefCell<VecDeque<Entry>>),
+}
+
+impl Transcript {
+    /// Record to a new transcript file at `path`, replacing any existing one.
+    pub fn record(path: &str) -> Result<Transcript, FlashromError> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create transcript {}: {}", path, e))?;
+        Ok(Transcript::Record(RefCell::new(file)))
+    }
+
+    /// Replay the transcript file at `path`.
+    pub fn replay(path: &str) -> Result<Transcript, FlashromError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read transcript {}: {}", path, e))?;
+        Ok(Transcript::Replay(RefCell::new(parse(&text)?)))
+    }
+
+    /// Append an entry to a recording; does nothing when replaying.
+    pub(crate) fn append(&self, entry: &Entry) -> Result<(), FlashromError> {
+        if let Transcript::Record(file) = self {
+            writeln!(file.borrow_mut(), "{}", entry.to_json())
+                .map_err(|e| format!("Failed to write transcript: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Take the next replayed entry, which must be for `op`, or None if
+    /// recording.
+    pub(crate) fn next(&self, op: &str) -> Result<Option<Entry>, FlashromError> {
+        let entries = match self {
+            Transcript::Record(_) => return Ok(None),
+            Transcript::Replay(entries) => entries,
+        };
+        match entries.borrow_mut().pop_front() {
+            Some(entry) if entry.op == op => Ok(Some(entry)),
+            Some(entry) => Err(format!(
+                "Transcript replay expected {}() but the next entry is {}()",
+                op, entry.op
+            )
+            .into()),
+            None => Err(format!("Transcript replay has no entry left for {}()", op).into()),
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<VecDeque<Entry>, FlashromError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .ok()
+                .as_ref()
+                .and_then(Entry::from_json)
+                .ok_or_else(|| format!("Malformed transcript entry on line {}", i + 1).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Entry};
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = Entry {
+            op: "name".into(),
+            args: vec!["-p".into(), "host".into(), "--flash-name".into()],
+            status: None,
+            stdout: "vendor=\"W\" name=\"X\"\n".into(),
+            stderr: "".into(),
+        };
+        let line = entry.to_json().to_string();
+        assert_eq!(parse(&line).unwrap().pop_front(), Some(entry));
+        assert!(parse("{\"op\": \"name\"}").is_err());
+    }
+}
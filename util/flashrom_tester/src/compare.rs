@@ -0,0 +1,284 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! Comparison of a test report against one from a previous run, to catch
+//! regressions across firmware or flashrom versions.
+
+use super::tester::OutputFormat;
+use super::types;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// A duration change is only significant when the slower run took at least
+/// this many times as long as the faster one...
+const DURATION_RATIO: f64 = 1.5;
+/// ...and by at least this much, so jitter in quick tests is not reported.
+const MIN_DURATION_CHANGE: Duration = Duration::from_secs(1);
+
+/// How one test differs between two reports.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Passed previously but no longer passes.
+    Regressed,
+    /// Failed previously but now passes.
+    Fixed,
+    /// Same conclusion, but took significantly longer or shorter.
+    Duration { before: Duration, after: Duration },
+    /// Only present in the current report.
+    Added,
+    /// Only present in the previous report.
+    Removed,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Regressed => write!(f, "pass -> fail"),
+            Change::Fixed => write!(f, "fail -> pass"),
+            Change::Duration { before, after } => write!(
+                f,
+                "{:.1}s -> {:.1}s",
+                before.as_secs_f64(),
+                after.as_secs_f64()
+            ),
+            Change::Added => write!(f, "new test"),
+            Change::Removed => write!(f, "no longer run"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TestChange {
+    pub name: String,
+    pub change: Change,
+}
+
+struct Outcome {
+    pass: bool,
+    not_run: bool,
+    duration: Option<Duration>,
+}
+
+fn outcomes(report: &Value) -> Result<BTreeMap<&str, Outcome>, String> {
+    let tests = report
+        .get("tests")
+        .and_then(Value::as_object)
+        .ok_or("Report has no \"tests\" object")?;
+    tests
+        .iter()
+        .map(|(name, test)| {
+            let pass = test
+                .get("pass")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| format!("Test {} in report has no \"pass\" result", name))?;
+            let outcome = Outcome {
+                pass,
+                not_run: test.get("not_run").and_then(Value::as_bool) == Some(true)
+                    || test.get("skipped").and_then(Value::as_bool) == Some(true),
+                // Reports from before durations were recorded have none.
+                duration: test
+                    .get("duration_secs")
+                    .and_then(Value::as_f64)
+                    .filter(|d| d.is_finite() && *d >= 0.0)
+                    .map(Duration::from_secs_f64),
+            };
+            Ok((name.as_str(), outcome))
+        })
+        .collect()
+}
+
+fn significant(before: Duration, after: Duration) -> bool {
+    let (fast, slow) = if before < after {
+        (before, after)
+    } else {
+        (after, before)
+    };
+    slow - fast >= MIN_DURATION_CHANGE && slow.as_secs_f64() >= fast.as_secs_f64() * DURATION_RATIO
+}
+
+/// Compare two JSON reports as produced by `tester::json_report`, returning
+/// the tests that changed in name order.
+///
+/// Tests that were not run or were skipped in either report are only compared
+/// for presence.
+pub fn compare_reports(previous: &Value, current: &Value) -> Result<Vec<TestChange>, String> {
+    let previous = outcomes(previous)?;
+    let current = outcomes(current)?;
+
+    let mut names: Vec<&str> = previous.keys().chain(current.keys()).copied().collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut changes = Vec::new();
+    for name in names {
+        let change = match (previous.get(name), current.get(name)) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(before), Some(after)) => {
+                if before.not_run || after.not_run {
+                    continue;
+                }
+                match (before.pass, after.pass, before.duration, after.duration) {
+                    (true, false, _, _) => Change::Regressed,
+                    (false, true, _, _) => Change::Fixed,
+                    (_, _, Some(before), Some(after)) if significant(before, after) => {
+                        Change::Duration { before, after }
+                    }
+                    _ => continue,
+                }
+            }
+            (None, None) => unreachable!(),
+        };
+        changes.push(TestChange {
+            name: name.into(),
+            change,
+        });
+    }
+    Ok(changes)
+}
+
+pub fn print_changes(changes: &[TestChange], format: OutputFormat) {
+    match format {
+        OutputFormat::Pretty => {
+            println!("  =====  Changes since previous report  ====");
+            if changes.is_empty() {
+                println!("   none");
+            }
+            for c in changes {
+                let color = match c.change {
+                    Change::Regressed => types::RED,
+                    Change::Fixed => types::GREEN,
+                    _ => types::YELLOW,
+                };
+                println!(
+                    " {} {}",
+                    style!(format!(" <+> {} test:", c.name), types::BOLD),
+                    style!(c.change, color)
+                );
+            }
+            println!();
+        }
+        // stdout holds the report, which must stay a single JSON document.
+        OutputFormat::Json => eprintln!("{:#}", json_changes(changes)),
+        // Anything more on stdout would make the report invalid XML, and the
+        // terminal logger writes there too.
+        OutputFormat::Junit => {
+            for c in changes {
+                eprintln!("Since previous report, {}: {}", c.name, c.change);
+            }
+        }
+    }
+}
+
+/// Build the machine-readable form of a report comparison.
+pub fn json_changes(changes: &[TestChange]) -> Value {
+    let changes: Vec<Value> = changes
+        .iter()
+        .map(|c| {
+            let mut v = json!({
+                "name": c.name,
+                "change": match c.change {
+                    Change::Regressed => "regressed",
+                    Change::Fixed => "fixed",
+                    Change::Duration { .. } => "duration",
+                    Change::Added => "added",
+                    Change::Removed => "removed",
+                },
+            });
+            if let Change::Duration { before, after } = c.change {
+                v["duration_secs_before"] = json!(before.as_secs_f64());
+                v["duration_secs_after"] = json!(after.as_secs_f64());
+            }
+            v
+        })
+        .collect();
+    json!({ "changes": changes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_reports, Change, TestChange};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn compare_reports_finds_changes() {
+        let previous = json!({"tests": {
+            "A": {"pass": true, "duration_secs": 1.0},
+            "B": {"pass": false, "duration_secs": 1.0},
+            "C": {"pass": true, "duration_secs": 1.0},
+            "D": {"pass": true, "duration_secs": 0.1},
+            "E": {"pass": true},
+            "F": {"pass": false, "not_run": true},
+        }});
+        let current = json!({"tests": {
+            "A": {"pass": false, "duration_secs": 1.0},
+            "B": {"pass": true, "duration_secs": 1.0},
+            "C": {"pass": true, "duration_secs": 4.0},
+            "D": {"pass": true, "duration_secs": 0.9},
+            "F": {"pass": true},
+            "G": {"pass": true},
+        }});
+
+        let change = |name: &str, change| TestChange {
+            name: name.into(),
+            change,
+        };
+        assert_eq!(
+            compare_reports(&previous, &current),
+            Ok(vec![
+                change("A", Change::Regressed),
+                change("B", Change::Fixed),
+                change(
+                    "C",
+                    Change::Duration {
+                        before: Duration::from_secs(1),
+                        after: Duration::from_secs(4),
+                    }
+                ),
+                change("E", Change::Removed),
+                change("G", Change::Added),
+            ])
+        );
+    }
+
+    #[test]
+    fn compare_reports_rejects_malformed() {
+        assert!(compare_reports(&json!({}), &json!({"tests": {}})).is_err());
+        assert!(compare_reports(&json!({"tests": {"A": {}}}), &json!({"tests": {}})).is_err());
+    }
+}
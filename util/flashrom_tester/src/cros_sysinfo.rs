@@ -35,11 +35,30 @@
 
 use std::ffi::OsStr;
 use std::fmt::Debug;
-use std::io::Result as IoResult;
+use std::fs;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::path::Path;
 use std::process::{Command, Stdio};
 
 use super::utils;
 
+/// Where the kernel exposes the SMBIOS system and BIOS identifiers that
+/// dmidecode would otherwise report.
+const DMI_DIR: &str = "/sys/class/dmi/id";
+
+const DMI_SYSTEM_FIELDS: &[(&str, &str)] = &[
+    ("Manufacturer", "sys_vendor"),
+    ("Product Name", "product_name"),
+    ("Version", "product_version"),
+    ("Family", "product_family"),
+];
+
+const DMI_BIOS_FIELDS: &[(&str, &str)] = &[
+    ("Vendor", "bios_vendor"),
+    ("Version", "bios_version"),
+    ("Release Date", "bios_date"),
+];
+
 fn dmidecode_dispatch<S: AsRef<OsStr>>(args: &[S]) -> IoResult<String> {
     let output = Command::new("/usr/sbin/dmidecode")
         .args(args)
@@ -52,10 +71,109 @@ fn dmidecode_dispatch<S: AsRef<OsStr>>(args: &[S]) -> IoResult<String> {
     Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
+/// Describe the DMI `fields` found in `dir` in the style of dmidecode's output,
+/// with `title` and the directory as the heading. Fields that are missing are
+/// left out, but it is an error if none are present.
+fn dmi_info(dir: &Path, title: &str, fields: &[(&str, &str)]) -> IoResult<String> {
+    let mut out = format!("{} (from {})\n", title, dir.display());
+    let mut found = false;
+    for (label, file) in fields {
+        if let Ok(value) = fs::read_to_string(dir.join(file)) {
+            out.push_str(&format!("\t{}: {}\n", label, value.trim()));
+            found = true;
+        }
+    }
+    if !found {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no {} in {}", title, dir.display()),
+        ));
+    }
+    Ok(out)
+}
+
+/// Describe the firmware from crossystem, for boards without DMI tables.
+fn crossystem_bios_info() -> IoResult<String> {
+    let get = |key| utils::collect_crosssystem(&[key]).map_err(Error::other);
+    Ok(format!(
+        "BIOS Information (from crossystem)\n\tVersion: {}\n\tRO Version: {}\n",
+        get("fwid")?,
+        get("ro_fwid")?
+    ))
+}
+
+/// Return the first of `sources` to succeed, logging why the others didn't.
+fn first_available(
+    what: &str,
+    sources: &[(&str, &dyn Fn() -> IoResult<String>)],
+) -> IoResult<String> {
+    let mut last_err = Error::new(ErrorKind::NotFound, format!("no source of {}", what));
+    for (name, source) in sources {
+        match source() {
+            Ok(info) => {
+                info!("Collected {} from {}", what, name);
+                return Ok(info);
+            }
+            Err(e) => {
+                info!("Unable to collect {} from {}: {}", what, name, e);
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// Describe the system, from dmidecode or failing that the kernel's DMI tables.
 pub fn system_info() -> IoResult<String> {
-    dmidecode_dispatch(&["-q", "-t1"])
+    first_available(
+        "system info",
+        &[
+            ("dmidecode", &|| dmidecode_dispatch(&["-q", "-t1"])),
+            (DMI_DIR, &|| {
+                dmi_info(Path::new(DMI_DIR), "System Information", DMI_SYSTEM_FIELDS)
+            }),
+        ],
+    )
 }
 
+/// Describe the firmware, from dmidecode, the kernel's DMI tables or crossystem,
+/// whichever is available first.
 pub fn bios_info() -> IoResult<String> {
-    dmidecode_dispatch(&["-q", "-t0"])
+    first_available(
+        "bios info",
+        &[
+            ("dmidecode", &|| dmidecode_dispatch(&["-q", "-t0"])),
+            (DMI_DIR, &|| {
+                dmi_info(Path::new(DMI_DIR), "BIOS Information", DMI_BIOS_FIELDS)
+            }),
+            ("crossystem", &crossystem_bios_info),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn dmi_info() {
+        use super::{dmi_info, DMI_BIOS_FIELDS};
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(dmi_info(dir.path(), "BIOS Information", DMI_BIOS_FIELDS).is_err());
+
+        std::fs::write(dir.path().join("bios_vendor"), "coreboot\n").unwrap();
+        std::fs::write(
+            dir.path().join("bios_version"),
+            "Google_Volteer.13672.0.0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            dmi_info(dir.path(), "BIOS Information", DMI_BIOS_FIELDS).unwrap(),
+            format!(
+                "BIOS Information (from {})\n\
+                 \tVendor: coreboot\n\
+                 \tVersion: Google_Volteer.13672.0.0\n",
+                dir.path().display()
+            )
+        );
+    }
 }
@@ -0,0 +1,279 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+use digest::consts::U4;
+use digest::{DynDigest, FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser};
+use digest::{Reset, Update};
+use std::fmt;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+/// A digest algorithm for summarizing flash contents in reports.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum DigestAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Crc32,
+    Md5,
+}
+
+impl DigestAlgorithm {
+    /// The name of the algorithm, as accepted by `from_str`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Sha1 => "sha1",
+            DigestAlgorithm::Crc32 => "crc32",
+            DigestAlgorithm::Md5 => "md5",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            DigestAlgorithm::Sha256 => Box::new(sha2::Sha256::default()),
+            DigestAlgorithm::Sha1 => Box::new(sha1::Sha1::default()),
+            DigestAlgorithm::Crc32 => Box::new(Crc32::default()),
+            DigestAlgorithm::Md5 => Box::new(md5::Md5::default()),
+        }
+    }
+
+    /// Compute the digest of `data`.
+    pub fn digest_bytes(self, data: &[u8]) -> Digest {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        self.finish(hasher)
+    }
+
+    /// Compute the digest of everything produced by `reader`.
+    pub fn digest_reader<R: Read>(self, mut reader: R) -> io::Result<Digest> {
+        let mut hasher = self.hasher();
+        let mut buf = vec![0u8; 64 << 10];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.finish(hasher))
+    }
+
+    /// Compute the digest of the file at `path`.
+    pub fn digest_file<P: AsRef<Path>>(self, path: P) -> io::Result<Digest> {
+        self.digest_reader(std::fs::File::open(path)?)
+    }
+
+    fn finish(self, hasher: Box<dyn DynDigest>) -> Digest {
+        Digest {
+            algorithm: self,
+            value: hasher.finalize().to_vec(),
+        }
+    }
+}
+
+impl std::str::FromStr for DigestAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use DigestAlgorithm::*;
+
+        [Sha256, Sha1, Crc32, Md5]
+            .iter()
+            .copied()
+            .find(|alg| s.eq_ignore_ascii_case(alg.name()))
+            .ok_or_else(|| format!("unknown digest algorithm {:?}", s))
+    }
+}
+
+/// A computed digest, which remembers the algorithm that produced it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Digest {
+    pub algorithm: DigestAlgorithm,
+    pub value: Vec<u8>,
+}
+
+impl Digest {
+    /// The digest value as lowercase hex.
+    pub fn hex(&self) -> String {
+        self.value.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Formats as `algorithm:hex`, so the algorithm is never separated from the
+/// value it describes.
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.hex())
+    }
+}
+
+/// Parses the `algorithm:hex` form that `Display` produces. A bare hex value is
+/// taken to be the default algorithm's.
+impl std::str::FromStr for Digest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = match s.split_once(':') {
+            Some((name, hex)) => (name.parse()?, hex),
+            None => (DigestAlgorithm::default(), s),
+        };
+        let expected_len = algorithm.hasher().output_size() * 2;
+        if hex.len() != expected_len {
+            return Err(format!(
+                "{} digest must be {} hex digits, not {}",
+                algorithm.name(),
+                expected_len,
+                hex.len()
+            ));
+        }
+        let value = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    .ok_or_else(|| format!("invalid hex digest {:?}", hex))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Digest { algorithm, value })
+    }
+}
+
+/// CRC-32 (IEEE) adapted to the `digest` traits, producing the checksum in
+/// big-endian order as conventionally printed.
+#[derive(Clone, Default)]
+struct Crc32(crc32fast::Hasher);
+
+impl HashMarker for Crc32 {}
+
+impl OutputSizeUser for Crc32 {
+    type OutputSize = U4;
+}
+
+impl Update for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Crc32 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.finalize().to_be_bytes());
+    }
+}
+
+impl Reset for Crc32 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl FixedOutputReset for Crc32 {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.clone().finalize().to_be_bytes());
+        self.0.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Digest, DigestAlgorithm};
+
+    #[test]
+    fn known_digests() {
+        let cases = [
+            (
+                DigestAlgorithm::Sha256,
+                "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                DigestAlgorithm::Sha1,
+                "sha1:a9993e364706816aba3e25717850c26c9cd0d89d",
+            ),
+            (DigestAlgorithm::Crc32, "crc32:352441c2"),
+            (DigestAlgorithm::Md5, "md5:900150983cd24fb0d6963f7d28e17f72"),
+        ];
+        for (alg, expected) in cases.iter() {
+            assert_eq!(alg.digest_bytes(b"abc").to_string(), *expected);
+            assert_eq!(
+                alg.digest_reader(&b"abc"[..]).unwrap().to_string(),
+                *expected
+            );
+        }
+    }
+
+    #[test]
+    fn standard_vectors() {
+        assert_eq!(
+            DigestAlgorithm::Sha256.digest_bytes(b"").hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            DigestAlgorithm::Crc32.digest_bytes(b"123456789").hex(),
+            "cbf43926"
+        );
+    }
+
+    #[test]
+    fn digest_file() {
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut f, &[0xffu8; 4096]).unwrap();
+        assert_eq!(
+            DigestAlgorithm::Sha256.digest_file(f.path()).unwrap(),
+            DigestAlgorithm::Sha256.digest_bytes(&[0xffu8; 4096])
+        );
+    }
+
+    #[test]
+    fn parse_digest() {
+        let digest = DigestAlgorithm::Crc32.digest_bytes(b"abc");
+        assert_eq!(digest.to_string().parse(), Ok(digest));
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015AD".parse(),
+            Ok(DigestAlgorithm::Sha256.digest_bytes(b"abc"))
+        );
+        assert!("crc32:352441".parse::<Digest>().is_err());
+        assert!("crc32:352441zz".parse::<Digest>().is_err());
+        assert!("sha512:00".parse::<Digest>().is_err());
+    }
+
+    #[test]
+    fn parse_algorithm() {
+        assert_eq!("SHA256".parse(), Ok(DigestAlgorithm::Sha256));
+        assert_eq!("crc32".parse(), Ok(DigestAlgorithm::Crc32));
+        assert!("sha512".parse::<DigestAlgorithm>().is_err());
+    }
+}
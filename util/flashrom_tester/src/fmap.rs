@@ -0,0 +1,197 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! Minimal parsing of the flashmap (FMAP) that describes a firmware image's
+//! regions, as defined by flashmap's fmap.h.
+
+use flashrom::{Flashrom, FlashromError};
+use std::convert::TryInto;
+
+const SIGNATURE: &[u8] = b"__FMAP__";
+/// Size of the FMAP header, up to the start of the areas.
+const HEADER_LEN: usize = 56;
+/// Size of each area description following the header.
+const AREA_LEN: usize = 42;
+const NAME_LEN: usize = 32;
+
+/// A named region of a firmware image.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FmapArea {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Find and parse the FMAP in `image`, returning its areas or None if the
+/// image contains no valid FMAP.
+pub fn read_fmap(image: &[u8]) -> Option<Vec<FmapArea>> {
+    let mut start = 0;
+    while let Some(pos) = find(&image[start..], SIGNATURE) {
+        let at = start + pos;
+        if let Some(areas) = parse_at(&image[at..]) {
+            return Some(areas);
+        }
+        start = at + 1;
+    }
+    None
+}
+
+/// Read the FMAP from the chip through flashrom, dumping its FMAP region to
+/// the file at `scratch`, and return its areas.
+pub fn fmap_regions(cmd: &dyn Flashrom, scratch: &str) -> Result<Vec<FmapArea>, FlashromError> {
+    cmd.read_region_into_file(scratch, "FMAP")?;
+    let dump =
+        std::fs::read(scratch).map_err(|e| format!("I/O error reading FMAP dump: {:#}", e))?;
+    read_fmap(&dump).ok_or_else(|| "The chip's FMAP region doesn't hold a valid FMAP".into())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn parse_at(fmap: &[u8]) -> Option<Vec<FmapArea>> {
+    let header = fmap.get(..HEADER_LEN)?;
+    // Only major version 1 is defined.
+    if header[8] != 1 {
+        return None;
+    }
+    let nareas = u16::from_le_bytes(header[54..56].try_into().unwrap()) as usize;
+
+    let areas = fmap.get(HEADER_LEN..HEADER_LEN + nareas * AREA_LEN)?;
+    Some(
+        areas
+            .chunks(AREA_LEN)
+            .map(|area| FmapArea {
+                offset: u32::from_le_bytes(area[0..4].try_into().unwrap()) as usize,
+                size: u32::from_le_bytes(area[4..8].try_into().unwrap()) as usize,
+                name: name(&area[8..8 + NAME_LEN]),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_fmap, FmapArea, NAME_LEN};
+
+    fn name(s: &str) -> Vec<u8> {
+        let mut v = s.as_bytes().to_vec();
+        v.resize(NAME_LEN, 0);
+        v
+    }
+
+    fn fmap(areas: &[(&str, u32, u32)]) -> Vec<u8> {
+        let mut v = b"__FMAP__".to_vec();
+        v.extend_from_slice(&[1, 1]);
+        v.extend_from_slice(&0u64.to_le_bytes());
+        v.extend_from_slice(&0x1000u32.to_le_bytes());
+        v.extend(name("FMAP"));
+        v.extend_from_slice(&(areas.len() as u16).to_le_bytes());
+        for &(n, offset, size) in areas {
+            v.extend_from_slice(&offset.to_le_bytes());
+            v.extend_from_slice(&size.to_le_bytes());
+            v.extend(name(n));
+            v.extend_from_slice(&0u16.to_le_bytes());
+        }
+        v
+    }
+
+    #[test]
+    fn read_fmap_in_image() {
+        // A stray signature with a bad version is skipped.
+        let mut image = b"junk__FMAP__\x09".to_vec();
+        image.extend(fmap(&[
+            ("RO_SECTION", 0, 0x800),
+            ("RW_SECTION_A", 0x800, 0x800),
+        ]));
+        image.extend_from_slice(&[0xff; 16]);
+
+        assert_eq!(
+            read_fmap(&image),
+            Some(vec![
+                FmapArea {
+                    name: "RO_SECTION".into(),
+                    offset: 0,
+                    size: 0x800
+                },
+                FmapArea {
+                    name: "RW_SECTION_A".into(),
+                    offset: 0x800,
+                    size: 0x800
+                },
+            ])
+        );
+    }
+
+    /// A dump of just the FMAP region, as flashrom writes it, starts with the
+    /// signature and is padded to the region's size.
+    #[test]
+    fn read_fmap_dump() {
+        let mut dump = fmap(&[
+            ("RO_SECTION", 0, 0x400000),
+            ("FMAP", 0x1000, 0x800),
+            ("RW_VPD", 0x400000, 0x2000),
+        ]);
+        dump.resize(0x800, 0xff);
+
+        let areas = read_fmap(&dump).unwrap();
+        let tuples: Vec<_> = areas
+            .iter()
+            .map(|a| (a.name.as_str(), a.offset, a.size))
+            .collect();
+        assert_eq!(
+            tuples,
+            [
+                ("RO_SECTION", 0, 0x400000),
+                ("FMAP", 0x1000, 0x800),
+                ("RW_VPD", 0x400000, 0x2000)
+            ]
+        );
+    }
+
+    #[test]
+    fn read_fmap_missing_or_truncated() {
+        assert_eq!(read_fmap(&[0xff; 256]), None);
+
+        let mut truncated = fmap(&[("RO_SECTION", 0, 0x800)]);
+        truncated.truncate(truncated.len() - 1);
+        assert_eq!(read_fmap(&truncated), None);
+    }
+}
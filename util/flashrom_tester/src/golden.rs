@@ -0,0 +1,141 @@
+//
+// Copyright 2019, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+/// Contents of the golden image, memory-mapped where possible.
+///
+/// Mapping the file avoids holding a copy of a potentially large image on the
+/// heap for the whole run. Where the file can't be mapped the contents are
+/// read into memory instead.
+pub enum GoldenImage {
+    Mapped(Mmap),
+    Buffered(Vec<u8>),
+}
+
+impl GoldenImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<GoldenImage> {
+        let file = File::open(&path)?;
+        // Safety: the golden image is private to this process and never modified
+        // while mapped.
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(GoldenImage::Mapped(map)),
+            Err(e) => {
+                warn!("Unable to map golden image ({}), reading it instead", e);
+                std::fs::read(path).map(GoldenImage::Buffered)
+            }
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            GoldenImage::Mapped(map) => map,
+            GoldenImage::Buffered(buf) => buf,
+        }
+    }
+
+    /// Compare the golden image to the bytes produced by `other`, returning the
+    /// offset of the first difference or None if they are identical.
+    ///
+    /// If one is a prefix of the other, the difference is at the end of the
+    /// shorter one.
+    pub fn first_difference<R: Read>(&self, mut other: R) -> io::Result<Option<usize>> {
+        let expected = self.as_bytes();
+        let mut buf = vec![0u8; 64 << 10];
+        let mut offset = 0;
+
+        loop {
+            let n = match other.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let remaining = &expected[offset..];
+            let common = n.min(remaining.len());
+            if let Some(i) = (0..common).find(|&i| buf[i] != remaining[i]) {
+                return Ok(Some(offset + i));
+            }
+            if n > remaining.len() {
+                return Ok(Some(expected.len()));
+            }
+            offset += n;
+        }
+
+        if offset == expected.len() {
+            Ok(None)
+        } else {
+            Ok(Some(offset))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GoldenImage;
+
+    #[test]
+    fn first_difference() {
+        let golden = GoldenImage::Buffered(vec![1, 2, 3, 4]);
+
+        assert_eq!(golden.first_difference(&[1, 2, 3, 4][..]).unwrap(), None);
+        assert_eq!(golden.first_difference(&[1, 2, 0, 4][..]).unwrap(), Some(2));
+        assert_eq!(golden.first_difference(&[1, 2][..]).unwrap(), Some(2));
+        assert_eq!(
+            golden.first_difference(&[1, 2, 3, 4, 5][..]).unwrap(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn open_mapped() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path();
+        std::fs::write(path, [0xAA; 4096]).unwrap();
+
+        let golden = GoldenImage::open(path).unwrap();
+        assert!(matches!(golden, GoldenImage::Mapped(_)));
+        assert_eq!(golden.as_bytes(), &[0xAA; 4096][..]);
+        assert_eq!(
+            golden
+                .first_difference(std::fs::File::open(path).unwrap())
+                .unwrap(),
+            None
+        );
+    }
+}
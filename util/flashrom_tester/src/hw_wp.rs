@@ -0,0 +1,229 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! Ways of changing the hardware write protect signal of the flash under test,
+//! which depend on how the tester is connected to it.
+
+use super::utils::{self, ManualWp};
+use flashrom::{FlashChip, Flashrom};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Asserts and deasserts hardware write protect.
+pub trait HwWpController {
+    /// Return true if this can change hardware write protect. If not, it is
+    /// assumed to always be deasserted.
+    fn can_control(&self) -> bool;
+
+    /// Return whether hardware write protect is asserted.
+    fn get(&self) -> Result<bool, String>;
+
+    /// Assert or deassert hardware write protect.
+    fn set(&self, enable: bool) -> Result<(), String>;
+
+    /// Whether the operator may be asked to make changes, for controllers that
+    /// rely on them.
+    fn manual_policy(&self) -> Option<ManualWp> {
+        None
+    }
+}
+
+/// Hardware write protect driven by servo's fw_wp control through dut-control.
+pub struct Servo;
+
+impl HwWpController for Servo {
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    fn get(&self) -> Result<bool, String> {
+        flashrom::dut_ctrl_get_wp().map_err(|e| e.to_string())
+    }
+
+    fn set(&self, enable: bool) -> Result<(), String> {
+        flashrom::dut_ctrl_toggle_wp(enable).map_err(|e| e.to_string())
+    }
+}
+
+/// Hardware write protect changed by the operator when prompted.
+///
+/// When the tests run on the DUT itself, crossystem confirms that each change
+/// was made. Otherwise the operator is trusted, and write protect is assumed
+/// to start deasserted.
+pub struct Manual {
+    policy: ManualWp,
+    confirm: bool,
+    assumed: Cell<bool>,
+}
+
+impl Manual {
+    pub fn new(policy: ManualWp, confirm: bool) -> Self {
+        Manual {
+            policy,
+            confirm,
+            assumed: Cell::new(false),
+        }
+    }
+}
+
+impl HwWpController for Manual {
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    fn get(&self) -> Result<bool, String> {
+        if self.confirm {
+            utils::get_hardware_wp()
+        } else {
+            Ok(self.assumed.get())
+        }
+    }
+
+    fn set(&self, enable: bool) -> Result<(), String> {
+        if self.confirm {
+            return utils::toggle_hw_wp(/* dis= */ !enable, self.policy);
+        }
+        if self.assumed.get() != enable {
+            utils::prompt_hw_wp(enable, self.policy)?;
+            self.assumed.set(enable);
+        }
+        Ok(())
+    }
+
+    fn manual_policy(&self) -> Option<ManualWp> {
+        Some(self.policy)
+    }
+}
+
+/// No way to change hardware write protect, which is assumed deasserted.
+pub struct Unsupported;
+
+impl HwWpController for Unsupported {
+    fn can_control(&self) -> bool {
+        false
+    }
+
+    fn get(&self) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    fn set(&self, _enable: bool) -> Result<(), String> {
+        Err("Hardware write protect can't be controlled".into())
+    }
+}
+
+/// Which `HwWpController` to use.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HwWpMode {
+    Servo,
+    Manual,
+    None,
+}
+
+impl std::str::FromStr for HwWpMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("servo") {
+            Ok(HwWpMode::Servo)
+        } else if s.eq_ignore_ascii_case("manual") {
+            Ok(HwWpMode::Manual)
+        } else if s.eq_ignore_ascii_case("none") {
+            Ok(HwWpMode::None)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl HwWpMode {
+    /// The mode for `cmd` when none is chosen: prompting the operator if the
+    /// programmer can control hardware write protect, which is only the case
+    /// for chips on the DUT running the tests.
+    pub fn default_for(cmd: &dyn Flashrom) -> Self {
+        if cmd.can_control_hw_wp() {
+            HwWpMode::Manual
+        } else {
+            HwWpMode::None
+        }
+    }
+
+    pub fn controller(self, fc: FlashChip, policy: ManualWp) -> Rc<dyn HwWpController> {
+        match self {
+            HwWpMode::Servo => Rc::new(Servo),
+            HwWpMode::Manual => Rc::new(Manual::new(
+                policy,
+                /* confirm= */ matches!(fc, FlashChip::HOST | FlashChip::EC),
+            )),
+            HwWpMode::None => Rc::new(Unsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_mode() {
+        use super::HwWpMode;
+
+        assert_eq!("servo".parse(), Ok(HwWpMode::Servo));
+        assert_eq!("Manual".parse(), Ok(HwWpMode::Manual));
+        assert_eq!("none".parse(), Ok(HwWpMode::None));
+        assert_eq!("battery".parse::<HwWpMode>(), Err(()));
+    }
+
+    #[test]
+    fn unconfirmed_manual_refused() {
+        use super::{HwWpController, Manual};
+        use crate::utils::ManualWp;
+
+        let manual = Manual::new(ManualWp::Refuse, false);
+        assert_eq!(manual.get(), Ok(false));
+        // Already deasserted, so the operator isn't needed.
+        assert_eq!(manual.set(false), Ok(()));
+        assert!(manual.set(true).is_err());
+        assert_eq!(manual.get(), Ok(false));
+        assert_eq!(manual.manual_policy(), Some(ManualWp::Refuse));
+    }
+
+    #[test]
+    fn unsupported() {
+        use super::{HwWpController, Unsupported};
+
+        assert!(!Unsupported.can_control());
+        assert_eq!(Unsupported.get(), Ok(false));
+        assert!(Unsupported.set(true).is_err());
+    }
+}
@@ -39,7 +39,12 @@ extern crate log;
 #[macro_use]
 pub mod types;
 
+pub mod compare;
 pub mod cros_sysinfo;
+pub mod digests;
+pub mod fmap;
+pub mod golden;
+pub mod hw_wp;
 pub mod rand_util;
 pub mod tester;
 pub mod tests;
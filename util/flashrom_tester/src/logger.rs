@@ -40,6 +40,7 @@ use std::sync::Mutex;
 
 struct Logger<W: Write + Send> {
     level: log::LevelFilter,
+    color: bool,
     target: LogTarget<W>,
 }
 
@@ -57,16 +58,25 @@ impl<W: Write + Send> log::Log for Logger<W> {
     }
 
     fn log(&self, record: &log::Record) {
-        fn log_internal<W: Write>(mut w: W, record: &log::Record) -> std::io::Result<()> {
+        fn log_internal<W: Write>(
+            mut w: W,
+            record: &log::Record,
+            color: bool,
+        ) -> std::io::Result<()> {
             let now = chrono::Local::now();
-            write!(w, "{}{} ", types::MAGENTA, now.format("%Y-%m-%dT%H:%M:%S"))?;
-            write!(
-                w,
-                "{}[ {} ]{} ",
-                types::YELLOW,
-                record.level(),
-                types::RESET
-            )?;
+            let now = now.format("%Y-%m-%dT%H:%M:%S");
+            if color {
+                write!(w, "{}{} ", types::MAGENTA, now)?;
+                write!(
+                    w,
+                    "{}[ {} ]{} ",
+                    types::YELLOW,
+                    record.level(),
+                    types::RESET
+                )?;
+            } else {
+                write!(w, "{} [ {} ] ", now, record.level())?;
+            }
             writeln!(w, "{}", record.args())
         }
 
@@ -75,11 +85,11 @@ impl<W: Write + Send> log::Log for Logger<W> {
             LogTarget::Terminal => {
                 let stdout = std::io::stdout();
                 let mut lock = stdout.lock();
-                log_internal(&mut lock, record)
+                log_internal(&mut lock, record, self.color)
             }
             LogTarget::Write(ref mutex) => {
                 let mut lock = mutex.lock().unwrap();
-                log_internal(&mut *lock, record)
+                log_internal(&mut *lock, record, self.color)
             }
         };
     }
@@ -93,9 +103,10 @@ impl<W: Write + Send> log::Log for Logger<W> {
     }
 }
 
-pub fn init(to_file: Option<PathBuf>, debug: bool) {
+pub fn init(to_file: Option<PathBuf>, debug: bool, color: bool) {
     let mut logger = Logger {
         level: log::LevelFilter::Info,
+        color,
         target: LogTarget::Terminal,
     };
 
@@ -119,11 +130,16 @@ mod tests {
     use std::sync::Mutex;
 
     fn run_records(records: &[Record]) -> String {
+        run_records_color(records, true)
+    }
+
+    fn run_records_color(records: &[Record], color: bool) -> String {
         let mut buf = Vec::<u8>::new();
         {
             let lock = Mutex::new(&mut buf);
             let logger = Logger {
                 level: LevelFilter::Info,
+                color,
                 target: LogTarget::Write(lock),
             };
 
@@ -152,6 +168,21 @@ mod tests {
         );
     }
 
+    /// Without colors there are no escape sequences at all
+    #[test]
+    fn format_no_color() {
+        let buf = run_records_color(
+            &[Record::builder()
+                .args(format_args!("Test message at INFO"))
+                .level(Level::Info)
+                .build()],
+            false,
+        );
+
+        assert!(!buf.contains("\x1b["), "{:?}", buf);
+        assert_eq!(&buf[19..], " [ INFO ] Test message at INFO\n");
+    }
+
     #[test]
     fn level_filter() {
         let buf = run_records(&[
@@ -39,9 +39,10 @@ extern crate log;
 mod logger;
 
 use clap::{App, Arg};
-use flashrom::{FlashChip, Flashrom, FlashromCmd, FlashromLib};
-use flashrom_tester::{tester, tests};
-use std::path::PathBuf;
+use flashrom::transcript::Transcript;
+use flashrom::{FlashChip, Flashrom, FlashromCmd};
+use flashrom_tester::{tester, tests, types};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 
 pub mod built_info {
@@ -76,14 +77,295 @@ fn main() {
                 .long("flashrom_binary")
                 .short("b")
                 .takes_value(true)
-                .required_unless("libflashrom")
+                .required_unless_one(&["libflashrom", "list-tests"])
                 .conflicts_with("libflashrom")
                 .help("Path to flashrom binary to test"),
         )
+        .arg(
+            Arg::with_name("probe-filter")
+                .long("probe-filter")
+                .takes_value(true)
+                .value_name("REGEX")
+                .conflicts_with("libflashrom")
+                .help("Ignore lines of flashrom's chip size and name output matching REGEX"),
+        )
+        .arg(
+            Arg::with_name("programmer")
+                .long("programmer")
+                .takes_value(true)
+                .value_name("PROGRAMMER")
+                .conflicts_with("libflashrom")
+                .help(
+                    "Pass PROGRAMMER to flashrom -p verbatim; ccd_target_type still decides \
+                     which tests apply and how write protect is controlled",
+                ),
+        )
+        .arg(
+            Arg::with_name("laptop-force")
+                .long("laptop-force")
+                .conflicts_with("libflashrom")
+                .help(
+                    "Pass laptop=force to the internal programmer, which otherwise \
+                     refuses to run on some laptops (host only)",
+                ),
+        )
+        .arg(
+            Arg::with_name("programmer-param")
+                .long("programmer-param")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KEY=VALUE")
+                .conflicts_with("libflashrom")
+                .validator(|s| {
+                    if s.contains('=') && !s.contains(',') {
+                        Ok(())
+                    } else {
+                        Err(format!("{:?} is not a single key=value parameter", s))
+                    }
+                })
+                .help("Extra programmer parameter, such as spispeed=12M (repeatable)"),
+        )
+        .arg(
+            Arg::with_name("write-verify")
+                .long("write-verify")
+                .takes_value(true)
+                .possible_values(&["all", "included", "off"])
+                .default_value("all")
+                .conflicts_with("libflashrom")
+                .help(
+                    "How much of the flash flashrom verifies after writing: all of it, \
+                     only the included regions, or none",
+                ),
+        )
+        .arg(
+            Arg::with_name("flashrom-verbose")
+                .short("v")
+                .long("flashrom-verbose")
+                .multiple(true)
+                .conflicts_with("libflashrom")
+                .help("Pass -V to flashrom, once per use, logging its output with --debug"),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .conflicts_with("libflashrom")
+                .help("Log flashrom's progress through reads, writes and erases"),
+        )
+        .arg(
+            Arg::with_name("verify-after-write")
+                .long("verify-after-write")
+                .help("Verify whole-image writes with a separate flashrom run after writing"),
+        )
+        .arg(
+            Arg::with_name("chip-size")
+                .long("chip-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(|s| flashrom_tester::utils::parse_chip_size(&s).map(|_| ()))
+                .help("Use this chip size instead of the one the programmer reports"),
+        )
+        .arg(
+            Arg::with_name("diff-against")
+                .long("diff-against")
+                .takes_value(true)
+                .value_name("IMAGE")
+                .help(
+                    "Instead of running tests, read the chip and report the \
+                     regions that differ from IMAGE",
+                ),
+        )
+        .arg(
+            Arg::with_name("verify-against")
+                .long("verify-against")
+                .takes_value(true)
+                .value_name("IMAGE")
+                .conflicts_with("diff-against")
+                .help(
+                    "Instead of running tests, verify the chip against IMAGE and \
+                     report the regions that differ if it doesn't match",
+                ),
+        )
+        .arg(
+            Arg::with_name("i-understand-this-is-destructive")
+                .long("i-understand-this-is-destructive")
+                .help(
+                    "Run the tests that erase and rewrite the flash and change its \
+                     write protection; without this they are reported as skipped",
+                ),
+        )
+        .arg(
+            Arg::with_name("erase-block")
+                .long("erase-block")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(|s| flashrom_tester::utils::parse_erase_block(&s).map(|_| ()))
+                .help("Align the test layout's sections to erase blocks of this size"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid seed {:?}: {}", s, e))
+                })
+                .help("Seed the random data written to the flash, to reproduce a run"),
+        )
+        .arg(
+            Arg::with_name("record-transcript")
+                .long("record-transcript")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with("libflashrom")
+                .help(
+                    "Record the output of every flashrom invocation to PATH, for replay in tests",
+                ),
+        )
+        .arg(
+            Arg::with_name("replay-transcript")
+                .long("replay-transcript")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with_all(&["libflashrom", "record-transcript"])
+                .help("Answer flashrom invocations from a transcript recorded at PATH"),
+        )
+        .arg(
+            Arg::with_name("manual-wp")
+                .long("manual-wp")
+                .takes_value(true)
+                .case_insensitive(true)
+                .possible_values(&["prompt", "refuse"])
+                .default_value("prompt")
+                .help(
+                    "Whether to prompt the operator to change hardware write protect by hand, \
+                     or fail instead",
+                ),
+        )
+        .arg(
+            Arg::with_name("hw-wp")
+                .long("hw-wp")
+                .takes_value(true)
+                .case_insensitive(true)
+                .possible_values(&["servo", "manual", "none"])
+                .help(
+                    "How to change hardware write protect: through servo's dut-control, \
+                     by prompting the operator, or not at all [default: manual for the \
+                     host and EC, otherwise none]",
+                ),
+        )
+        .arg(
+            Arg::with_name("compare")
+                .long("compare")
+                .takes_value(true)
+                .value_name("previous-report.json")
+                .help(
+                    "After running, compare the results with a JSON report from a previous \
+                     run and show the tests that changed",
+                ),
+        )
+        .arg(
+            Arg::with_name("heartbeat")
+                .long("heartbeat")
+                .takes_value(true)
+                .value_name("SECS")
+                .conflicts_with("libflashrom")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(0) => Err("heartbeat interval must be at least 1 second".into()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(format!("invalid heartbeat interval {:?}: {}", s, e)),
+                })
+                .help("Log that flashrom is still running every SECS seconds"),
+        )
+        .arg(
+            Arg::with_name("flashrom-log")
+                .long("flashrom-log")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with("libflashrom")
+                .validator(|s| {
+                    if std::path::Path::new(&s).is_dir() {
+                        Ok(())
+                    } else {
+                        Err(format!("{:?} is not a directory", s))
+                    }
+                })
+                .help("Keep flashrom's own log of each invocation in DIR"),
+        )
+        .arg(
+            Arg::with_name("max-runtime")
+                .long("max-runtime")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid max runtime {:?}: {}", s, e))
+                })
+                .help("Kill any flashrom invocation that runs longer than SECS seconds"),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("0")
+                .validator(|s| {
+                    s.parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid retry count {:?}: {}", s, e))
+                })
+                .help(
+                    "Retry flashrom invocations up to N times when the programmer fails \
+                     in a way known to be transient",
+                ),
+        )
+        .arg(
+            Arg::with_name("retry-delay")
+                .long("retry-delay")
+                .takes_value(true)
+                .value_name("MS")
+                .default_value("1000")
+                .validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid retry delay {:?}: {}", s, e))
+                })
+                .help("Wait MS milliseconds before the first retry, doubling for each after"),
+        )
+        .arg(Arg::with_name("wp-by-region").long("wp-by-region").help(
+            "Protect regions by name with --wp-region in the partial lock tests, \
+                     rather than by offset with --wp-range (needs a newer flashrom)",
+        ))
+        .arg(
+            Arg::with_name("report-file")
+                .long("report-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Also write the report to PATH, without colors"),
+        )
+        .arg(
+            Arg::with_name("no-color")
+                .long("no-color")
+                .help("Never color output; colors are already off when stdout isn't a terminal"),
+        )
+        .arg(
+            Arg::with_name("trace-args")
+                .long("trace-args")
+                .conflicts_with("libflashrom")
+                .help("Log the flashrom arguments encoded for every operation"),
+        )
         .arg(
             Arg::with_name("ccd_target_type")
-                .required(true)
-                .possible_values(&["host", "ec", "servo"]),
+                .required_unless("list-tests")
+                .possible_values(&FlashChip::CLI_NAMES),
+        )
+        .arg(
+            Arg::with_name("list-tests")
+                .long("list-tests")
+                .help("Print the name of every test and exit, without touching the Flash"),
         )
         .arg(
             Arg::with_name("print-layout")
@@ -111,9 +393,173 @@ fn main() {
                 .help("Set the test report format")
                 .takes_value(true)
                 .case_insensitive(true)
-                .possible_values(&["pretty", "json"])
+                .possible_values(&["pretty", "json", "junit"])
                 .default_value("pretty"),
         )
+        .arg(Arg::with_name("no-restore").long("no-restore").help(
+            "Do not restore the original Flash contents after tests, \
+                     leaving the chip modified for inspection (DANGEROUS)",
+        ))
+        .arg(
+            Arg::with_name("capture-dmesg")
+                .long("capture-dmesg")
+                .help("Add kernel messages logged during failed destructive tests to their errors (needs root)"),
+        )
+        .arg(
+            Arg::with_name("pre-run")
+                .long("pre-run")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Run this shell command once before any test, aborting if it fails"),
+        )
+        .arg(
+            Arg::with_name("post-run")
+                .long("post-run")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Run this shell command once after all tests"),
+        )
+        .arg(
+            Arg::with_name("restore-on-exit")
+                .long("restore-on-exit")
+                .conflicts_with("no-restore")
+                .help(
+                    "Save the Flash contents and write protect state before testing \
+                     and restore both at the end, even if tests fail",
+                ),
+        )
+        .arg(
+            Arg::with_name("post-results")
+                .long("post-results")
+                .takes_value(true)
+                .value_name("URL")
+                .validator(|s| flashrom_tester::utils::check_post_url(&s))
+                .help("POST the JSON test report to this URL after the tests complete"),
+        )
+        .arg(
+            Arg::with_name("post-header")
+                .long("post-header")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("post-results")
+                .value_name("HEADER")
+                .help("Extra 'Name: value' HTTP header for --post-results (repeatable)"),
+        )
+        .arg(
+            Arg::with_name("report-env")
+                .long("report-env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("VAR")
+                .help(
+                    "Record only these environment variables in the report (repeatable); \
+                     by default FLASHROM_TESTER_LOG and PATH are recorded",
+                ),
+        )
+        .arg(
+            Arg::with_name("layout-file")
+                .long("layout-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Flashrom layout whose regions are each verified; they must not overlap"),
+        )
+        .arg(
+            Arg::with_name("repeat")
+                .long("repeat")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("1")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("{:?} is not a positive number of runs", s)),
+                })
+                .help(
+                    "Run the tests N times, reporting how many times each passed; \
+                     a test only passes if it passed every time",
+                ),
+        )
+        .arg(
+            Arg::with_name("work-dir")
+                .long("work-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                // Paths under it are passed to flashrom as strings.
+                .validator_os(|s| match s.to_str() {
+                    Some(_) => Ok(()),
+                    None => Err("DIR must be valid UTF-8".into()),
+                })
+                .help(
+                    "Create the run's generated files, such as the layout file, \
+                     under DIR instead of the system temporary directory",
+                ),
+        )
+        .arg(
+            Arg::with_name("preflight-only")
+                .long("preflight-only")
+                .help(
+                    "Only run quick, non-destructive health checks, \
+                     exiting with failure status if any fail",
+                ),
+        )
+        .arg(
+            Arg::with_name("digest")
+                .long("digest")
+                .takes_value(true)
+                .case_insensitive(true)
+                .possible_values(&["sha256", "sha1", "crc32", "md5"])
+                .default_value("sha256")
+                .help("Set the digest algorithm used in the test report"),
+        )
+        .arg(
+            Arg::with_name("expected-digest")
+                .long("expected-digest")
+                .takes_value(true)
+                .value_name("ALG:HEX")
+                .validator(|s| s.parse::<flashrom_tester::digests::Digest>().map(|_| ()))
+                .help(
+                    "Fail the Expected_digest test unless the flash contents have this \
+                     digest, given as algorithm:hex or as bare SHA-256 hex",
+                ),
+        )
+        .arg(
+            Arg::with_name("time-budget")
+                .long("time-budget")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(|s| {
+                    s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("invalid time budget {:?}: {}", s, e))
+                })
+                .help("Stop starting new tests when the run would exceed SECS seconds"),
+        )
+        .arg(
+            Arg::with_name("test-filter")
+                .long("test-filter")
+                .takes_value(true)
+                .value_name("REGEX")
+                .validator(|s| regex::Regex::new(&s).map(|_| ()).map_err(|e| e.to_string()))
+                .help(
+                    "Only run tests whose names match REGEX, ignoring case \
+                     (e.g. \"lock|wp\")",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude-tests")
+                .long("exclude-tests")
+                .takes_value(true)
+                .value_name("NAMES")
+                .use_delimiter(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Comma-separated names of tests not to run, ignoring case; they are \
+                     reported as skipped. Applied after test names and --test-filter \
+                     select the tests, so excluding wins",
+                ),
+        )
         .arg(
             Arg::with_name("test_name")
                 .multiple(true)
@@ -121,15 +567,29 @@ fn main() {
         )
         .get_matches();
 
+    let color = !matches.is_present("no-color") && types::stdout_is_tty();
+    types::set_color(color);
     logger::init(
         matches.value_of_os("log-file").map(PathBuf::from),
         matches.is_present("log_debug"),
+        color,
     );
     debug!("Args parsed and logging initialized OK");
 
+    if matches.is_present("list-tests") {
+        if let Err(e) = tests::list_tests(&mut std::io::stdout()) {
+            error!("Failed to list tests: {}", e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+        return;
+    }
+
     debug!("Collecting crossystem info");
-    let crossystem =
-        flashrom_tester::utils::collect_crosssystem(&[]).expect("could not run crossystem");
+    // Not every board (or VM) has crossystem, and the tests don't need it.
+    let crossystem = flashrom_tester::utils::collect_crosssystem(&[]).unwrap_or_else(|e| {
+        warn!("Could not collect crossystem info: {}", e);
+        format!("<crossystem unavailable: {}>", e)
+    });
 
     let ccd_type = FlashChip::from(
         matches
@@ -138,15 +598,14 @@ fn main() {
     )
     .expect("ccd_target_type should admit only known types");
 
+    let laptop_force = matches.is_present("laptop-force");
+    if laptop_force && ccd_type != FlashChip::HOST {
+        eprintln!("--laptop-force only applies to the host's internal programmer");
+        std::process::exit(1);
+    }
+
     let cmd: Box<dyn Flashrom> = if matches.is_present("libflashrom") {
-        Box::new(FlashromLib::new(
-            ccd_type,
-            if matches.is_present("log_debug") {
-                flashrom::FLASHROM_MSG_DEBUG
-            } else {
-                flashrom::FLASHROM_MSG_WARN
-            },
-        ))
+        libflashrom_backend(ccd_type, matches.is_present("log_debug"))
     } else {
         Box::new(FlashromCmd {
             path: matches
@@ -154,28 +613,244 @@ fn main() {
                 .expect("flashrom_binary is required")
                 .to_string(),
             fc: ccd_type,
+            probe_filter: matches.value_of("probe-filter").map(|re| {
+                regex::Regex::new(re).unwrap_or_else(|e| {
+                    eprintln!("Invalid --probe-filter: {}", e);
+                    std::process::exit(1);
+                })
+            }),
+            trace_args: matches.is_present("trace-args"),
+            max_runtime: matches
+                .value_of("max-runtime")
+                .map(|s| std::time::Duration::from_secs(s.parse().expect("validated by clap"))),
+            heartbeat: matches
+                .value_of("heartbeat")
+                .map(|s| std::time::Duration::from_secs(s.parse().expect("validated by clap"))),
+            flashrom_log: matches.value_of("flashrom-log").map(String::from),
+            retries: matches
+                .value_of("retries")
+                .expect("retries should have a default value")
+                .parse()
+                .expect("validated by clap"),
+            retry_delay: std::time::Duration::from_millis(
+                matches
+                    .value_of("retry-delay")
+                    .expect("retry-delay should have a default value")
+                    .parse()
+                    .expect("validated by clap"),
+            ),
+            size: Default::default(),
+            write_verify: matches
+                .value_of("write-verify")
+                .expect("write-verify should have a default value")
+                .parse()
+                .expect("validated by clap"),
+            progress: matches.is_present("progress"),
+            verbosity: matches
+                .occurrences_of("flashrom-verbose")
+                .min(u8::MAX as u64) as u8,
+            programmer: matches.value_of("programmer").map(String::from),
+            programmer_params: matches
+                .values_of("programmer-param")
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .chain(laptop_force.then(|| "laptop=force".to_string()))
+                .collect(),
+            transcript: match (
+                matches.value_of("record-transcript"),
+                matches.value_of("replay-transcript"),
+            ) {
+                (Some(path), _) => Some(Transcript::record(path).unwrap_or_else(|e| {
+                    eprintln!("Invalid --record-transcript: {}", e);
+                    std::process::exit(1);
+                })),
+                (None, Some(path)) => Some(Transcript::replay(path).unwrap_or_else(|e| {
+                    eprintln!("Invalid --replay-transcript: {}", e);
+                    std::process::exit(1);
+                })),
+                (None, None) => None,
+            },
         })
     };
 
-    let print_layout = matches.is_present("print-layout");
-    let output_format = matches
-        .value_of("output-format")
-        .expect("output-format should have a default value")
-        .parse::<tester::OutputFormat>()
-        .expect("output-format is not a parseable OutputFormat");
+    let opts = tests::RunOptions {
+        print_layout: matches.is_present("print-layout"),
+        output_format: matches
+            .value_of("output-format")
+            .expect("output-format should have a default value")
+            .parse::<tester::OutputFormat>()
+            .expect("output-format is not a parseable OutputFormat"),
+        restore: !matches.is_present("no-restore"),
+        restore_on_exit: matches.is_present("restore-on-exit"),
+        capture_dmesg: matches.is_present("capture-dmesg"),
+        pre_run: matches.value_of("pre-run").map(String::from),
+        post_run: matches.value_of("post-run").map(String::from),
+        post_results: matches.value_of("post-results").map(String::from),
+        post_headers: matches
+            .values_of("post-header")
+            .into_iter()
+            .flatten()
+            .map(String::from)
+            .collect(),
+        chip_size: matches
+            .value_of("chip-size")
+            .map(|s| flashrom_tester::utils::parse_chip_size(s).expect("validated by clap")),
+        allow_destructive: matches.is_present("i-understand-this-is-destructive"),
+        seed: matches
+            .value_of("seed")
+            .map(|s| s.parse().expect("validated by clap")),
+        erase_block: matches
+            .value_of("erase-block")
+            .map(|s| flashrom_tester::utils::parse_erase_block(s).expect("validated by clap")),
+        report_env: matches
+            .values_of("report-env")
+            .map(|vars| vars.map(String::from).collect()),
+        digest: matches
+            .value_of("digest")
+            .expect("digest should have a default value")
+            .parse()
+            .expect("digest is not a parseable DigestAlgorithm"),
+        preflight_only: matches.is_present("preflight-only"),
+        layout_file: matches.value_of("layout-file").map(String::from),
+        compare: matches.value_of("compare").map(String::from),
+        report_file: matches.value_of("report-file").map(String::from),
+        verify_after_write: matches.is_present("verify-after-write"),
+        repeat: matches
+            .value_of("repeat")
+            .expect("repeat should have a default value")
+            .parse()
+            .expect("validated by clap"),
+        work_dir: matches.value_of_os("work-dir").map(PathBuf::from),
+        expected_digest: matches
+            .value_of("expected-digest")
+            .map(|s| s.parse().expect("validated by clap")),
+        wp_by_region: matches.is_present("wp-by-region"),
+        exclude_tests: matches
+            .values_of("exclude-tests")
+            .map(|names| names.map(String::from).collect())
+            .unwrap_or_default(),
+        test_filter: matches.value_of("test-filter").map(|re| {
+            regex::RegexBuilder::new(re)
+                .case_insensitive(true)
+                .build()
+                .expect("validated by clap")
+        }),
+        hw_wp: matches
+            .value_of("hw-wp")
+            .map(|s| s.parse().expect("hw-wp is not a parseable HwWpMode")),
+        manual_wp: matches
+            .value_of("manual-wp")
+            .expect("manual-wp should have a default value")
+            .parse()
+            .expect("manual-wp is not a parseable ManualWp"),
+        time_budget: matches
+            .value_of("time-budget")
+            .map(|s| std::time::Duration::from_secs(s.parse().expect("validated by clap"))),
+    };
+    let work_dir = matches.value_of_os("work-dir").map(Path::new);
+    if let Some(reference) = matches.value_of("diff-against") {
+        let diff = flashrom_tester::utils::diff_chip(cmd.as_ref(), reference, work_dir);
+        std::process::exit(report_diff(reference, diff));
+    }
+    if let Some(reference) = matches.value_of("verify-against") {
+        let diff = flashrom_tester::utils::verify_chip(cmd.as_ref(), reference, work_dir);
+        std::process::exit(report_diff(reference, diff));
+    }
     let test_names = matches.values_of("test_name");
+    flashrom_tester::utils::ac_power_warning();
+    if !opts.restore {
+        warn!("*************************************************************");
+        warn!("--no-restore given: the original Flash contents will NOT be");
+        warn!("restored, the DUT will be left modified and may not boot!");
+        warn!("*************************************************************");
+    }
 
-    if let Err(e) = tests::generic(
+    let outcome = match tests::generic(
         cmd.as_ref(),
         ccd_type,
-        print_layout,
-        output_format,
+        &opts,
         test_names,
         Some(handle_sigint()),
         crossystem,
     ) {
-        eprintln!("Failed to run tests: {:?}", e);
-        std::process::exit(1);
+        Ok(outcome) => outcome,
+        Err(e) => {
+            eprintln!("Failed to run tests: {:?}", e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    };
+
+    if opts.preflight_only {
+        if outcome.summary.all_passed() {
+            info!("Preflight checks passed");
+        } else {
+            error!("Preflight checks failed");
+            std::process::exit(EXIT_PREFLIGHT_FAILED);
+        }
+    }
+
+    if !opts.restore {
+        warn!("DUT Flash was left in its post-test state (--no-restore)");
+    }
+    if !outcome.summary.all_passed() {
+        std::process::exit(EXIT_TESTS_FAILED);
+    }
+    if !opts.restore {
+        std::process::exit(EXIT_NOT_RESTORED);
+    }
+}
+
+/// Exit status when the tests could not be run, as opposed to failing.
+const EXIT_INTERNAL_ERROR: i32 = 1;
+/// Exit status for a run where any test failed or was not run.
+const EXIT_TESTS_FAILED: i32 = 2;
+/// Exit status for a run that completed but deliberately left the DUT modified.
+const EXIT_NOT_RESTORED: i32 = 3;
+/// Exit status for a --preflight-only run where any check did not pass.
+const EXIT_PREFLIGHT_FAILED: i32 = 4;
+/// Exit status for --diff-against and --verify-against when the chip differs
+/// from the image.
+const EXIT_IMAGES_DIFFER: i32 = 5;
+
+/// Open `fc` through libflashrom in-process.
+#[cfg(feature = "libflashrom")]
+fn libflashrom_backend(fc: FlashChip, debug: bool) -> Box<dyn Flashrom> {
+    Box::new(flashrom::FlashromLib::new(
+        fc,
+        if debug {
+            flashrom::FLASHROM_MSG_DEBUG
+        } else {
+            flashrom::FLASHROM_MSG_WARN
+        },
+    ))
+}
+
+#[cfg(not(feature = "libflashrom"))]
+fn libflashrom_backend(_fc: FlashChip, _debug: bool) -> Box<dyn Flashrom> {
+    eprintln!("--libflashrom is unavailable: built without the libflashrom feature");
+    std::process::exit(EXIT_INTERNAL_ERROR);
+}
+
+/// Report how the chip differs from the image at `reference`, as found by
+/// `diff`, returning the exit status.
+fn report_diff(reference: &str, diff: Result<Vec<String>, flashrom::FlashromError>) -> i32 {
+    match diff {
+        Ok(spans) if spans.is_empty() => {
+            info!("Chip contents match {}", reference);
+            0
+        }
+        Ok(spans) => {
+            error!("Chip contents differ from {} at:", reference);
+            for span in spans {
+                error!("  {}", span);
+            }
+            EXIT_IMAGES_DIFFER
+        }
+        Err(e) => {
+            eprintln!("Failed to compare the chip with {}: {}", reference, e);
+            EXIT_INTERNAL_ERROR
+        }
     }
 }
 
@@ -35,7 +35,7 @@
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use std::io::{self, BufWriter};
 
 use rand::prelude::*;
 
@@ -52,6 +52,45 @@ pub fn gen_rand_testdata(path: &str, size: usize) -> std::io::Result<()> {
     Ok(())
 }
 
+/// How many times to regenerate random data that matches the image it must
+/// differ from before deciding the RNG is broken.
+const MAX_ATTEMPTS: usize = 4;
+
+/// Return a seed for `gen_rand_testdata_unlike` when none was chosen.
+pub fn random_seed() -> u64 {
+    thread_rng().gen()
+}
+
+/// Like `gen_rand_testdata`, but guarantees the data differs from `avoid`, and
+/// is the same every time for a given `seed` so failures can be reproduced.
+///
+/// Tests that expect writing random data to change the flash would spuriously
+/// pass if it happened to match the current contents.
+pub fn gen_rand_testdata_unlike(
+    path: &str,
+    size: usize,
+    avoid: &[u8],
+    seed: u64,
+) -> io::Result<()> {
+    let data = rand_data_unlike(&mut StdRng::seed_from_u64(seed), size, avoid)?;
+    File::create(path)?.write_all(&data)
+}
+
+fn rand_data_unlike<R: Rng>(rng: &mut R, size: usize, avoid: &[u8]) -> io::Result<Vec<u8>> {
+    let mut a = vec![0u8; size];
+    for _ in 0..MAX_ATTEMPTS {
+        rng.fill(a.as_mut_slice());
+        if a != avoid {
+            return Ok(a);
+        }
+        warn!("Generated random data is identical to the flash contents; regenerating");
+    }
+    Err(io::Error::other(format!(
+        "Random data matched the flash contents {} times in a row",
+        MAX_ATTEMPTS
+    )))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -78,4 +117,47 @@ mod tests {
 
         assert_ne!(buf0, buf1);
     }
+
+    #[test]
+    fn gen_rand_testdata_unlike_seeded() {
+        use super::gen_rand_testdata_unlike;
+
+        let path0 = "/tmp/idk_test_seeded00";
+        let path1 = "/tmp/idk_test_seeded01";
+        let path2 = "/tmp/idk_test_seeded02";
+        let sz = 1024;
+
+        gen_rand_testdata_unlike(path0, sz, &[], 42).unwrap();
+        gen_rand_testdata_unlike(path1, sz, &[], 42).unwrap();
+        gen_rand_testdata_unlike(path2, sz, &[], 43).unwrap();
+
+        let buf0 = std::fs::read(path0).unwrap();
+        assert_eq!(buf0.len(), sz);
+        assert_eq!(buf0, std::fs::read(path1).unwrap());
+        assert_ne!(buf0, std::fs::read(path2).unwrap());
+    }
+
+    #[test]
+    fn rand_data_unlike_regenerates() {
+        use super::rand_data_unlike;
+        use rand::rngs::mock::StepRng;
+
+        // Predict what the first fill will produce and ask to avoid it.
+        let rng = StepRng::new(1, 1);
+        let mut first = vec![0u8; 64];
+        rng.clone().fill(first.as_mut_slice());
+
+        let data = rand_data_unlike(&mut rng.clone(), first.len(), &first).unwrap();
+        assert_eq!(data.len(), first.len());
+        assert_ne!(data, first);
+    }
+
+    #[test]
+    fn rand_data_unlike_gives_up() {
+        use super::rand_data_unlike;
+        use rand::rngs::mock::StepRng;
+
+        // A generator stuck on zero can never avoid an all-zero image.
+        assert!(rand_data_unlike(&mut StepRng::new(0, 0), 64, &[0; 64]).is_err());
+    }
 }
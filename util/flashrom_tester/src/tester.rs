@@ -33,76 +33,372 @@
 // Software Foundation.
 //
 
+use super::digests::Digest;
+use super::golden::GoldenImage;
+use super::hw_wp::HwWpController;
 use super::rand_util;
 use super::types;
-use super::utils::{self, LayoutSizes};
+use super::utils::{self, LayoutRegion, LayoutSizes};
 use flashrom::FlashromError;
-use flashrom::{FlashChip, Flashrom};
+use flashrom::{FlashChip, Flashrom, ROMWriteSpecifics, WpStatus};
 use serde_json::json;
+use std::cell::Cell;
+use std::fs::File;
 use std::mem::MaybeUninit;
+use std::path::Path;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tempfile::{NamedTempFile, TempDir};
 
 // type-signature comes from the return type of lib.rs workers.
-type TestError = Box<dyn std::error::Error>;
+pub type TestError = Box<dyn std::error::Error>;
 pub type TestResult = Result<(), TestError>;
 
+/// Returned by a test that doesn't apply to the chip or host under test,
+/// giving the reason, so that it is reported as skipped rather than passed.
+#[derive(Debug)]
+pub struct Skip(pub String);
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Skip {}
+
+/// The outcome of running a single test.
+pub struct TestRun {
+    pub name: String,
+    pub conclusion: TestConclusion,
+    pub error: Option<TestError>,
+    /// True if the test issued at least one write or erase to the flash.
+    pub modified_flash: bool,
+    /// How long the test took to run.
+    pub duration: Duration,
+    /// When the suite was repeated, how many of the runs of this test passed
+    /// out of how many it was run.
+    pub passes: Option<(u32, u32)>,
+}
+
+/// A Flashrom that counts the operations which can modify flash contents,
+/// forwarding everything to the wrapped implementation.
+pub struct CountingFlashrom<'a> {
+    inner: &'a dyn Flashrom,
+    modifications: Cell<usize>,
+}
+
+impl<'a> CountingFlashrom<'a> {
+    fn new(inner: &'a dyn Flashrom) -> Self {
+        CountingFlashrom {
+            inner,
+            modifications: Cell::new(0),
+        }
+    }
+
+    fn count<T>(&self, r: Result<T, FlashromError>) -> Result<T, FlashromError> {
+        self.modifications.set(self.modifications.get() + 1);
+        r
+    }
+}
+
+impl Flashrom for CountingFlashrom<'_> {
+    fn get_size(&self) -> Result<i64, FlashromError> {
+        self.inner.get_size()
+    }
+
+    fn probe_size(&self) -> Result<i64, FlashromError> {
+        self.inner.probe_size()
+    }
+
+    fn name(&self) -> Result<(String, String), FlashromError> {
+        self.inner.name()
+    }
+
+    fn probe_chips(&self) -> Result<Vec<String>, FlashromError> {
+        self.inner.probe_chips()
+    }
+
+    fn voltage(&self) -> Result<String, FlashromError> {
+        self.inner.voltage()
+    }
+
+    fn write_file_with_layout(&self, rws: &ROMWriteSpecifics) -> Result<bool, FlashromError> {
+        self.count(self.inner.write_file_with_layout(rws))
+    }
+
+    fn write_ifd_region(&self, path: &str, region: &str) -> Result<(), FlashromError> {
+        self.count(self.inner.write_ifd_region(path, region))
+    }
+
+    fn wp_range(&self, range: (i64, i64), wp_enable: bool) -> Result<bool, FlashromError> {
+        self.inner.wp_range(range, wp_enable)
+    }
+
+    fn wp_region(
+        &self,
+        layout_file: &str,
+        region: &str,
+        wp_enable: bool,
+    ) -> Result<bool, FlashromError> {
+        self.inner.wp_region(layout_file, region, wp_enable)
+    }
+
+    fn wp_list(&self) -> Result<String, FlashromError> {
+        self.inner.wp_list()
+    }
+
+    fn read_to_vec(&self, scratch: &str) -> Result<Vec<u8>, FlashromError> {
+        self.inner.read_to_vec(scratch)
+    }
+
+    fn wp_status(&self, en: bool) -> Result<bool, FlashromError> {
+        self.inner.wp_status(en)
+    }
+
+    fn wp_get_state(&self) -> Result<WpStatus, FlashromError> {
+        self.inner.wp_get_state()
+    }
+
+    fn wp_toggle(&self, en: bool) -> Result<bool, FlashromError> {
+        self.inner.wp_toggle(en)
+    }
+
+    fn read_into_file(&self, path: &str) -> Result<(), FlashromError> {
+        self.inner.read_into_file(path)
+    }
+
+    fn read_region_into_file(&self, path: &str, region: &str) -> Result<(), FlashromError> {
+        self.inner.read_region_into_file(path, region)
+    }
+
+    fn write_from_file(&self, path: &str) -> Result<(), FlashromError> {
+        self.count(self.inner.write_from_file(path))
+    }
+
+    fn write_from_file_changed(&self, path: &str) -> Result<bool, FlashromError> {
+        match self.inner.write_from_file_changed(path) {
+            // Nothing was written.
+            Ok(false) => Ok(false),
+            r => self.count(r),
+        }
+    }
+
+    fn verify_from_file(&self, path: &str) -> Result<(), FlashromError> {
+        self.inner.verify_from_file(path)
+    }
+
+    fn verify_file_with_layout(
+        &self,
+        layout_file: &str,
+        region: &str,
+        path: &str,
+    ) -> Result<(), FlashromError> {
+        self.inner
+            .verify_file_with_layout(layout_file, region, path)
+    }
+
+    fn erase(&self) -> Result<(), FlashromError> {
+        self.count(self.inner.erase())
+    }
+
+    fn reinit_programmer(&self) -> Result<(), FlashromError> {
+        self.inner.reinit_programmer()
+    }
+
+    fn can_control_hw_wp(&self) -> bool {
+        self.inner.can_control_hw_wp()
+    }
+}
+
 pub struct TestEnv<'a> {
     chip_type: FlashChip,
     /// Flashrom instantiation information.
     ///
     /// Where possible, prefer to use methods on the TestEnv rather than delegating
-    /// to the raw flashrom functions.
-    pub cmd: &'a dyn Flashrom,
+    /// to the raw flashrom functions. Writes and erases issued through this are
+    /// counted to report whether each test modified the flash.
+    pub cmd: CountingFlashrom<'a>,
+    /// Size of the chip in bytes, which may differ from what the programmer reports.
+    rom_sz: i64,
     layout: LayoutSizes,
+    /// Regions of a layout supplied by the user, if any.
+    user_layout: Vec<LayoutRegion>,
 
     pub wp: WriteProtectState<'a, 'static>,
     /// The path to a file containing the flash contents at test start.
     // TODO(pmarheine) migrate this to a PathBuf for clarity
     original_flash_contents: String,
+    /// The contents of original_flash_contents, for comparison against reads.
+    golden: GoldenImage,
+    /// The path to a scratch file that flash contents are read into for
+    /// comparison with the golden image.
+    readback: String,
     /// The path to a file containing flash-sized random data
     // TODO(pmarheine) make this a PathBuf too
     random_data: String,
+    /// The seed `random_data` was generated from.
+    seed: u64,
+    /// The path to a layout file describing the sections in `layout`.
+    layout_file: String,
+    /// If false, the golden image is never written back automatically so the
+    /// chip can be inspected in whatever state the tests left it.
+    restore: bool,
+    /// If true, writes of whole images are followed by a separate verify.
+    verify_after_write: bool,
+    /// How long the body of the current (or most recent) test took.
+    test_duration: Duration,
+    /// The output of `--wp-list`, or why protection ranges can't be listed.
+    wp_list: Result<String, String>,
+    /// If true, kernel messages logged during failed destructive tests are
+    /// added to their errors.
+    capture_dmesg: bool,
+    /// Flash contents and write protect configuration to put back when the
+    /// environment is dropped, if requested with `restore_on_exit`.
+    exit_state: Option<(Snapshot, WpStatus)>,
+    /// True once the end-of-suite restore has run, so dropping the
+    /// environment doesn't repeat it.
+    finished: bool,
+    /// Directory holding all of the above files, private to this environment.
+    ///
+    /// Declared last so it outlives everything that uses the files, and removed
+    /// along with its contents when the environment is dropped.
+    dir: TempDir,
 }
 
 impl<'a> TestEnv<'a> {
-    pub fn create(chip_type: FlashChip, cmd: &'a dyn Flashrom) -> Result<Self, FlashromError> {
-        let rom_sz = cmd.get_size()?;
-        let out = TestEnv {
-            chip_type: chip_type,
-            cmd: cmd,
-            layout: utils::get_layout_sizes(rom_sz)?,
-            wp: WriteProtectState::from_hardware(cmd, chip_type)?,
-            original_flash_contents: "/tmp/flashrom_tester_golden.bin".into(),
-            random_data: "/tmp/random_content.bin".into(),
-        };
-
+    /// Set up the environment, with its files in a new directory under
+    /// `work_dir`, or the system temporary directory if that is None.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create(
+        chip_type: FlashChip,
+        cmd: &'a dyn Flashrom,
+        rom_sz: i64,
+        user_layout: Vec<LayoutRegion>,
+        restore: bool,
+        hw_wp: Rc<dyn HwWpController>,
+        verify_after_write: bool,
+        work_dir: Option<&Path>,
+        erase_block: Option<i64>,
+        seed: u64,
+    ) -> Result<Self, FlashromError> {
+        let layout = utils::get_layout_sizes(rom_sz, erase_block)?;
+        let dir = tempfile::Builder::new()
+            .prefix("flashrom_tester.")
+            .tempdir_in(utils::work_dir_or_temp(work_dir))
+            .map_err(|e| format!("I/O error creating temporary directory: {:#}", e))?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        let original_flash_contents = path("golden.bin");
         info!("Stashing golden image for verification/recovery on completion");
-        out.cmd.read_into_file(&out.original_flash_contents)?;
-        out.cmd.verify_from_file(&out.original_flash_contents)?;
-
-        info!("Generating random flash-sized data");
-        rand_util::gen_rand_testdata(&out.random_data, rom_sz as usize)
+        cmd.read_into_file(&original_flash_contents)?;
+        cmd.verify_from_file(&original_flash_contents)?;
+        let golden = GoldenImage::open(&original_flash_contents)
+            .map_err(|e| format!("I/O error opening golden image: {:#}", e))?;
+
+        let random_data = path("random_content.bin");
+        info!("Generating random flash-sized data with seed {}", seed);
+        rand_util::gen_rand_testdata_unlike(&random_data, rom_sz as usize, golden.as_bytes(), seed)
             .map_err(|io_err| format!("I/O error writing random data file: {:#}", io_err))?;
 
-        Ok(out)
+        let layout_file = path("layout.file");
+        File::create(&layout_file)
+            .and_then(|f| utils::construct_layout_file(f, &layout))
+            .map_err(|e| format!("I/O error writing layout file: {:#}", e))?;
+
+        let wp_list = utils::wp_list_support(cmd.wp_list());
+        if let Err(reason) = &wp_list {
+            warn!(
+                "Tests needing protection ranges will be skipped: {}",
+                reason
+            );
+        }
+
+        Ok(TestEnv {
+            chip_type,
+            cmd: CountingFlashrom::new(cmd),
+            rom_sz,
+            layout,
+            user_layout,
+            wp: WriteProtectState::from_hardware(cmd, chip_type, hw_wp)?,
+            original_flash_contents,
+            golden,
+            readback: path("readback.bin"),
+            random_data,
+            seed,
+            layout_file,
+            restore,
+            verify_after_write,
+            test_duration: Duration::from_secs(0),
+            wp_list,
+            capture_dmesg: false,
+            exit_state: None,
+            finished: false,
+            dir,
+        })
     }
 
     pub fn run_test<T: TestCase>(&mut self, test: T) -> TestResult {
-        let use_dut_control = self.chip_type == FlashChip::SERVO;
-        if use_dut_control && flashrom::dut_ctrl_toggle_wp(false).is_err() {
-            error!("failed to dispatch dut_ctrl_toggle_wp()!");
-        }
+        let dmesg_before = if self.capture_dmesg && test.destructive() {
+            utils::collect_dmesg()
+                .map_err(|e| warn!("Not capturing kernel messages: {}", e))
+                .ok()
+        } else {
+            None
+        };
 
+        self.cmd.modifications.set(0);
         let name = test.get_name();
         info!("Beginning test: {}", name);
+        let start = Instant::now();
         let out = test.run(self);
+        self.test_duration = start.elapsed();
         info!("Completed test: {}; result {:?}", name, out);
 
-        if use_dut_control && flashrom::dut_ctrl_toggle_wp(true).is_err() {
-            error!("failed to dispatch dut_ctrl_toggle_wp()!");
+        match (out, dmesg_before) {
+            (Err(e), Some(before)) if !e.is::<Skip>() => Err(with_new_dmesg(e, &before)),
+            (out, _) => out,
+        }
+    }
+
+    /// Include kernel messages logged during destructive tests in their errors
+    /// if they fail. Reading the kernel log usually needs root.
+    pub fn capture_dmesg(&mut self) {
+        self.capture_dmesg = true;
+    }
+
+    /// Return true if servo's write protect must be held off with dut-control
+    /// for the run, because it can't be controlled through `wp`.
+    pub fn needs_dut_control(&self) -> bool {
+        self.chip_type == FlashChip::SERVO && !self.wp.can_control_hw_wp()
+    }
+
+    /// Return true if the flash has been written or erased since the current
+    /// (or most recent) test began.
+    pub fn modified_flash(&self) -> bool {
+        self.cmd.modifications.get() > 0
+    }
+
+    /// Return how long the body of the current (or most recent) test took,
+    /// excluding the setup and teardown around it.
+    pub fn test_duration(&self) -> Duration {
+        self.test_duration
+    }
+
+    /// Return the size of the chip in bytes.
+    pub fn rom_size(&self) -> i64 {
+        self.rom_sz
+    }
+
+    /// Return the protection ranges the chip supports as (start, len) pairs,
+    /// or skip the calling test if they can't be listed.
+    pub fn wp_ranges(&self) -> Result<Vec<(i64, i64)>, TestError> {
+        match &self.wp_list {
+            Ok(list) => Ok(utils::parse_wp_list(list)?),
+            Err(reason) => Err(Skip(reason.clone()).into()),
         }
-        out
     }
 
     pub fn chip_type(&self) -> FlashChip {
@@ -117,23 +413,150 @@ impl<'a> TestEnv<'a> {
         &self.random_data
     }
 
+    /// Return the seed the random data was generated from, for tests that
+    /// generate more so it too is reproducible.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Return the flash contents at test start.
+    pub fn golden(&self) -> &GoldenImage {
+        &self.golden
+    }
+
+    /// Return the regions of the user-supplied layout, empty if none was given.
+    pub fn user_layout(&self) -> &[LayoutRegion] {
+        &self.user_layout
+    }
+
     pub fn layout(&self) -> &LayoutSizes {
         &self.layout
     }
 
+    /// Return the path to a file containing the flash contents at test start.
+    pub fn golden_file(&self) -> &str {
+        &self.original_flash_contents
+    }
+
+    /// Return the path to a layout file describing the sections of `layout()`.
+    pub fn layout_file(&self) -> &str {
+        &self.layout_file
+    }
+
+    /// Return the path to a scratch file called `name`, which is removed along
+    /// with this TestEnv.
+    pub fn scratch_file(&self, name: &str) -> String {
+        self.dir.path().join(name).to_string_lossy().into_owned()
+    }
+
     /// Return true if the current Flash contents are the same as the golden image
     /// that was present at the start of testing.
     pub fn is_golden(&self) -> bool {
-        self.cmd
-            .verify_from_file(&self.original_flash_contents)
-            .is_ok()
+        let compare = || -> Result<Option<usize>, FlashromError> {
+            let contents = self.cmd.read_to_vec(&self.readback)?;
+            let offset = self
+                .golden
+                .first_difference(&contents[..])
+                .map_err(|e| e.to_string())?;
+            Ok(offset)
+        };
+
+        match compare() {
+            Ok(None) => true,
+            Ok(Some(offset)) => {
+                debug!("Flash differs from golden image at {:#x}", offset);
+                false
+            }
+            Err(e) => {
+                debug!("Unable to compare Flash with golden image: {}", e);
+                false
+            }
+        }
     }
 
     /// Do whatever is necessary to make the current Flash contents the same as they
     /// were at the start of testing.
     pub fn ensure_golden(&mut self) -> Result<(), FlashromError> {
         self.wp.set_hw(false)?.set_sw(false)?;
-        self.cmd.write_from_file(&self.original_flash_contents)?;
+        self.write(&self.original_flash_contents)
+    }
+
+    /// Write the whole flash from the file at `path`, verifying it separately
+    /// afterwards if verify-after-write was requested.
+    pub fn write(&self, path: &str) -> Result<(), FlashromError> {
+        if self.verify_after_write {
+            self.cmd.write_and_verify(path)
+        } else {
+            self.cmd.write_from_file(path)
+        }
+    }
+
+    /// Write the golden image back after a test that deliberately modified the
+    /// Flash, unless automatic restoration has been disabled.
+    pub fn restore_golden(&mut self) -> Result<(), FlashromError> {
+        if !self.restore {
+            warn!("Automatic restore disabled; leaving Flash as modified by the test");
+            return Ok(());
+        }
+        self.ensure_golden()
+    }
+
+    /// Read the current Flash contents into a snapshot that `restore_snapshot`
+    /// can write back. The snapshot's file is removed when it is dropped.
+    pub fn snapshot(&self) -> Result<Snapshot, FlashromError> {
+        let file = NamedTempFile::new_in(self.dir.path())
+            .map_err(|e| format!("I/O error creating snapshot file: {:#}", e))?;
+        let snapshot = Snapshot { file };
+        self.cmd.read_into_file(utf8_path(snapshot.path())?)?;
+        Ok(snapshot)
+    }
+
+    /// Write `snapshot` back to the Flash and verify that it took.
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), FlashromError> {
+        let path = utf8_path(snapshot.path())?;
+        self.wp.set_hw(false)?.set_sw(false)?;
+        self.cmd.write_from_file(path)?;
+        self.cmd.verify_from_file(path)?;
+        Ok(())
+    }
+
+    /// Save the current Flash contents and write protect configuration, and
+    /// put both back when the environment is dropped, even if tests fail.
+    pub fn restore_on_exit(&mut self) -> Result<(), FlashromError> {
+        let snapshot = self.snapshot()?;
+        let wp = self.cmd.wp_get_state()?;
+        info!(
+            "Saved Flash contents and write protect state {:?} to restore on exit",
+            wp
+        );
+        self.exit_state = Some((snapshot, wp));
+        Ok(())
+    }
+
+    fn restore_exit_state(&mut self, snapshot: &Snapshot, wp: &WpStatus) -> TestResult {
+        if self
+            .cmd
+            .verify_from_file(utf8_path(snapshot.path())?)
+            .is_ok()
+        {
+            info!("Flash contents are unchanged since startup");
+        } else {
+            self.restore_snapshot(snapshot)?;
+            info!("Restored the Flash contents saved at startup");
+        }
+
+        let current = self.cmd.wp_get_state()?;
+        if current == *wp {
+            info!("Write protect state is unchanged since startup");
+            return Ok(());
+        }
+        // Keep self.wp in step, so it doesn't toggle software WP again (and
+        // lose the range) when it is dropped.
+        self.wp.set_hw(false)?.set_sw(wp.enabled)?;
+        if wp.enabled {
+            self.cmd.wp_range(wp.range, true)?;
+        }
+        info!("Restored write protect state {:?} (was {:?})", wp, current);
         Ok(())
     }
 
@@ -151,16 +574,113 @@ impl<'a> TestEnv<'a> {
         self.cmd.verify_from_file(contents_path)?;
         Ok(())
     }
+
+    /// Put the chip back as it was at the start of the suite: the state saved
+    /// by `restore_on_exit` if any, then the golden image unless automatic
+    /// restore is disabled.
+    ///
+    /// Call this when the suite ends. Dropping the environment does the same
+    /// if it hasn't been done, but drop never runs if the process exits first.
+    pub fn finish(mut self) {
+        self.restore_at_exit();
+    }
+
+    fn restore_at_exit(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        if let Some((snapshot, wp)) = self.exit_state.take() {
+            if let Err(e) = self.restore_exit_state(&snapshot, &wp) {
+                error!("Failed to restore the state saved at startup: {}", e);
+            }
+        }
+
+        if !self.restore {
+            warn!("*****************************************************");
+            warn!("Automatic restore disabled: Flash was NOT verified or");
+            warn!("restored and may be left in a modified state!");
+            warn!("*****************************************************");
+        } else {
+            info!("Verifying flash remains unmodified");
+            if !self.is_golden() {
+                warn!("ROM seems to be in a different state at finish; restoring original");
+                if let Err(e) = self.ensure_golden() {
+                    error!("Failed to write back golden image: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Append the kernel messages logged since `before` to `e`.
+fn with_new_dmesg(e: TestError, before: &str) -> TestError {
+    let after = match utils::collect_dmesg() {
+        Ok(after) => after,
+        Err(err) => {
+            warn!("Failed to capture kernel messages: {}", err);
+            return e;
+        }
+    };
+    let lines = utils::new_dmesg_lines(before, &after);
+    if lines.is_empty() {
+        return e;
+    }
+    format!(
+        "{}\nKernel messages during the test:\n{}",
+        e,
+        lines.join("\n")
+    )
+    .into()
+}
+
+/// Run `body` between `setup` and `teardown`. The body is skipped if setup
+/// fails, but teardown always runs to clean up; the first error is returned.
+pub fn with_setup(
+    setup: impl FnOnce() -> TestResult,
+    body: impl FnOnce() -> TestResult,
+    teardown: impl FnOnce() -> TestResult,
+) -> TestResult {
+    let out = setup().and_then(|()| body());
+    match (out, teardown()) {
+        (Err(e), Err(cleanup)) => {
+            error!("Test teardown also failed: {}", cleanup);
+            Err(e)
+        }
+        (out, cleanup) => out.and(cleanup),
+    }
+}
+
+/// Flash contents saved by `TestEnv::snapshot`.
+pub struct Snapshot {
+    file: NamedTempFile,
+}
+
+impl Snapshot {
+    /// Return the path to the file holding the saved contents.
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Return `path` as a string to pass to flashrom, which needs it to be UTF-8.
+fn utf8_path(path: &Path) -> Result<&str, FlashromError> {
+    path.to_str()
+        .ok_or_else(|| format!("Path {} is not valid UTF-8", path.display()).into())
 }
 
 impl<'a> Drop for TestEnv<'a> {
     fn drop(&mut self) {
-        info!("Verifying flash remains unmodified");
-        if !self.is_golden() {
-            warn!("ROM seems to be in a different state at finish; restoring original");
-            if let Err(e) = self.ensure_golden() {
-                error!("Failed to write back golden image: {:?}", e);
-            }
+        self.restore_at_exit();
+
+        // TempDir would remove this too, but silently ignores failures.
+        if let Err(e) = std::fs::remove_dir_all(self.dir.path()) {
+            debug!(
+                "Failed to remove temporary files in {}: {}",
+                self.dir.path().display(),
+                e
+            );
         }
     }
 }
@@ -185,6 +705,7 @@ pub struct WriteProtectState<'a, 'p> {
     current: (bool, bool),
     cmd: &'a dyn Flashrom,
     fc: FlashChip,
+    hw: Rc<dyn HwWpController>,
 }
 
 enum InitialState<'p> {
@@ -206,7 +727,11 @@ impl<'a> WriteProtectState<'a, 'static> {
     ///
     /// Panics if there is already a live state derived from hardware. In such a situation the
     /// new state must be derived from the live one, or the live one must be dropped first.
-    pub fn from_hardware(cmd: &'a dyn Flashrom, fc: FlashChip) -> Result<Self, FlashromError> {
+    pub fn from_hardware(
+        cmd: &'a dyn Flashrom,
+        fc: FlashChip,
+        hw: Rc<dyn HwWpController>,
+    ) -> Result<Self, FlashromError> {
         let mut lock = Self::get_liveness_lock()
             .lock()
             .expect("Somebody panicked during WriteProtectState init from hardware");
@@ -215,23 +740,27 @@ impl<'a> WriteProtectState<'a, 'static> {
             panic!("Attempted to create a new WriteProtectState when one is already live");
         }
 
-        let hw = Self::get_hw(cmd)?;
+        let hw_enabled = Self::get_hw(hw.as_ref())?;
         let sw = Self::get_sw(cmd)?;
-        info!("Initial hardware write protect: HW={} SW={}", hw, sw);
+        info!(
+            "Initial hardware write protect: HW={} SW={}",
+            hw_enabled, sw
+        );
 
         *lock = true;
         Ok(WriteProtectState {
-            initial: InitialState::Hardware(hw, sw),
-            current: (hw, sw),
+            initial: InitialState::Hardware(hw_enabled, sw),
+            current: (hw_enabled, sw),
             cmd,
             fc,
+            hw,
         })
     }
 
     /// Get the actual hardware write protect state.
-    fn get_hw(cmd: &dyn Flashrom) -> Result<bool, String> {
-        if cmd.can_control_hw_wp() {
-            super::utils::get_hardware_wp()
+    fn get_hw(hw: &dyn HwWpController) -> Result<bool, String> {
+        if hw.can_control() {
+            hw.get()
         } else {
             Ok(false)
         }
@@ -250,7 +779,7 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
     ///
     /// If false, calls to set_hw() will do nothing.
     pub fn can_control_hw_wp(&self) -> bool {
-        self.cmd.can_control_hw_wp()
+        self.hw.can_control()
     }
 
     /// Set the software write protect.
@@ -267,7 +796,7 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
     pub fn set_hw(&mut self, enable: bool) -> Result<&mut Self, String> {
         if self.current.0 != enable {
             if self.can_control_hw_wp() {
-                super::utils::toggle_hw_wp(/* dis= */ !enable)?;
+                self.hw.set(enable)?;
                 self.current.0 = enable;
             } else if enable {
                 info!(
@@ -279,14 +808,24 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
         Ok(self)
     }
 
+    /// Return the means of changing hardware write protect, which states pushed
+    /// from this one share.
+    pub fn hw_wp(&self) -> &dyn HwWpController {
+        self.hw.as_ref()
+    }
+
     /// Stack a new write protect state on top of the current one.
     ///
     /// This is useful if you need to temporarily make a change to write protection:
     ///
     /// ```no_run
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// # let cmd: flashrom::FlashromCmd = unimplemented!();
-    /// let wp = flashrom_tester::tester::WriteProtectState::from_hardware(&cmd, flashrom::FlashChip::SERVO)?;
+    /// # let cmd: &dyn flashrom::Flashrom = unimplemented!();
+    /// let wp = flashrom_tester::tester::WriteProtectState::from_hardware(
+    ///     cmd,
+    ///     flashrom::FlashChip::SERVO,
+    ///     std::rc::Rc::new(flashrom_tester::hw_wp::Servo),
+    /// )?;
     /// {
     ///     let mut wp = wp.push();
     ///     wp.set_sw(false)?;
@@ -307,6 +846,7 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
             current: self.current,
             cmd: self.cmd,
             fc: self.fc,
+            hw: Rc::clone(&self.hw),
         }
     }
 
@@ -378,7 +918,7 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
         if sw != self.current.1 {
             // Is the hw wp currently enabled?
             if self.current.0 {
-                super::utils::toggle_hw_wp(/* dis= */ true).map_err(|e| {
+                self.hw.set(false).map_err(|e| {
                     format!(
                         "Failed to {}able hardware write protect: {}",
                         enable_str(false),
@@ -396,11 +936,11 @@ impl<'a, 'p> WriteProtectState<'a, 'p> {
         }
 
         assert!(
-            self.cmd.can_control_hw_wp() || (!self.current.0 && !hw),
+            self.hw.can_control() || (!self.current.0 && !hw),
             "HW WP must be disabled if it cannot be controlled"
         );
         if hw != self.current.0 {
-            super::utils::toggle_hw_wp(/* dis= */ !hw).map_err(|e| {
+            self.hw.set(hw).map_err(|e| {
                 format!(
                     "Failed to {}able hardware write protect: {}",
                     enable_str(hw),
@@ -429,19 +969,29 @@ impl<'a, 'p> Drop for WriteProtectState<'a, 'p> {
 
 pub trait TestCase {
     fn get_name(&self) -> &str;
-    fn expected_result(&self) -> TestConclusion;
+    /// The conclusion the test should reach on chips of type `fc`.
+    fn expected_result(&self, fc: FlashChip) -> TestConclusion;
+    /// True if the test may modify the flash contents or its write protect
+    /// configuration.
+    fn destructive(&self) -> bool;
     fn run(&self, env: &mut TestEnv) -> TestResult;
 }
 
+/// A (name, function) test is assumed to be destructive unless wrapped in
+/// `ReadOnly`.
 impl<S: AsRef<str>, F: Fn(&mut TestEnv) -> TestResult> TestCase for (S, F) {
     fn get_name(&self) -> &str {
         self.0.as_ref()
     }
 
-    fn expected_result(&self) -> TestConclusion {
+    fn expected_result(&self, _fc: FlashChip) -> TestConclusion {
         TestConclusion::Pass
     }
 
+    fn destructive(&self) -> bool {
+        true
+    }
+
     fn run(&self, env: &mut TestEnv) -> TestResult {
         (self.1)(env)
     }
@@ -452,8 +1002,12 @@ impl<T: TestCase + ?Sized> TestCase for &T {
         (*self).get_name()
     }
 
-    fn expected_result(&self) -> TestConclusion {
-        (*self).expected_result()
+    fn expected_result(&self, fc: FlashChip) -> TestConclusion {
+        (*self).expected_result(fc)
+    }
+
+    fn destructive(&self) -> bool {
+        (*self).destructive()
     }
 
     fn run(&self, env: &mut TestEnv) -> TestResult {
@@ -461,6 +1015,53 @@ impl<T: TestCase + ?Sized> TestCase for &T {
     }
 }
 
+/// A test that only reads the flash and its write protect status.
+pub struct ReadOnly<T>(pub T);
+
+impl<T: TestCase> TestCase for ReadOnly<T> {
+    fn get_name(&self) -> &str {
+        self.0.get_name()
+    }
+
+    fn expected_result(&self, fc: FlashChip) -> TestConclusion {
+        self.0.expected_result(fc)
+    }
+
+    fn destructive(&self) -> bool {
+        false
+    }
+
+    fn run(&self, env: &mut TestEnv) -> TestResult {
+        self.0.run(env)
+    }
+}
+
+/// A test that must skip itself on chips for which the function returns true,
+/// so that losing the check that makes it skip is reported.
+pub struct ExpectSkip<T>(pub T, pub fn(FlashChip) -> bool);
+
+impl<T: TestCase> TestCase for ExpectSkip<T> {
+    fn get_name(&self) -> &str {
+        self.0.get_name()
+    }
+
+    fn expected_result(&self, fc: FlashChip) -> TestConclusion {
+        if (self.1)(fc) {
+            TestConclusion::Skipped
+        } else {
+            self.0.expected_result(fc)
+        }
+    }
+
+    fn destructive(&self) -> bool {
+        self.0.destructive()
+    }
+
+    fn run(&self, env: &mut TestEnv) -> TestResult {
+        self.0.run(env)
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TestConclusion {
@@ -468,56 +1069,189 @@ pub enum TestConclusion {
     Fail,
     UnexpectedPass,
     UnexpectedFail,
+    /// Not started because the run stopped early, or its time budget was
+    /// nearly exhausted.
+    NotRun,
+    /// Not applicable to the chip or host under test.
+    Skipped,
+    /// Ran when it should have skipped itself as not applicable.
+    UnexpectedRun,
+}
+
+/// How many tests in a report reached each kind of conclusion.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Summary {
+    pub passed: usize,
+    /// Tests with any unexpected result.
+    pub failed: usize,
+    pub skipped: usize,
+    pub not_run: usize,
+}
+
+impl Summary {
+    pub fn add(&mut self, conclusion: TestConclusion) {
+        use TestConclusion::*;
+
+        match conclusion {
+            Pass => self.passed += 1,
+            Fail | UnexpectedPass | UnexpectedFail | UnexpectedRun => self.failed += 1,
+            Skipped => self.skipped += 1,
+            NotRun => self.not_run += 1,
+        }
+    }
+
+    /// True if every test passed or was skipped.
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0 && self.not_run == 0
+    }
+
+    pub fn tally(truns: &[TestRun]) -> Self {
+        let mut summary = Summary::default();
+        for trun in truns {
+            summary.add(trun.conclusion);
+        }
+        summary
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped, {} not run",
+            self.passed, self.failed, self.skipped, self.not_run
+        )
+    }
 }
 
 pub struct ReportMetaData {
     pub chip_name: String,
+    /// Chip size in bytes, as used by the tests.
+    pub rom_size: i64,
+    /// Voltage reported by flashrom, or None if it didn't report one.
+    pub chip_voltage: Option<String>,
     pub os_release: String,
     pub system_info: String,
     pub bios_info: String,
+    pub environment: utils::EnvironmentInfo,
+    /// Digest of the flash contents before any tests ran.
+    pub golden_digest: Digest,
 }
 
 fn decode_test_result(res: TestResult, con: TestConclusion) -> (TestConclusion, Option<TestError>) {
     use TestConclusion::*;
 
     match (res, con) {
+        (Err(e), _) if e.is::<Skip>() => (Skipped, Some(e)),
+        (res, Skipped) => (UnexpectedRun, res.err()),
         (Ok(_), Fail) => (UnexpectedPass, None),
         (Err(e), Pass) => (UnexpectedFail, Some(e)),
         _ => (Pass, None),
     }
 }
 
+/// Run each test in `env`, stopping early if `terminate_flag` is set or
+/// reporting the remaining tests as not run once `deadline` is near.
 pub fn run_all_tests<T, TS>(
-    chip: FlashChip,
-    cmd: &dyn Flashrom,
+    env: &mut TestEnv,
     ts: TS,
     terminate_flag: Option<&AtomicBool>,
-) -> Vec<(String, (TestConclusion, Option<TestError>))>
+    deadline: Option<Instant>,
+) -> Vec<TestRun>
 where
     T: TestCase + Copy,
     TS: IntoIterator<Item = T>,
 {
-    let mut env = TestEnv::create(chip, cmd).expect("Failed to set up test environment");
-
+    let not_run = |t: T| TestRun {
+        name: t.get_name().into(),
+        conclusion: TestConclusion::NotRun,
+        error: None,
+        modified_flash: false,
+        duration: Duration::from_secs(0),
+        passes: None,
+    };
+
+    // The longest test so far estimates how long the next one might take, so
+    // a test is only started if it is likely to finish within the budget.
+    let mut longest = Duration::from_secs(0);
     let mut results = Vec::new();
-    for t in ts {
+    let mut ts = ts.into_iter();
+    for t in ts.by_ref() {
         if terminate_flag
             .map(|b| b.load(Ordering::Acquire))
             .unwrap_or(false)
         {
             break;
         }
+        if let Some(deadline) = deadline {
+            if Instant::now() + longest > deadline {
+                warn!("Time budget nearly exhausted; not starting any more tests");
+                results.push(not_run(t));
+                results.extend(ts.map(not_run));
+                break;
+            }
+        }
 
-        let result = decode_test_result(env.run_test(t), t.expected_result());
-        results.push((t.get_name().into(), result));
+        let test_start = Instant::now();
+        let (conclusion, error) =
+            decode_test_result(env.run_test(t), t.expected_result(env.chip_type()));
+        results.push(TestRun {
+            name: t.get_name().into(),
+            conclusion,
+            error,
+            modified_flash: env.modified_flash(),
+            duration: env.test_duration(),
+            passes: None,
+        });
+        longest = longest.max(test_start.elapsed());
     }
     results
 }
 
+/// Combine the results of running the suite repeatedly into one result per
+/// test. A test passes only if it passed every time it ran; otherwise it takes
+/// the conclusion and error of its first run that didn't pass. The duration is
+/// that of its longest run.
+pub fn merge_iterations(iterations: Vec<Vec<TestRun>>) -> Vec<TestRun> {
+    let mut merged: Vec<TestRun> = Vec::new();
+    for run in iterations.into_iter().flatten() {
+        let ran = run.conclusion != TestConclusion::NotRun;
+        let passed = run.conclusion == TestConclusion::Pass;
+        let i = match merged.iter().position(|m| m.name == run.name) {
+            Some(i) => {
+                let m = &mut merged[i];
+                if m.conclusion == TestConclusion::NotRun
+                    || (m.conclusion == TestConclusion::Pass && ran && !passed)
+                {
+                    m.conclusion = run.conclusion;
+                    m.error = run.error;
+                }
+                m.modified_flash |= run.modified_flash;
+                m.duration = m.duration.max(run.duration);
+                i
+            }
+            None => {
+                merged.push(TestRun {
+                    passes: Some((0, 0)),
+                    ..run
+                });
+                merged.len() - 1
+            }
+        };
+        if let Some((passes, runs)) = &mut merged[i].passes {
+            *passes += passed as u32;
+            *runs += ran as u32;
+        }
+    }
+    merged
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OutputFormat {
     Pretty,
     Json,
+    /// JUnit XML, for CI systems.
+    Junit,
 }
 
 impl std::str::FromStr for OutputFormat {
@@ -530,95 +1264,287 @@ impl std::str::FromStr for OutputFormat {
             Ok(Pretty)
         } else if s.eq_ignore_ascii_case("json") {
             Ok(Json)
+        } else if s.eq_ignore_ascii_case("junit") {
+            Ok(Junit)
         } else {
             Err(())
         }
     }
 }
 
+/// Print the report in `format`, returning the tally of its conclusions.
+///
+/// If `copy` is given, the same report is also written there without colors.
 pub fn collate_all_test_runs(
-    truns: &[(String, (TestConclusion, Option<TestError>))],
-    meta_data: ReportMetaData,
+    truns: &[TestRun],
+    meta_data: &ReportMetaData,
     format: OutputFormat,
-) {
-    match format {
-        OutputFormat::Pretty => {
-            println!();
-            println!("  =============================");
-            println!("  =====  AVL qual RESULTS  ====");
-            println!("  =============================");
-            println!();
-            println!("  %---------------------------%");
-            println!("   os release: {}", meta_data.os_release);
-            println!("   chip name: {}", meta_data.chip_name);
-            println!("   system info: \n{}", meta_data.system_info);
-            println!("   bios info: \n{}", meta_data.bios_info);
-            println!("  %---------------------------%");
-            println!();
-
-            for trun in truns.iter() {
-                let (name, (result, error)) = trun;
-                if *result != TestConclusion::Pass {
-                    println!(
-                        " {} {}",
-                        style!(format!(" <+> {} test:", name), types::BOLD),
-                        style_dbg!(result, types::RED)
-                    );
-                    match error {
-                        None => {}
-                        Some(e) => info!(" - {} failure details:\n{}", name, e.to_string()),
-                    };
-                } else {
-                    println!(
-                        " {} {}",
-                        style!(format!(" <+> {} test:", name), types::BOLD),
-                        style_dbg!(result, types::GREEN)
-                    );
-                }
-            }
-            println!();
-        }
+    copy: Option<&mut dyn std::io::Write>,
+) -> std::io::Result<Summary> {
+    use std::fmt::Write as _;
+
+    let mut report = String::new();
+    let summary = match format {
+        OutputFormat::Pretty => write_pretty_report(&mut report, truns, meta_data),
         OutputFormat::Json => {
-            use serde_json::{Map, Value};
-
-            let mut all_pass = true;
-            let mut tests = Map::<String, Value>::new();
-            for (name, (result, error)) in truns {
-                let passed = *result == TestConclusion::Pass;
-                all_pass &= passed;
-
-                let error = match error {
-                    Some(e) => Value::String(format!("{:#?}", e)),
-                    None => Value::Null,
-                };
-
-                assert!(
-                    !tests.contains_key(name),
-                    "Found multiple tests named {:?}",
-                    name
-                );
-                tests.insert(
-                    name.into(),
-                    json!({
-                        "pass": passed,
-                        "error": error,
-                    }),
-                );
+            writeln!(report, "{:#}", json_report(truns, meta_data)).map(|_| Summary::tally(truns))
+        }
+        OutputFormat::Junit => {
+            write!(report, "{}", junit_report(truns, meta_data)).map(|_| Summary::tally(truns))
+        }
+    }
+    .expect("writing to a String cannot fail");
+
+    print!("{}", report);
+    if let Some(copy) = copy {
+        copy.write_all(types::strip_ansi(&report).as_bytes())?;
+        copy.flush()?;
+    }
+    Ok(summary)
+}
+
+fn write_pretty_report<W: std::fmt::Write>(
+    out: &mut W,
+    truns: &[TestRun],
+    meta_data: &ReportMetaData,
+) -> Result<Summary, std::fmt::Error> {
+    let mut summary = Summary::default();
+    writeln!(out)?;
+    writeln!(out, "  =============================")?;
+    writeln!(out, "  =====  AVL qual RESULTS  ====")?;
+    writeln!(out, "  =============================")?;
+    writeln!(out)?;
+    writeln!(out, "  %---------------------------%")?;
+    writeln!(out, "   os release: {}", meta_data.os_release)?;
+    writeln!(out, "   chip name: {}", meta_data.chip_name)?;
+    writeln!(out, "   chip size: {:#x} bytes", meta_data.rom_size)?;
+    writeln!(
+        out,
+        "   chip voltage: {}",
+        meta_data.chip_voltage.as_deref().unwrap_or("<unknown>")
+    )?;
+    writeln!(out, "   system info: \n{}", meta_data.system_info)?;
+    writeln!(out, "   bios info: \n{}", meta_data.bios_info)?;
+    writeln!(out, "   golden image: {}", meta_data.golden_digest)?;
+    writeln!(out, "   kernel: {}", meta_data.environment.kernel)?;
+    for (var, value) in &meta_data.environment.variables {
+        writeln!(out, "   {}={}", var, value)?;
+    }
+    writeln!(out, "  %---------------------------%")?;
+    writeln!(out)?;
+
+    for trun in truns.iter() {
+        summary.add(trun.conclusion);
+        let name = &trun.name;
+        let modified = if trun.modified_flash { "yes" } else { "no" };
+        let color = match trun.conclusion {
+            TestConclusion::Pass => types::GREEN,
+            TestConclusion::NotRun | TestConclusion::Skipped => types::YELLOW,
+            _ => types::RED,
+        };
+        let passes = match trun.passes {
+            Some((passes, runs)) => format!(", passed {}/{}", passes, runs),
+            None => String::new(),
+        };
+        writeln!(
+            out,
+            " {} {} (modified flash: {}, took {:.2}s{})",
+            style!(format!(" <+> {} test:", name), types::BOLD),
+            style_dbg!(trun.conclusion, color),
+            modified,
+            trun.duration.as_secs_f64(),
+            passes
+        )?;
+        match &trun.error {
+            Some(e) if trun.conclusion == TestConclusion::Skipped => {
+                info!(" - {} skipped: {}", name, e)
             }
+            Some(e) => info!(" - {} failure details:\n{}", name, e),
+            None => {}
+        }
+    }
+    writeln!(out)?;
+    let color = if summary.failed > 0 {
+        types::RED
+    } else {
+        types::GREEN
+    };
+    writeln!(out, "  {}", style!(format!("Summary: {}", summary), color))?;
+    writeln!(out)?;
+    Ok(summary)
+}
 
-            let json = json!({
-                "pass": all_pass,
-                "metadata": {
-                    "os_release": meta_data.os_release,
-                    "chip_name": meta_data.chip_name,
-                    "system_info": meta_data.system_info,
-                    "bios_info": meta_data.bios_info,
-                },
-                "tests": tests,
-            });
-            println!("{:#}", json);
+/// Build the machine-readable form of a test report.
+pub fn json_report(truns: &[TestRun], meta_data: &ReportMetaData) -> serde_json::Value {
+    use serde_json::{Map, Value};
+
+    let mut all_pass = true;
+    let mut tests = Map::<String, Value>::new();
+    for trun in truns {
+        let name = &trun.name;
+        let passed = trun.conclusion == TestConclusion::Pass;
+        let skipped = trun.conclusion == TestConclusion::Skipped;
+        all_pass &= passed || skipped;
+
+        let error = match &trun.error {
+            Some(e) => Value::String(format!("{:#?}", e)),
+            None => Value::Null,
+        };
+
+        assert!(
+            !tests.contains_key(name),
+            "Found multiple tests named {:?}",
+            name
+        );
+        let mut test = json!({
+            "pass": passed,
+            "conclusion": format!("{:?}", trun.conclusion),
+            "error": error,
+            "modified_flash": trun.modified_flash,
+            "not_run": trun.conclusion == TestConclusion::NotRun,
+            "skipped": skipped,
+            "duration_secs": trun.duration.as_secs_f64(),
+            "duration_ms": trun.duration.as_millis() as u64,
+        });
+        if let Some((passes, runs)) = trun.passes {
+            test["passes"] = passes.into();
+            test["runs"] = runs.into();
+        }
+        tests.insert(name.into(), test);
+    }
+
+    let summary = Summary::tally(truns);
+    json!({
+        "pass": all_pass,
+        "summary": {
+            "passed": summary.passed,
+            "failed": summary.failed,
+            "skipped": summary.skipped,
+            "not_run": summary.not_run,
+        },
+        "metadata": {
+            "os_release": meta_data.os_release,
+            "chip_name": meta_data.chip_name,
+            "rom_size": meta_data.rom_size,
+            "chip_voltage": meta_data.chip_voltage,
+            "system_info": meta_data.system_info,
+            "bios_info": meta_data.bios_info,
+            "golden_digest": {
+                "algorithm": meta_data.golden_digest.algorithm.name(),
+                "value": meta_data.golden_digest.hex(),
+            },
+            "environment": {
+                "kernel": meta_data.environment.kernel,
+                "variables": meta_data.environment.variables,
+            },
+        },
+        "tests": tests,
+    })
+}
+
+/// Escape text for use in XML character data or attribute values.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace are not allowed in XML.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
         }
     }
+    escaped
+}
+
+/// Render the report as a JUnit XML document with one testsuite, in which
+/// each test is a testcase.
+///
+/// Unexpected results are failures, and tests that were skipped or not run
+/// are skipped. Expected failures pass, as they do in the other formats.
+pub fn junit_report(truns: &[TestRun], meta_data: &ReportMetaData) -> String {
+    use std::fmt::Write as _;
+
+    let summary = Summary::tally(truns);
+    let total: Duration = truns.iter().map(|t| t.duration).sum();
+    let mut out = String::new();
+    let mut w = || -> std::fmt::Result {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<testsuite name="flashrom_tester" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            truns.len(),
+            summary.failed,
+            summary.skipped + summary.not_run,
+            total.as_secs_f64()
+        )?;
+        writeln!(out, "  <properties>")?;
+        for (name, value) in [
+            ("os_release", meta_data.os_release.clone()),
+            ("chip_name", meta_data.chip_name.clone()),
+            ("rom_size", format!("{:#x}", meta_data.rom_size)),
+            ("golden_digest", meta_data.golden_digest.to_string()),
+            ("kernel", meta_data.environment.kernel.clone()),
+        ] {
+            writeln!(
+                out,
+                r#"    <property name="{}" value="{}"/>"#,
+                name,
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "  </properties>")?;
+
+        for trun in truns {
+            write!(
+                out,
+                r#"  <testcase name="{}" classname="flashrom_tester" time="{:.3}""#,
+                xml_escape(&trun.name),
+                trun.duration.as_secs_f64()
+            )?;
+            let error = trun.error.as_ref().map(|e| xml_escape(&e.to_string()));
+            match trun.conclusion {
+                TestConclusion::Pass | TestConclusion::Fail => {
+                    writeln!(out, "/>")?;
+                    continue;
+                }
+                TestConclusion::UnexpectedPass => writeln!(
+                    out,
+                    r#"><failure message="Expected failure but test passed"/>"#
+                )?,
+                TestConclusion::UnexpectedFail => {
+                    let error = error.unwrap_or_default();
+                    let message = error.lines().next().unwrap_or_default();
+                    writeln!(out, ">")?;
+                    writeln!(
+                        out,
+                        r#"    <failure message="{}">{}</failure>"#,
+                        message, error
+                    )?;
+                }
+                TestConclusion::UnexpectedRun => writeln!(
+                    out,
+                    r#"><failure message="Expected to be skipped but test ran">{}</failure>"#,
+                    error.unwrap_or_default()
+                )?,
+                TestConclusion::NotRun => {
+                    writeln!(out, r#"><skipped message="Not run: the run ended early"/>"#)?
+                }
+                TestConclusion::Skipped => writeln!(
+                    out,
+                    r#"><skipped message="{}"/>"#,
+                    error.unwrap_or_default()
+                )?,
+            }
+            writeln!(out, "  </testcase>")?;
+        }
+        writeln!(out, "</testsuite>")
+    };
+    w().expect("writing to a String cannot fail");
+    out
 }
 
 #[cfg(test)]
@@ -643,6 +1569,245 @@ mod tests {
         let (result, err) = decode_test_result(Err("broken".into()), Fail);
         assert_eq!(result, Pass);
         assert!(err.is_none());
+
+        for expected in [Pass, Fail] {
+            let (result, err) =
+                decode_test_result(Err(super::Skip("not applicable".into()).into()), expected);
+            assert_eq!(result, Skipped);
+            assert_eq!(err.unwrap().to_string(), "not applicable");
+        }
+    }
+
+    #[test]
+    fn decode_expected_skip() {
+        use super::decode_test_result;
+        use super::TestConclusion::*;
+
+        let (result, err) =
+            decode_test_result(Err(super::Skip("not applicable".into()).into()), Skipped);
+        assert_eq!(result, Skipped);
+        assert_eq!(err.unwrap().to_string(), "not applicable");
+
+        // Running at all is a regression, whether it passed or failed.
+        let (result, err) = decode_test_result(Ok(()), Skipped);
+        assert_eq!(result, UnexpectedRun);
+        assert!(err.is_none());
+
+        let (result, err) = decode_test_result(Err("broken".into()), Skipped);
+        assert_eq!(result, UnexpectedRun);
+        assert_eq!(err.unwrap().to_string(), "broken");
+    }
+
+    #[test]
+    fn expect_skip_by_chip() {
+        use super::{ExpectSkip, TestCase, TestConclusion, TestEnv, TestResult};
+        use flashrom::FlashChip;
+
+        fn body(_: &mut TestEnv) -> TestResult {
+            Ok(())
+        }
+        let test = ExpectSkip(("Host_only", body), |fc| fc != FlashChip::HOST);
+        assert_eq!(test.expected_result(FlashChip::HOST), TestConclusion::Pass);
+        assert_eq!(test.expected_result(FlashChip::EC), TestConclusion::Skipped);
+        assert!(test.destructive());
+    }
+
+    #[test]
+    fn with_setup() {
+        use super::{with_setup, TestResult};
+        use std::cell::Cell;
+
+        let ran = Cell::new(false);
+        let torn_down = Cell::new(false);
+        let body = || -> TestResult {
+            ran.set(true);
+            Ok(())
+        };
+        let teardown = || -> TestResult {
+            torn_down.set(true);
+            Ok(())
+        };
+        let out = with_setup(|| Err("no servo".into()), body, teardown);
+        assert_eq!(out.unwrap_err().to_string(), "no servo");
+        assert!(!ran.get(), "body ran after setup failed");
+        assert!(torn_down.get(), "teardown skipped after setup failed");
+
+        assert!(with_setup(|| Ok(()), || Ok(()), || Ok(())).is_ok());
+        let out = with_setup(|| Ok(()), || Ok(()), || Err("teardown".into()));
+        assert_eq!(out.unwrap_err().to_string(), "teardown");
+        let out = with_setup(|| Ok(()), || Err("body".into()), || Err("teardown".into()));
+        assert_eq!(out.unwrap_err().to_string(), "body");
+    }
+
+    #[test]
+    fn json_report_includes_errors() {
+        use super::*;
+
+        let meta_data = ReportMetaData {
+            chip_name: "chip".into(),
+            rom_size: 0x1000,
+            chip_voltage: None,
+            os_release: "os".into(),
+            system_info: "system".into(),
+            bios_info: "bios".into(),
+            environment: utils::EnvironmentInfo::default(),
+            golden_digest: crate::digests::DigestAlgorithm::Sha256.digest_bytes(b""),
+        };
+        let truns = [
+            TestRun {
+                name: "Good".into(),
+                conclusion: TestConclusion::Pass,
+                error: None,
+                modified_flash: false,
+                duration: Duration::from_secs(1),
+                passes: None,
+            },
+            TestRun {
+                name: "Bad".into(),
+                conclusion: TestConclusion::UnexpectedFail,
+                error: Some("broken".into()),
+                modified_flash: true,
+                duration: Duration::from_secs(2),
+                passes: Some((2, 3)),
+            },
+        ];
+
+        let report = json_report(&truns, &meta_data);
+        assert_eq!(report["pass"], false);
+        assert_eq!(report["summary"]["passed"], 1);
+        assert_eq!(report["summary"]["failed"], 1);
+        assert_eq!(report["metadata"]["chip_name"], "chip");
+        assert_eq!(report["tests"]["Good"]["conclusion"], "Pass");
+        assert_eq!(report["tests"]["Good"]["duration_ms"], 1000);
+        assert!(report["tests"]["Good"]["error"].is_null());
+        assert!(report["tests"]["Good"].get("passes").is_none());
+        assert_eq!(report["tests"]["Bad"]["passes"], 2);
+        assert_eq!(report["tests"]["Bad"]["runs"], 3);
+        assert_eq!(report["tests"]["Bad"]["conclusion"], "UnexpectedFail");
+        assert!(report["tests"]["Bad"]["error"]
+            .as_str()
+            .unwrap()
+            .contains("broken"));
+    }
+
+    #[test]
+    fn junit_report() {
+        use super::*;
+
+        let meta_data = ReportMetaData {
+            chip_name: "W25Q64<FV>".into(),
+            rom_size: 0x1000,
+            chip_voltage: None,
+            os_release: "os".into(),
+            system_info: "system".into(),
+            bios_info: "bios".into(),
+            environment: utils::EnvironmentInfo::default(),
+            golden_digest: crate::digests::DigestAlgorithm::Sha256.digest_bytes(b""),
+        };
+        let run = |name: &str, conclusion, error: Option<&str>| TestRun {
+            name: name.into(),
+            conclusion,
+            error: error.map(Into::into),
+            modified_flash: false,
+            duration: Duration::from_millis(1500),
+            passes: None,
+        };
+        let truns = [
+            run("Good", TestConclusion::Pass, None),
+            run(
+                "Bad",
+                TestConclusion::UnexpectedFail,
+                Some("a < b & \"c\"\nmore"),
+            ),
+            run("Lucky", TestConclusion::UnexpectedPass, None),
+            run("Skip", TestConclusion::Skipped, Some("no FMAP")),
+            run("Late", TestConclusion::NotRun, None),
+        ];
+
+        let xml = junit_report(&truns, &meta_data);
+        let doc = roxmltree::Document::parse(&xml).expect("report is well-formed XML");
+        let cases: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("testcase"))
+            .map(|n| n.attribute("name").unwrap())
+            .collect();
+        assert_eq!(cases, ["Good", "Bad", "Lucky", "Skip", "Late"]);
+        let failure = doc
+            .descendants()
+            .find(|n| n.has_tag_name("failure"))
+            .unwrap();
+        assert_eq!(failure.text(), Some("a < b & \"c\"\nmore"));
+        assert!(xml.contains(
+            r#"<testsuite name="flashrom_tester" tests="5" failures="2" skipped="2" time="7.500">"#
+        ));
+        assert!(xml.contains(r#"<property name="chip_name" value="W25Q64&lt;FV&gt;"/>"#));
+        assert!(xml.contains(r#"<testcase name="Good" classname="flashrom_tester" time="1.500"/>"#));
+        assert!(xml.contains(
+            r#"<failure message="a &lt; b &amp; &quot;c&quot;">a &lt; b &amp; &quot;c&quot;
+more</failure>"#
+        ));
+        assert!(xml.contains(r#"<skipped message="no FMAP"/>"#));
+    }
+
+    #[test]
+    fn merge_iterations() {
+        use super::{merge_iterations, TestConclusion, TestRun};
+        use std::time::Duration;
+
+        let run = |name: &str, conclusion, secs| TestRun {
+            name: name.into(),
+            conclusion,
+            error: None,
+            modified_flash: false,
+            duration: Duration::from_secs(secs),
+            passes: None,
+        };
+        let merged = merge_iterations(vec![
+            vec![
+                run("Stable", TestConclusion::Pass, 1),
+                run("Flaky", TestConclusion::Pass, 1),
+            ],
+            vec![
+                run("Stable", TestConclusion::Pass, 3),
+                run("Flaky", TestConclusion::UnexpectedFail, 1),
+            ],
+            vec![
+                run("Stable", TestConclusion::Pass, 2),
+                run("Flaky", TestConclusion::NotRun, 0),
+            ],
+        ]);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].name, "Stable");
+        assert_eq!(merged[0].conclusion, TestConclusion::Pass);
+        assert_eq!(merged[0].passes, Some((3, 3)));
+        assert_eq!(merged[0].duration, Duration::from_secs(3));
+        assert_eq!(merged[1].name, "Flaky");
+        assert_eq!(merged[1].conclusion, TestConclusion::UnexpectedFail);
+        assert_eq!(merged[1].passes, Some((1, 2)));
+    }
+
+    #[test]
+    fn summary_tally() {
+        use super::{Summary, TestConclusion::*};
+
+        let mut summary = Summary::default();
+        for c in [Pass, Pass, UnexpectedFail, UnexpectedPass, Skipped, NotRun] {
+            summary.add(c);
+        }
+        assert_eq!(
+            summary,
+            Summary {
+                passed: 2,
+                failed: 2,
+                skipped: 1,
+                not_run: 1,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "2 passed, 2 failed, 1 skipped, 1 not run"
+        );
     }
 
     #[test]
@@ -651,5 +1816,6 @@ mod tests {
 
         assert_eq!(format!("{:?}", Pretty).parse::<OutputFormat>(), Ok(Pretty));
         assert_eq!(format!("{:?}", Json).parse::<OutputFormat>(), Ok(Json));
+        assert_eq!(format!("{:?}", Junit).parse::<OutputFormat>(), Ok(Junit));
     }
 }
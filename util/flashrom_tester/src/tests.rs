@@ -33,18 +33,64 @@
 // Software Foundation.
 //
 
+use super::compare;
 use super::cros_sysinfo;
-use super::tester::{self, OutputFormat, TestCase, TestEnv, TestResult};
+use super::digests::{Digest, DigestAlgorithm};
+use super::fmap;
+use super::golden::GoldenImage;
+use super::hw_wp::HwWpMode;
+use super::rand_util;
+use super::tester::{
+    self, ExpectSkip, OutputFormat, ReadOnly, Skip, TestCase, TestEnv, TestResult, TestRun,
+};
 use super::utils::{self, LayoutNames};
-use flashrom::{FlashChip, Flashrom};
+use flashrom::{ErrorKind, FlashChip, Flashrom};
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::fs::{self, File};
 use std::io::{BufRead, Write};
-use std::sync::atomic::AtomicBool;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-const LAYOUT_FILE: &'static str = "/tmp/layout.file";
-const ELOG_FILE: &'static str = "/tmp/elog.file";
+/// Chips on which host-specific tests must skip themselves.
+fn not_host(fc: FlashChip) -> bool {
+    fc != FlashChip::HOST
+}
+
+/// Chips on which tests of arbitrary protection ranges must skip themselves.
+fn is_ec(fc: FlashChip) -> bool {
+    fc == FlashChip::EC
+}
+
+/// Prepare for the whole run, once: without control of servo's write protect
+/// it is held off with dut-control throughout, and then the user's pre-run hook
+/// runs.
+fn begin_suite(use_dut_control: bool, pre_run: Option<&str>) -> TestResult {
+    if use_dut_control {
+        flashrom::dut_ctrl_toggle_wp(false)
+            .map_err(|e| format!("Failed to deassert servo write protect: {}", e))?;
+    }
+    if let Some(hook) = pre_run {
+        utils::run_hook(hook).map_err(|e| format!("Pre-run hook failed: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Undo `begin_suite` once the run is over. Failures are only logged, so the
+/// results of the run are still reported.
+fn end_suite(use_dut_control: bool, post_run: Option<&str>) {
+    if let Some(hook) = post_run {
+        if let Err(e) = utils::run_hook(hook) {
+            error!("Post-run hook failed: {}", e);
+        }
+    }
+    if use_dut_control {
+        if let Err(e) = flashrom::dut_ctrl_toggle_wp(true) {
+            error!("Failed to reassert servo write protect: {}", e);
+        }
+    }
+}
 
 /// Iterate over tests, yielding only those tests with names matching filter_names.
 ///
@@ -72,7 +118,254 @@ fn filter_tests<'n, 't: 'n, T: TestCase>(
     })
 }
 
-/// Run tests.
+/// Options controlling how the test suite is run and reported.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Print the layout file's contents before running tests.
+    pub print_layout: bool,
+    pub output_format: OutputFormat,
+    /// Write the golden image back to the chip when tests leave it modified.
+    ///
+    /// Disabling this leaves the chip in whatever state the tests left it.
+    pub restore: bool,
+    /// URL to POST the JSON report to after the suite completes.
+    pub post_results: Option<String>,
+    /// Extra HTTP headers ("Name: value") sent with the posted report.
+    pub post_headers: Vec<String>,
+    /// Chip size in bytes to use instead of the one flashrom reports, for
+    /// programmers that misreport it.
+    pub chip_size: Option<i64>,
+    /// Environment variables to record in the report, replacing
+    /// `utils::DEFAULT_REPORT_ENV` when set.
+    pub report_env: Option<Vec<String>>,
+    /// Stop starting new tests once the run is likely to exceed this long,
+    /// reporting the remainder as not run.
+    pub time_budget: Option<Duration>,
+    /// Algorithm for the digests in the report.
+    pub digest: DigestAlgorithm,
+    /// Run only the quick, non-destructive preflight checks.
+    pub preflight_only: bool,
+    /// Path to a flashrom layout file whose regions are verified individually.
+    pub layout_file: Option<String>,
+    /// Whether the operator may be asked to change hardware write protect.
+    pub manual_wp: utils::ManualWp,
+    /// How hardware write protect is changed, or None to choose by programmer.
+    pub hw_wp: Option<HwWpMode>,
+    /// Path to a JSON report from a previous run to compare the results with.
+    pub compare: Option<String>,
+    /// Only run tests whose names match this, in addition to any names given.
+    pub test_filter: Option<regex::Regex>,
+    /// Names of tests to report as skipped instead of running, applied after
+    /// the names and `test_filter` select which tests to run.
+    pub exclude_tests: Vec<String>,
+    /// Have the partial lock tests protect layout regions by name with
+    /// --wp-region, rather than by offset with --wp-range.
+    pub wp_by_region: bool,
+    /// Path to also write the report to, without colors.
+    pub report_file: Option<String>,
+    /// Digest the chip contents must have at the start of the run.
+    pub expected_digest: Option<Digest>,
+    /// Follow whole-image writes with a separate verify.
+    pub verify_after_write: bool,
+    /// Run the selected tests this many times, reporting how often each passed.
+    pub repeat: u32,
+    /// Directory to create the run's private directory of generated files,
+    /// such as the layout file, in instead of the system temporary directory.
+    pub work_dir: Option<PathBuf>,
+    /// The chip's erase block size, which layout section boundaries are
+    /// aligned to.
+    pub erase_block: Option<i64>,
+    /// Run the tests that modify the flash or its write protect configuration,
+    /// rather than reporting them as skipped.
+    pub allow_destructive: bool,
+    /// Seed for the random data written to the flash, chosen at random if None.
+    pub seed: Option<u64>,
+    /// Snapshot the Flash contents and write protect state before testing and
+    /// put both back afterwards, whatever the tests did.
+    pub restore_on_exit: bool,
+    /// Add kernel messages logged during failed destructive tests to their
+    /// errors.
+    pub capture_dmesg: bool,
+    /// Shell command run once before any test, aborting the run if it fails.
+    pub pre_run: Option<String>,
+    /// Shell command run once after all tests, even if the pre-run hook failed.
+    pub post_run: Option<String>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            print_layout: false,
+            output_format: OutputFormat::Pretty,
+            restore: true,
+            post_results: None,
+            post_headers: Vec::new(),
+            chip_size: None,
+            report_env: None,
+            time_budget: None,
+            digest: DigestAlgorithm::default(),
+            preflight_only: false,
+            layout_file: None,
+            manual_wp: utils::ManualWp::default(),
+            hw_wp: None,
+            compare: None,
+            test_filter: None,
+            exclude_tests: Vec::new(),
+            wp_by_region: false,
+            report_file: None,
+            expected_digest: None,
+            verify_after_write: false,
+            repeat: 1,
+            work_dir: None,
+            erase_block: None,
+            allow_destructive: false,
+            seed: None,
+            restore_on_exit: false,
+            capture_dmesg: false,
+            pre_run: None,
+            post_run: None,
+        }
+    }
+}
+
+/// The results of a completed run.
+pub struct RunOutcome {
+    pub runs: Vec<TestRun>,
+    pub summary: tester::Summary,
+}
+
+/// The tests `generic` chooses from, in the order they run.
+pub struct Registry<'a> {
+    /// Quick checks run on their own with --preflight-only.
+    pub preflight: Vec<Box<dyn TestCase + 'a>>,
+    /// The suite run when no tests are selected.
+    pub all: Vec<Box<dyn TestCase + 'a>>,
+    /// Tests covered by others in `all`, which only run when selected by name
+    /// or --test-filter.
+    pub by_name_only: Vec<Box<dyn TestCase + 'a>>,
+}
+
+/// Register every test, parameterized by `opts`. Nothing touches the Flash
+/// until a test is run.
+pub fn registered_tests(opts: &RunOptions) -> Registry<'_> {
+    let preflight: Vec<Box<dyn TestCase + '_>> = vec![
+        Box::new(ReadOnly(("Get_device_name", get_device_name_test))),
+        Box::new(ReadOnly(("Chip_size", chip_size_test))),
+        Box::new(ReadOnly(("Not_blank", not_blank_test))),
+    ];
+    let all: Vec<Box<dyn TestCase + '_>> = vec![
+        Box::new(ReadOnly(("Get_device_name", get_device_name_test))),
+        Box::new(ReadOnly((
+            "Unambiguous_detection",
+            unambiguous_detection_test,
+        ))),
+        Box::new(ReadOnly((
+            "Expected_digest",
+            expected_digest_test(&opts.expected_digest),
+        ))),
+        Box::new(ExpectSkip(
+            ReadOnly(("Coreboot_ELOG_sanity", elog_sanity_test)),
+            not_host,
+        )),
+        Box::new(ReadOnly(("Host_is_ChromeOS", host_is_chrome_test))),
+        Box::new(("Toggle_WP", wp_toggle_test)),
+        Box::new(("Manual_HW_WP", hw_wp_manual_test)),
+        Box::new(("Redundant_WP_disable", wp_redundant_disable_test)),
+        Box::new(("Name_under_WP", wp_name_test)),
+        Box::new(("Size_under_WP", wp_size_test)),
+        Box::new(("Erase_and_Write", erase_write_test)),
+        Box::new(("Oversize_write", oversize_write_test)),
+        Box::new(("Interrupted_erase", interrupted_erase_test)),
+        Box::new(ReadOnly(("Fail_to_verify", verify_fail_test))),
+        Box::new(ReadOnly(("Reinit_read_consistency", reinit_read_test))),
+        Box::new(ReadOnly(("Verify_FMAP_regions", fmap_regions_test))),
+        Box::new(ReadOnly(("Verify_layout_regions", layout_regions_test))),
+        Box::new(("Restore_idempotent", restore_idempotent_test)),
+        Box::new(("Snapshot_rollback", snapshot_rollback_test)),
+        Box::new(("Lock", lock_test)),
+        Box::new(("Reboot_WP_persistence", reboot_wp_persistence_test)),
+        Box::new(("Erase_under_WP", erase_under_wp_test)),
+        Box::new(("WP_granularity", wp_granularity_test)),
+        Box::new(ExpectSkip(
+            ("Lock_sections", lock_sections_test(opts.wp_by_region)),
+            is_ec,
+        )),
+        Box::new(ExpectSkip(("IFD_ME_locked", ifd_me_locked_test), not_host)),
+    ];
+    let lock_each_section: Vec<Box<dyn TestCase + '_>> = vec![
+        Box::new(ExpectSkip(
+            (
+                "Lock_top_quad",
+                partial_lock_test(LayoutNames::TopQuad, opts.wp_by_region),
+            ),
+            is_ec,
+        )),
+        Box::new(ExpectSkip(
+            (
+                "Lock_bottom_quad",
+                partial_lock_test(LayoutNames::BottomQuad, opts.wp_by_region),
+            ),
+            is_ec,
+        )),
+        Box::new(ExpectSkip(
+            (
+                "Lock_bottom_half",
+                partial_lock_test(LayoutNames::BottomHalf, opts.wp_by_region),
+            ),
+            is_ec,
+        )),
+        Box::new(ExpectSkip(
+            (
+                "Lock_top_half",
+                partial_lock_test(LayoutNames::TopHalf, opts.wp_by_region),
+            ),
+            is_ec,
+        )),
+    ];
+
+    Registry {
+        preflight,
+        all,
+        by_name_only: lock_each_section,
+    }
+}
+
+/// Write the name of every registered test, one per line, with whether it
+/// is destructive and which chips it is expected to skip on.
+pub fn list_tests(out: &mut dyn Write) -> std::io::Result<()> {
+    let opts = RunOptions::default();
+    let registry = registered_tests(&opts);
+    let groups = [
+        (&registry.all, None),
+        (&registry.by_name_only, Some("only when named")),
+        (&registry.preflight, Some("preflight only")),
+    ];
+
+    let mut seen = HashSet::new();
+    for (tests, note) in groups.iter() {
+        for t in tests.iter().filter(|t| seen.insert(t.get_name())) {
+            let mut notes: Vec<String> = note.iter().map(|n| n.to_string()).collect();
+            let skipped_on: Vec<&str> = FlashChip::ALL
+                .iter()
+                .filter(|&&fc| t.expected_result(fc) == tester::TestConclusion::Skipped)
+                .map(|&fc| FlashChip::to_cli(fc))
+                .collect();
+            if !skipped_on.is_empty() {
+                notes.push(format!("skipped on {}", skipped_on.join(", ")));
+            }
+            let kind = if t.destructive() {
+                "destructive"
+            } else {
+                "read-only"
+            };
+            let line = format!("{:<24} {:<11} {}", t.get_name(), kind, notes.join("; "));
+            writeln!(out, "{}", line.trim_end())?;
+        }
+    }
+    Ok(())
+}
+
+/// Run tests, returning the result of each.
 ///
 /// Only returns an Error if there was an internal error; test failures are Ok.
 ///
@@ -82,53 +375,83 @@ fn filter_tests<'n, 't: 'n, T: TestCase>(
 pub fn generic<'a, TN: Iterator<Item = &'a str>>(
     cmd: &dyn Flashrom,
     fc: FlashChip,
-    print_layout: bool,
-    output_format: OutputFormat,
+    opts: &RunOptions,
     test_names: Option<TN>,
     terminate_flag: Option<&AtomicBool>,
     crossystem: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    utils::ac_power_warning();
+) -> Result<RunOutcome, Box<dyn std::error::Error>> {
+    let deadline = opts.time_budget.map(|budget| Instant::now() + budget);
 
-    info!("Calculate ROM partition sizes & Create the layout file.");
-    let rom_sz: i64 = cmd.get_size()?;
-    let layout_sizes = utils::get_layout_sizes(rom_sz)?;
-    {
-        let mut f = File::create(LAYOUT_FILE)?;
-        let mut buf: Vec<u8> = vec![];
-        utils::construct_layout_file(&mut buf, &layout_sizes)?;
+    // Create the report file now, so a bad path doesn't waste a whole run.
+    let mut report_file = match &opts.report_file {
+        Some(path) => Some(
+            File::create(path)
+                .map_err(|e| format!("Failed to create report file {}: {}", path, e))?,
+        ),
+        None => None,
+    };
 
-        f.write_all(&buf)?;
-        if print_layout {
-            info!(
-                "Dumping layout file as requested:\n{}",
-                String::from_utf8_lossy(&buf)
+    // Load the previous report first, so a bad path doesn't waste a whole run.
+    let previous_report: Option<serde_json::Value> = match &opts.compare {
+        Some(path) => {
+            let file = File::open(path)
+                .map_err(|e| format!("Failed to open previous report {}: {}", path, e))?;
+            let report = serde_json::from_reader(std::io::BufReader::new(file))
+                .map_err(|e| format!("Failed to parse previous report {}: {}", path, e))?;
+            Some(report)
+        }
+        None => None,
+    };
+
+    info!("Calculate ROM partition sizes.");
+    let rom_sz: i64 = match opts.chip_size {
+        Some(sz) => {
+            warn!(
+                "Chip size override in effect: using {:#x} bytes, not the size flashrom reports",
+                sz
             );
+            sz
+        }
+        None => cmd.get_size()?,
+    };
+    let layout_sizes = utils::get_layout_sizes(rom_sz, opts.erase_block)?;
+    let user_layout = match &opts.layout_file {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read layout file {}: {}", path, e))?;
+            let regions = utils::parse_layout(&text)?;
+            utils::check_layout(&regions, rom_sz)?;
+            regions
         }
+        None => Vec::new(),
+    };
+    if opts.print_layout {
+        let mut buf: Vec<u8> = vec![];
+        utils::construct_layout_file(&mut buf, &layout_sizes)?;
+        info!(
+            "Dumping layout file as requested:\n{}",
+            String::from_utf8_lossy(&buf)
+        );
     }
 
     info!("Record crossystem information.\n{}", crossystem);
 
-    // Register tests to run:
-    let tests: &[&dyn TestCase] = &[
-        &("Get_device_name", get_device_name_test),
-        &("Coreboot_ELOG_sanity", elog_sanity_test),
-        &("Host_is_ChromeOS", host_is_chrome_test),
-        &("Toggle_WP", wp_toggle_test),
-        &("Erase_and_Write", erase_write_test),
-        &("Fail_to_verify", verify_fail_test),
-        &("Lock", lock_test),
-        &("Lock_top_quad", partial_lock_test(LayoutNames::TopQuad)),
-        &(
-            "Lock_bottom_quad",
-            partial_lock_test(LayoutNames::BottomQuad),
-        ),
-        &(
-            "Lock_bottom_half",
-            partial_lock_test(LayoutNames::BottomHalf),
-        ),
-        &("Lock_top_half", partial_lock_test(LayoutNames::TopHalf)),
-    ];
+    let registry = registered_tests(opts);
+    let preflight: Vec<&dyn TestCase> = registry.preflight.iter().map(|t| t.as_ref()).collect();
+    let all: Vec<&dyn TestCase> = registry.all.iter().map(|t| t.as_ref()).collect();
+    let all_and_sections: Vec<&dyn TestCase> = all
+        .iter()
+        .copied()
+        .chain(registry.by_name_only.iter().map(|t| t.as_ref()))
+        .collect();
+    let tests = if opts.preflight_only {
+        info!("Running preflight checks only");
+        &preflight
+    } else if test_names.is_some() || opts.test_filter.is_some() {
+        &all_and_sections
+    } else {
+        &all
+    };
 
     // Limit the tests to only those requested, unless none are requested
     // in which case all tests are included.
@@ -137,20 +460,158 @@ pub fn generic<'a, TN: Iterator<Item = &'a str>>(
     } else {
         None
     };
-    let tests = filter_tests(tests, &mut filter_names);
+    let tests: Vec<_> = filter_tests(tests, &mut filter_names)
+        .filter(|t| match &opts.test_filter {
+            Some(re) => re.is_match(t.get_name()),
+            None => true,
+        })
+        .collect();
+
+    // Any leftover filtered names were specified to be run but don't exist
+    for leftover in filter_names.iter().flatten() {
+        warn!("No test matches filter name \"{}\"", leftover);
+    }
+    if tests.is_empty() {
+        return Err("No tests match the given test names and --test-filter".into());
+    }
+
+    let is_excluded = |name: &str| {
+        opts.exclude_tests
+            .iter()
+            .any(|excluded| excluded.eq_ignore_ascii_case(name))
+    };
+    let skip_reason = |t: &dyn TestCase| {
+        if is_excluded(t.get_name()) {
+            Some("excluded by user")
+        } else if t.destructive() && !opts.allow_destructive {
+            Some("destructive, not enabled")
+        } else {
+            None
+        }
+    };
+    for excluded in &opts.exclude_tests {
+        if !tests
+            .iter()
+            .any(|t| t.get_name().eq_ignore_ascii_case(excluded))
+        {
+            warn!("No selected test matches excluded name \"{}\"", excluded);
+        }
+    }
+    let to_run: Vec<_> = tests
+        .iter()
+        .copied()
+        .filter(|t| skip_reason(t).is_none())
+        .collect();
+    if !opts.allow_destructive && tests.iter().any(|t| t.destructive()) {
+        warn!("Skipping tests that modify the flash; pass --i-understand-this-is-destructive to run them");
+    }
 
     let chip_name = cmd
         .name()
         .map(|x| format!("vendor=\"{}\" name=\"{}\"", x.0, x.1))
         .unwrap_or("<Unknown chip>".into());
+    let chip_voltage = match cmd.voltage() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            info!("Chip voltage unknown: {}", e);
+            None
+        }
+    };
+
+    let seed = opts.seed.unwrap_or_else(rand_util::random_seed);
+    info!(
+        "Random data seed: {} (pass --seed {} to reproduce)",
+        seed, seed
+    );
 
     // ------------------------.
     // Run all the tests and collate the findings:
-    let results = tester::run_all_tests(fc, cmd, tests, terminate_flag);
+    let mut env = TestEnv::create(
+        fc,
+        cmd,
+        rom_sz,
+        user_layout,
+        opts.restore,
+        opts.hw_wp
+            .unwrap_or_else(|| HwWpMode::default_for(cmd))
+            .controller(fc, opts.manual_wp),
+        opts.verify_after_write,
+        opts.work_dir.as_deref(),
+        opts.erase_block,
+        seed,
+    )
+    .map_err(|e| format!("Failed to set up test environment: {}", e))?;
+    if opts.capture_dmesg {
+        env.capture_dmesg();
+    }
+    if opts.restore_on_exit {
+        env.restore_on_exit()
+            .map_err(|e| format!("Failed to save state to restore on exit: {}", e))?;
+    }
+    let golden_digest = opts.digest.digest_bytes(env.golden().as_bytes());
+    info!("Golden image digest: {}", golden_digest);
+    let repeat = opts.repeat.max(1);
+    let mut iterations = Vec::new();
+    let use_dut_control = env.needs_dut_control();
+    tester::with_setup(
+        || begin_suite(use_dut_control, opts.pre_run.as_deref()),
+        || {
+            for i in 1..=repeat {
+                if repeat > 1 {
+                    info!("Starting iteration {} of {}", i, repeat);
+                }
+                iterations.push(tester::run_all_tests(
+                    &mut env,
+                    to_run.iter().copied(),
+                    terminate_flag,
+                    deadline,
+                ));
+                if terminate_flag.is_some_and(|b| b.load(Ordering::Acquire)) {
+                    break;
+                }
+            }
+            Ok(())
+        },
+        || {
+            end_suite(use_dut_control, opts.post_run.as_deref());
+            Ok(())
+        },
+    )
+    .map_err(|e| format!("Suite setup failed: {}", e))?;
+    let mut ran = if repeat > 1 {
+        tester::merge_iterations(iterations)
+    } else {
+        iterations.pop().unwrap_or_default()
+    }
+    .into_iter();
+    // Restore the chip before reporting.
+    env.finish();
 
-    // Any leftover filtered names were specified to be run but don't exist
-    for leftover in filter_names.iter().flatten() {
-        warn!("No test matches filter name \"{}\"", leftover);
+    // Report excluded tests in their place among those that ran, and any the
+    // run never reached because it ended early as not run.
+    let mut results = Vec::new();
+    for t in &tests {
+        if let Some(reason) = skip_reason(t) {
+            results.push(TestRun {
+                name: t.get_name().into(),
+                conclusion: tester::TestConclusion::Skipped,
+                error: Some(Skip(reason.into()).into()),
+                modified_flash: false,
+                duration: Duration::from_secs(0),
+                passes: None,
+            });
+        } else if let Some(run) = ran.next() {
+            results.push(run);
+        } else {
+            results.push(TestRun {
+                name: t.get_name().into(),
+                conclusion: tester::TestConclusion::NotRun,
+                error: None,
+                modified_flash: false,
+                duration: Duration::from_secs(0),
+                passes: None,
+            });
+        }
     }
 
     let os_rel = sys_info::os_release().unwrap_or("<Unknown OS>".to_string());
@@ -159,12 +620,49 @@ pub fn generic<'a, TN: Iterator<Item = &'a str>>(
 
     let meta_data = tester::ReportMetaData {
         chip_name: chip_name,
+        rom_size: rom_sz,
+        chip_voltage,
         os_release: os_rel,
         system_info: system_info,
         bios_info: bios_info,
+        environment: match &opts.report_env {
+            Some(vars) => utils::collect_environment(vars),
+            None => utils::collect_environment(utils::DEFAULT_REPORT_ENV),
+        },
+        golden_digest,
     };
-    tester::collate_all_test_runs(&results, meta_data, output_format);
-    Ok(())
+    let summary = tester::collate_all_test_runs(
+        &results,
+        &meta_data,
+        opts.output_format,
+        report_file.as_mut().map(|f| f as &mut dyn std::io::Write),
+    )
+    .map_err(|e| format!("Failed to write report file: {}", e))?;
+    // Close the report file before anything else can fail.
+    drop(report_file);
+
+    if let Some(previous) = &previous_report {
+        let current = tester::json_report(&results, &meta_data);
+        let changes = compare::compare_reports(previous, &current)
+            .map_err(|e| format!("Failed to compare with previous report: {}", e))?;
+        compare::print_changes(&changes, opts.output_format);
+    }
+
+    if let Some(url) = &opts.post_results {
+        let report = tester::json_report(&results, &meta_data);
+        info!("Posting test report to {}", url);
+        if let Err(e) = utils::post_json(url, &opts.post_headers, &report) {
+            error!("Failed to post test report to {}: {}", url, e);
+            if opts.output_format != OutputFormat::Json {
+                info!("Falling back to printing the JSON report locally");
+                println!("{:#}", report);
+            }
+        }
+    }
+    Ok(RunOutcome {
+        runs: results,
+        summary,
+    })
 }
 
 fn get_device_name_test(env: &mut TestEnv) -> TestResult {
@@ -173,6 +671,53 @@ fn get_device_name_test(env: &mut TestEnv) -> TestResult {
     Ok(())
 }
 
+/// Exactly one chip definition must match, or every operation needs the chip
+/// named explicitly.
+fn unambiguous_detection_test(env: &mut TestEnv) -> TestResult {
+    match env.cmd.probe_chips()?.as_slice() {
+        [_] => Ok(()),
+        [] => Err("flashrom didn't detect a chip".into()),
+        chips => Err(format!(
+            "Chip detection is ambiguous; candidates are {}",
+            chips.join(", ")
+        )
+        .into()),
+    }
+}
+
+/// The reported chip size must be usable and match what is actually read.
+fn chip_size_test(env: &mut TestEnv) -> TestResult {
+    let reported = env.cmd.get_size()?;
+    if reported != env.rom_size() {
+        warn!(
+            "Flashrom reports a chip size of {:#x} but {:#x} is being used",
+            reported,
+            env.rom_size()
+        );
+    }
+    utils::get_layout_sizes(reported, None)?;
+
+    let read_len = env.golden().as_bytes().len() as i64;
+    if read_len != env.rom_size() {
+        return Err(format!(
+            "Read {:#x} bytes from a chip of {:#x} bytes",
+            read_len,
+            env.rom_size()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A chip that reads back entirely erased is blank or, more often, not
+/// actually connected to the programmer.
+fn not_blank_test(env: &mut TestEnv) -> TestResult {
+    if env.golden().as_bytes().iter().all(|&b| b == 0xFF) {
+        return Err("Flash reads as all 0xFF; is the chip blank or disconnected?".into());
+    }
+    Ok(())
+}
+
 fn wp_toggle_test(env: &mut TestEnv) -> TestResult {
     // NOTE: This is not strictly a 'test' as it is allowed to fail on some platforms.
     //       However, we will warn when it does fail.
@@ -187,6 +732,116 @@ fn wp_toggle_test(env: &mut TestEnv) -> TestResult {
     Ok(())
 }
 
+/// Disabling hardware write protect without servo needs physical action, so
+/// it must either be done by the operator when prompted or be reported as
+/// impossible, never claimed without actually happening.
+fn hw_wp_manual_test(env: &mut TestEnv) -> TestResult {
+    let policy = match env.wp.hw_wp().manual_policy() {
+        Some(policy) => policy,
+        None => return Err(Skip("hardware WP isn't changed by hand".into()).into()),
+    };
+
+    let was_enabled = env.wp.hw_wp().get()?;
+    let refused = policy == utils::ManualWp::Refuse;
+    let mut wp = env.wp.push();
+    match wp.set_hw(false).map(|_| ()) {
+        Ok(()) if wp.hw_wp().get()? => {
+            Err("Disabling hardware WP reported success but it is still enabled".into())
+        }
+        Ok(()) if was_enabled && refused => {
+            Err("Hardware WP was disabled without the operator despite refusing manual WP".into())
+        }
+        Ok(()) => Ok(()),
+        Err(e) if was_enabled && refused => {
+            info!(
+                "Disabling hardware WP correctly reported as impossible: {}",
+                e
+            );
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Disabling write protect when it is already disabled must be a clean no-op.
+fn wp_redundant_disable_test(env: &mut TestEnv) -> TestResult {
+    env.wp.set_hw(false)?.set_sw(false)?;
+
+    // Bypass env.wp, which skips toggles that would not change its idea of the
+    // state, so flashrom really is asked to disable it again.
+    for attempt in 1..=2 {
+        env.cmd.wp_toggle(false).map_err(|e| {
+            format!(
+                "Disabling already-disabled write protect failed (attempt {}): {}",
+                attempt, e
+            )
+        })?;
+    }
+
+    if !env.cmd.wp_status(false)? {
+        return Err("Write protect is not disabled after disabling it twice".into());
+    }
+    Ok(())
+}
+
+fn wp_name_test(env: &mut TestEnv) -> TestResult {
+    env.wp.set_hw(false)?.set_sw(false)?;
+    let unprotected = env.cmd.name()?;
+
+    // Probing must not depend on write protect; the pushed state turns it back
+    // off when dropped.
+    let mut wp = env.wp.push();
+    wp.set_sw(true)?.set_hw(true)?;
+    let protected = env.cmd.name().map_err(|e| {
+        format!(
+            "Failed to probe chip name with write protect enabled: {}",
+            e
+        )
+    })?;
+
+    if protected != unprotected {
+        return Err(format!(
+            "Chip probed as {:?} with write protect enabled but {:?} without",
+            protected, unprotected
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The chip size must not change with write protect, and must match the
+/// number of bytes actually read back.
+///
+/// The size is probed afresh each time, since `get_size` may be cached.
+fn wp_size_test(env: &mut TestEnv) -> TestResult {
+    env.wp.set_hw(false)?.set_sw(false)?;
+    let unprotected = env.cmd.probe_size()?;
+
+    // The pushed state turns write protect back off when dropped.
+    let mut wp = env.wp.push();
+    wp.set_sw(true)?.set_hw(true)?;
+    let protected = env.cmd.probe_size()?;
+    let readback = env.scratch_file("wp_size_readback.bin");
+    env.cmd.read_into_file(&readback)?;
+    let read_len = fs::metadata(&readback)?.len() as i64;
+
+    if protected != unprotected {
+        return Err(format!(
+            "Chip size is {:#x} with write protect enabled but {:#x} without",
+            protected, unprotected
+        )
+        .into());
+    }
+    if read_len != protected {
+        return Err(format!(
+            "Read {:#x} bytes with write protect enabled but chip size is {:#x}",
+            read_len, protected
+        )
+        .into());
+    }
+    Ok(())
+}
+
 fn erase_write_test(env: &mut TestEnv) -> TestResult {
     if !env.is_golden() {
         info!("Memory has been modified; reflashing to ensure erasure can be detected");
@@ -195,12 +850,30 @@ fn erase_write_test(env: &mut TestEnv) -> TestResult {
 
     // With write protect enabled erase should fail.
     env.wp.set_sw(true)?.set_hw(true)?;
-    if env.erase().is_ok() {
-        info!("Flashrom returned Ok but this may be incorrect; verifying");
-        if !env.is_golden() {
-            return Err("Hardware write protect asserted however can still erase!".into());
+    match env.erase() {
+        Ok(()) => {
+            info!("Flashrom returned Ok but this may be incorrect; verifying");
+            if !env.is_golden() {
+                return Err("Hardware write protect asserted however can still erase!".into());
+            }
+            info!("Erase claimed to succeed but verify is Ok; assume erase failed");
+        }
+        Err(e) if e.kind() == &ErrorKind::WriteProtected => {
+            info!("Erase refused due to write protect: {}", e);
+        }
+        // The cause of an unclassified failure isn't known, so check whether
+        // anything was erased.
+        Err(e) if matches!(e.kind(), ErrorKind::NonZeroExit { .. } | ErrorKind::Other) => {
+            info!("Erase failed ({}); verifying memory is unchanged", e);
+            if !env.is_golden() {
+                return Err("Hardware write protect asserted however can still erase!".into());
+            }
+        }
+        Err(e) => {
+            return Err(
+                format!("Erase failed for a reason other than write protect: {}", e).into(),
+            );
         }
-        info!("Erase claimed to succeed but verify is Ok; assume erase failed");
     }
 
     // With write protect disabled erase should succeed.
@@ -210,15 +883,122 @@ fn erase_write_test(env: &mut TestEnv) -> TestResult {
         return Err("Successful erase didn't modify memory".into());
     }
 
+    // Writing the erased chip must bring the image back.
+    env.ensure_golden()?;
+    if !env.is_golden() {
+        return Err("Writing the golden image after erase didn't restore it".into());
+    }
+    Ok(())
+}
+
+fn interrupted_erase_test(env: &mut TestEnv) -> TestResult {
+    // Start from a known image so the intact region can be recognized.
+    env.ensure_golden()?;
+    let result = check_interrupted_erase(env);
+    info!("Restoring golden image after interrupted erase");
+    env.restore_golden()?;
+    result
+}
+
+/// Model an erase that lost power partway through by erasing only the bottom
+/// half of the chip, then check that the damage is detected and confined to
+/// exactly that range.
+fn check_interrupted_erase(env: &mut TestEnv) -> TestResult {
+    let rom_sz = env.rom_size() as usize;
+    let (section_name, _, erase_len) = utils::layout_section(env.layout(), LayoutNames::BottomHalf);
+    let erase_len = erase_len as usize;
+
+    // Writing an erased image to the region makes flashrom erase its blocks
+    // and skip programming them, leaving the rest of the chip untouched.
+    let erased_file = env.scratch_file("erased_content.bin");
+    fs::write(&erased_file, vec![0xFF; rom_sz])?;
+    let rws = flashrom::ROMWriteSpecifics {
+        layout_file: Some(env.layout_file()),
+        write_file: Some(&erased_file),
+        name_file: Some(section_name),
+    };
+    env.cmd.write_file_with_layout(&rws)?;
+
+    if env.is_golden() {
+        return Err("Full-chip verify against golden passed after a partial erase".into());
+    }
+
+    let contents = env
+        .cmd
+        .read_to_vec(&env.scratch_file("readback_content.bin"))?;
+    let boundary = find_erase_boundary(env.golden().as_bytes(), &contents)?;
+    info!(
+        "Partial erase boundary: erased 0x0..{:#x}, intact {:#x}..{:#x}",
+        boundary,
+        boundary,
+        contents.len()
+    );
+
+    if boundary > erase_len {
+        return Err(format!(
+            "Erase of {} extended to {:#x}, past the region end at {:#x}",
+            section_name, boundary, erase_len
+        )
+        .into());
+    }
+    if let Some(offset) = contents[..erase_len].iter().position(|&b| b != 0xFF) {
+        return Err(format!(
+            "Erase of {} stopped short: byte at {:#x} is not erased",
+            section_name, offset
+        )
+        .into());
+    }
     Ok(())
 }
 
+/// Locate the end of an erase that stopped partway through the chip.
+///
+/// Returns the offset just past the last byte of `contents` that differs from
+/// `golden`; every byte below it must read as erased (0xFF). Bytes that were
+/// already 0xFF in the golden image cannot be told apart from erased ones, so
+/// the result is the lowest boundary consistent with the data.
+fn find_erase_boundary(golden: &[u8], contents: &[u8]) -> Result<usize, String> {
+    if golden.len() != contents.len() {
+        return Err(format!(
+            "Read back {} bytes but golden image is {} bytes",
+            contents.len(),
+            golden.len()
+        ));
+    }
+
+    let boundary = match golden
+        .iter()
+        .zip(contents)
+        .rposition(|(expected, actual)| expected != actual)
+    {
+        Some(last_diff) => last_diff + 1,
+        None => 0,
+    };
+    match contents[..boundary].iter().position(|&b| b != 0xFF) {
+        None => Ok(boundary),
+        Some(offset) => Err(format!(
+            "Byte at {:#x} was modified but not erased (read {:#04x})",
+            offset, contents[offset]
+        )),
+    }
+}
+
 fn lock_test(env: &mut TestEnv) -> TestResult {
+    // Software WP covers a range, which chips that can't list them can't set.
+    env.wp_ranges()?;
     if !env.wp.can_control_hw_wp() {
         return Err("Lock test requires ability to control hardware write protect".into());
     }
 
     env.wp.set_hw(false)?.set_sw(true)?;
+    let state = env.cmd.wp_get_state()?;
+    if !state.enabled || state.range != (0, env.rom_size()) {
+        return Err(format!(
+            "Enabling software WP should protect the whole chip, but status is {:?}",
+            state
+        )
+        .into());
+    }
     // Toggling software WP off should work when hardware is off.
     // Then enable again for another go.
     env.wp.push().set_sw(false)?;
@@ -231,27 +1011,198 @@ fn lock_test(env: &mut TestEnv) -> TestResult {
     Ok(())
 }
 
+/// Software write protect lives in the chip's status register, so it must still
+/// be reported after the programmer is reset and the chip probed afresh.
+fn reboot_wp_persistence_test(env: &mut TestEnv) -> TestResult {
+    let fc = env.chip_type();
+    let mut wp = env.wp.push();
+    wp.set_hw(false)?.set_sw(true)?;
+    if !utils::reset_programmer(fc)? {
+        return Err(Skip(format!("the {:?} programmer can't be reset", fc)).into());
+    }
+    if !env.cmd.wp_status(true)? {
+        return Err("Software WP was lost when the programmer was reset".into());
+    }
+    Ok(())
+}
+
+fn erase_under_wp_test(env: &mut TestEnv) -> TestResult {
+    env.ensure_golden()?;
+    let result = check_erase_under_wp(env);
+    env.restore_golden()?;
+    result
+}
+
+/// Attempt to erase the whole chip while it is write protected, which must
+/// fail without disturbing the write protect configuration.
+fn check_erase_under_wp(env: &mut TestEnv) -> TestResult {
+    let mut wp = env.wp.push();
+    wp.set_sw(true)?.set_hw(true)?;
+    let before = env.cmd.wp_get_state()?;
+    if !before.enabled {
+        return Err(format!(
+            "Write protect does not report enabled after enabling it, status is {:?}",
+            before
+        )
+        .into());
+    }
+
+    if env.cmd.erase().is_ok() {
+        return Err("Erase succeeded despite write protect being enabled".into());
+    }
+
+    let after = env.cmd.wp_get_state()?;
+    if after != before {
+        return Err(format!(
+            "Write protect status changed by failed erase: {:?} before, {:?} after",
+            before, after
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn wp_granularity_test(env: &mut TestEnv) -> TestResult {
+    env.ensure_golden()?;
+    let result = check_wp_granularity(env);
+
+    // Range protection isn't tracked by env.wp, so clear it explicitly.
+    env.wp.set_hw(false)?;
+    env.cmd.wp_toggle(/* en= */ false)?;
+    env.restore_golden()?;
+    result
+}
+
+/// Protect the smallest range advertised by --wp-list and check that the
+/// protection actually ends where advertised, by writing single bytes just
+/// inside and just outside of it.
+fn check_wp_granularity(env: &mut TestEnv) -> TestResult {
+    let rom_sz = env.rom_size();
+    let ranges = env.wp_ranges()?;
+    let (start, len) = match ranges
+        .into_iter()
+        .filter(|&(_, len)| len > 0 && len < rom_sz)
+        .min_by_key(|&(_, len)| len)
+    {
+        Some(r) => r,
+        None => return Err("wp-list advertised no partial protection ranges".into()),
+    };
+    info!(
+        "Smallest advertised protection range: {:#x}+{:#x}",
+        start, len
+    );
+
+    // Probe the edge of the range that borders unprotected memory.
+    let (inside, outside) = if start == 0 {
+        (start + len - 1, start + len)
+    } else {
+        (start, start - 1)
+    };
+
+    let mut probe = env.golden().as_bytes().to_vec();
+    probe[inside as usize] = !probe[inside as usize];
+    probe[outside as usize] = !probe[outside as usize];
+    let probe_file = env.scratch_file("wp_probe_content.bin");
+    let probe_layout_file = env.scratch_file("wp_probe_layout.file");
+    fs::write(&probe_file, &probe)?;
+    fs::write(
+        &probe_layout_file,
+        format!(
+            "{:x}:{:x} INSIDE\n{:x}:{:x} OUTSIDE\n",
+            inside, inside, outside, outside
+        ),
+    )?;
+
+    env.wp.set_hw(false)?.set_sw(false)?;
+    env.cmd.wp_range((start, len), true)?;
+    env.wp.set_hw(true)?;
+    check_protected_range(env, (start, len))?;
+
+    for region in &["INSIDE", "OUTSIDE"] {
+        let rws = flashrom::ROMWriteSpecifics {
+            layout_file: Some(&probe_layout_file),
+            write_file: Some(&probe_file),
+            name_file: Some(region),
+        };
+        if let Err(e) = env.cmd.write_file_with_layout(&rws) {
+            info!("Write to {} probe failed: {}", region, e);
+        }
+    }
+
+    let contents = env
+        .cmd
+        .read_to_vec(&env.scratch_file("readback_content.bin"))?;
+    let inside_written = contents[inside as usize] == probe[inside as usize];
+    let outside_written = contents[outside as usize] == probe[outside as usize];
+
+    let mut problems = Vec::new();
+    if inside_written {
+        problems.push(format!(
+            "byte {:#x} inside the range was writable; enforced protection is narrower than advertised",
+            inside
+        ));
+    }
+    if !outside_written {
+        problems.push(format!(
+            "byte {:#x} outside the range was not writable; enforced protection is coarser than advertised",
+            outside
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(format!(
+            "Protection of {:#x}+{:#x} does not match wp-list: {}",
+            start,
+            len,
+            problems.join("; ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Read the chip and check its contents against the digest the user expects.
+fn expected_digest_test(expected: &Option<Digest>) -> impl Fn(&mut TestEnv) -> TestResult + '_ {
+    move |env: &mut TestEnv| {
+        let expected = match expected {
+            Some(d) => d,
+            None => return Err(Skip("no expected digest was given".into()).into()),
+        };
+
+        let path = env.scratch_file("digest.bin");
+        env.cmd.read_into_file(&path)?;
+        let actual = expected.algorithm.digest_file(&path)?;
+        if actual != *expected {
+            return Err(format!(
+                "Flash contents have digest {}, but {} was expected",
+                actual, expected
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
 fn elog_sanity_test(env: &mut TestEnv) -> TestResult {
     // Check that the elog contains *something*, as an indication that Coreboot
     // is actually able to write to the Flash. This only makes sense for chips
     // running Coreboot, which we assume is just host.
     if env.chip_type() != FlashChip::HOST {
-        info!("Skipping ELOG sanity check for non-host chip");
-        return Ok(());
+        return Err(Skip("ELOG is only checked on the host chip".into()).into());
     }
     // flash should be back in the golden state
     env.ensure_golden()?;
 
     const ELOG_RW_REGION_NAME: &str = "RW_ELOG";
+    let elog_file = env.scratch_file("elog.file");
     env.cmd
-        .read_region_into_file(ELOG_FILE, ELOG_RW_REGION_NAME)?;
+        .read_region_into_file(&elog_file, ELOG_RW_REGION_NAME)?;
 
     // Just checking for the magic numer
     // TODO: improve this test to read the events
-    if fs::metadata(ELOG_FILE)?.len() < 4 {
+    if fs::metadata(&elog_file)?.len() < 4 {
         return Err("ELOG contained no data".into());
     }
-    let data = fs::read(ELOG_FILE)?;
+    let data = fs::read(&elog_file)?;
     if u32::from_le_bytes(data[0..4].try_into()?) != 0x474f4c45 {
         return Err("ELOG had bad magic number".into());
     }
@@ -283,44 +1234,275 @@ fn host_is_chrome_test(_env: &mut TestEnv) -> TestResult {
     }
 }
 
-fn partial_lock_test(section: LayoutNames) -> impl Fn(&mut TestEnv) -> TestResult {
+/// The EC only write protects its RO region, so locking arbitrary sections of
+/// the test layout doesn't apply to it.
+fn check_partial_lock_supported(env: &TestEnv) -> TestResult {
+    if env.chip_type() == FlashChip::EC {
+        return Err(
+            Skip("the EC can only protect its RO region, not arbitrary sections".into()).into(),
+        );
+    }
+    env.wp_ranges()?;
+    Ok(())
+}
+
+fn partial_lock_test(section: LayoutNames, by_region: bool) -> impl Fn(&mut TestEnv) -> TestResult {
     move |env: &mut TestEnv| {
+        check_partial_lock_supported(env)?;
         // Need a clean image for verification
         env.ensure_golden()?;
+        let result = check_partial_lock(env, section, by_region);
 
-        let (wp_section_name, start, len) = utils::layout_section(env.layout(), section);
-        // Disable software WP so we can do range protection, but hardware WP
-        // must remain enabled for (most) range protection to do anything.
-        env.wp.set_hw(false)?.set_sw(false)?;
-        env.cmd.wp_range((start, len), true)?;
-        env.wp.set_hw(true)?;
+        // Range protection isn't tracked by env.wp, so clear it explicitly
+        // rather than leaving it for later tests (or runs) to trip over.
+        env.wp.set_hw(false)?;
+        env.cmd.wp_toggle(/* en= */ false)?;
+        result
+    }
+}
 
-        // Check that we cannot write to the protected region.
-        let rws = flashrom::ROMWriteSpecifics {
-            layout_file: Some(LAYOUT_FILE),
-            write_file: Some(env.random_data_file()),
-            name_file: Some(wp_section_name),
-        };
-        if env.cmd.write_file_with_layout(&rws).is_ok() {
+/// The Intel Flash Descriptor's signature and where it is in the image.
+const IFD_SIGNATURE: &[u8] = &[0x5a, 0xa5, 0xf0, 0x0f];
+const IFD_SIGNATURE_OFFSET: usize = 0x10;
+
+/// The Management Engine region of the Intel Flash Descriptor must be locked
+/// against writes from the host, even with write protect disabled.
+fn ifd_me_locked_test(env: &mut TestEnv) -> TestResult {
+    if env.chip_type() != FlashChip::HOST {
+        return Err(
+            Skip("the Intel Flash Descriptor is only checked on the host chip".into()).into(),
+        );
+    }
+    let signature = env
+        .golden()
+        .as_bytes()
+        .get(IFD_SIGNATURE_OFFSET..IFD_SIGNATURE_OFFSET + IFD_SIGNATURE.len());
+    if signature != Some(IFD_SIGNATURE) {
+        return Err(Skip("golden image has no Intel Flash Descriptor".into()).into());
+    }
+    env.ensure_golden()?;
+
+    env.wp.set_hw(false)?.set_sw(false)?;
+    let result = match env.cmd.write_ifd_region(env.random_data_file(), "me") {
+        Ok(()) => {
             info!(
-                "Section should be locked but flashrom write returned Ok. This may be incorrect; verifying"
+                "Flashrom returned Ok writing the ME region but this may be incorrect; verifying"
             );
+            Ok(())
+        }
+        Err(e) if e.kind() == &ErrorKind::Unsupported => {
+            return Err(Skip(e.to_string()).into());
         }
-        if !env.is_golden() {
-            return Err("Section didn't lock, has been overwritten with random data!".into());
+        Err(e) if e.kind() == &ErrorKind::WriteProtected => {
+            info!("Write to the ME region was refused: {}", e);
+            Ok(())
         }
+        Err(e) => Err(format!(
+            "Writing the ME region failed for a reason other than it being locked: {}",
+            e
+        )),
+    };
+    if !env.is_golden() {
+        env.restore_golden()?;
+        return Err("ME region isn't locked, has been overwritten with random data!".into());
+    }
+    result.map_err(Into::into)
+}
 
-        // Check that we can write to the non protected region.
-        let (non_wp_section_name, _, _) =
-            utils::layout_section(env.layout(), section.get_non_overlapping_section());
-        let rws = flashrom::ROMWriteSpecifics {
-            layout_file: Some(LAYOUT_FILE),
-            write_file: Some(env.random_data_file()),
-            name_file: Some(non_wp_section_name),
-        };
-        env.cmd.write_file_with_layout(&rws)?;
+/// Lock each section of the test layout in turn, reporting every section that
+/// misbehaved rather than stopping at the first.
+fn lock_sections_test(by_region: bool) -> impl Fn(&mut TestEnv) -> TestResult {
+    move |env: &mut TestEnv| {
+        check_partial_lock_supported(env)?;
+        let mut failures = Vec::new();
+        for &section in LayoutNames::ALL.iter() {
+            match partial_lock_test(section, by_region)(env) {
+                Ok(()) => info!("Section {:?} locked correctly", section),
+                Err(e) => {
+                    warn!("Section {:?} misbehaved: {}", section, e);
+                    failures.push(format!("{:?}: {}", section, e));
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} of {} sections misbehaved; {}",
+                failures.len(),
+                LayoutNames::ALL.len(),
+                failures.join("; ")
+            )
+            .into())
+        }
+    }
+}
 
-        Ok(())
+/// Check that --wp-status reports write protect enabled over exactly `range`.
+fn check_protected_range(env: &TestEnv, range: (i64, i64)) -> TestResult {
+    let state = env.cmd.wp_get_state()?;
+    if !state.enabled || state.range != range {
+        return Err(format!(
+            "Protected range should be {:#x}+{:#x}, but status is {:?}",
+            range.0, range.1, state
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn check_partial_lock(env: &mut TestEnv, section: LayoutNames, by_region: bool) -> TestResult {
+    let (wp_section_name, start, len) = utils::layout_section(env.layout(), section);
+    utils::check_wp_range_listed(&env.wp_ranges()?, (start, len))?;
+    // Disable software WP so we can do range protection, but hardware WP
+    // must remain enabled for (most) range protection to do anything.
+    env.wp.set_hw(false)?.set_sw(false)?;
+    if by_region {
+        env.cmd
+            .wp_region(env.layout_file(), wp_section_name, true)?;
+    } else {
+        env.cmd.wp_range((start, len), true)?;
+    }
+    env.wp.set_hw(true)?;
+    check_protected_range(env, (start, len))?;
+
+    // Check that we cannot write to the protected region.
+    let rws = flashrom::ROMWriteSpecifics {
+        layout_file: Some(env.layout_file()),
+        write_file: Some(env.random_data_file()),
+        name_file: Some(wp_section_name),
+    };
+    if env.cmd.write_file_with_layout(&rws).is_ok() {
+        info!(
+            "Section should be locked but flashrom write returned Ok. This may be incorrect; verifying"
+        );
+    }
+    if !env.is_golden() {
+        return Err("Section didn't lock, has been overwritten with random data!".into());
+    }
+
+    // Check that we can write to the non protected region.
+    let (non_wp_section_name, _, _) =
+        utils::layout_section(env.layout(), section.get_non_overlapping_section());
+    let rws = flashrom::ROMWriteSpecifics {
+        layout_file: Some(env.layout_file()),
+        write_file: Some(env.random_data_file()),
+        name_file: Some(non_wp_section_name),
+    };
+    env.cmd.write_file_with_layout(&rws)?;
+
+    // The protected region must still match golden after the other was written.
+    env.cmd
+        .verify_file_with_layout(env.layout_file(), wp_section_name, env.golden_file())
+        .map_err(|e| format!("Locked section changed after writing the rest: {}", e))?;
+
+    Ok(())
+}
+
+fn snapshot_rollback_test(env: &mut TestEnv) -> TestResult {
+    env.ensure_golden()?;
+    let result = check_snapshot_rollback(env);
+    env.restore_golden()?;
+    result
+}
+
+/// Corrupt part of the chip, checkpoint it, corrupt it again and roll back to
+/// the checkpoint, which must restore exactly the first corruption.
+fn check_snapshot_rollback(env: &mut TestEnv) -> TestResult {
+    env.wp.set_hw(false)?.set_sw(false)?;
+    let (section_name, _, _) = utils::layout_section(env.layout(), LayoutNames::BottomQuad);
+    let rws = flashrom::ROMWriteSpecifics {
+        layout_file: Some(env.layout_file()),
+        write_file: Some(env.random_data_file()),
+        name_file: Some(section_name),
+    };
+    env.cmd.write_file_with_layout(&rws)?;
+
+    let snapshot = env.snapshot()?;
+    env.erase()?;
+    env.restore_snapshot(&snapshot)?;
+
+    if env.is_golden() {
+        return Err("Rolling back to the snapshot restored the golden image instead".into());
+    }
+    env.cmd
+        .verify_file_with_layout(env.layout_file(), section_name, env.random_data_file())
+        .map_err(|e| format!("{} doesn't hold the snapshot's data: {}", section_name, e))?;
+    Ok(())
+}
+
+fn restore_idempotent_test(env: &mut TestEnv) -> TestResult {
+    // Dirty part of the chip so the first restore has real work to do.
+    env.wp.set_hw(false)?.set_sw(false)?;
+    let (section_name, _, _) = utils::layout_section(env.layout(), LayoutNames::BottomQuad);
+    let rws = flashrom::ROMWriteSpecifics {
+        layout_file: Some(env.layout_file()),
+        write_file: Some(env.random_data_file()),
+        name_file: Some(section_name),
+    };
+    env.cmd.write_file_with_layout(&rws)?;
+
+    let start = Instant::now();
+    env.ensure_golden()?;
+    let first = start.elapsed();
+
+    // The chip now matches, so writing the golden image again should find
+    // nothing to write. Whether it did is judged by comparing the chip with the
+    // image, not by how long it took, which depends on how busy the host is.
+    let start = Instant::now();
+    let changed = match env.cmd.write_from_file_changed(env.golden_file()) {
+        Err(e) if e.kind() == &ErrorKind::Unsupported => return Err(Skip(e.to_string()).into()),
+        changed => changed?,
+    };
+    let second = start.elapsed();
+    info!(
+        "Golden restore took {:?} from a modified chip, {:?} when already golden",
+        first, second
+    );
+
+    if !env.is_golden() {
+        return Err("Flash does not match golden image after restoring twice".into());
+    }
+    if changed {
+        return Err("Restoring an already-golden chip erased or wrote the flash again".into());
+    }
+    Ok(())
+}
+
+/// Writing an image larger than the chip must be refused for its size, leaving
+/// the chip untouched.
+fn oversize_write_test(env: &mut TestEnv) -> TestResult {
+    env.ensure_golden()?;
+    env.wp.set_hw(false)?.set_sw(false)?;
+
+    let oversize = env.scratch_file("oversize.bin");
+    rand_util::gen_rand_testdata_unlike(
+        &oversize,
+        env.rom_size() as usize + 4096,
+        env.golden().as_bytes(),
+        env.seed(),
+    )
+    .map_err(|e| format!("I/O error writing oversized data file: {:#}", e))?;
+    let result = env.cmd.write_from_file(&oversize);
+    if let Err(e) = fs::remove_file(&oversize) {
+        warn!("Failed to remove {}: {}", oversize, e);
+    }
+
+    if !env.is_golden() {
+        env.restore_golden()?;
+        return Err("Writing an oversized image modified the chip".into());
+    }
+    match result {
+        Ok(()) => Err("Writing an image larger than the chip succeeded".into()),
+        Err(e) if e.kind() == &ErrorKind::SizeMismatch => {
+            info!("Oversized image refused: {}", e);
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "Writing an oversized image failed for a reason other than its size: {}",
+            e
+        )
+        .into()),
     }
 }
 
@@ -332,6 +1514,145 @@ fn verify_fail_test(env: &mut TestEnv) -> TestResult {
     }
 }
 
+/// Read the chip twice with the programmer initialised afresh for each read,
+/// and check that both reads agree.
+///
+/// This catches programmers whose first read after init differs from later
+/// ones. Each read through the flashrom binary is a new process anyway, while
+/// libflashrom shuts the programmer down and initialises it again in between.
+fn reinit_read_test(env: &mut TestEnv) -> TestResult {
+    let first = env.scratch_file("reinit_first.bin");
+    let second = env.scratch_file("reinit_second.bin");
+    env.cmd.read_into_file(&first)?;
+    env.cmd.reinit_programmer()?;
+    env.cmd.read_into_file(&second)?;
+
+    match GoldenImage::open(&first)?.first_difference(File::open(&second)?)? {
+        None => Ok(()),
+        Some(offset) => Err(format!(
+            "Reads after programmer re-init differ, first at offset {:#x}",
+            offset
+        )
+        .into()),
+    }
+}
+
+/// Read every region described by the golden image's FMAP and compare it with
+/// the same region of the golden image, reporting which regions differ.
+fn fmap_regions_test(env: &mut TestEnv) -> TestResult {
+    let areas = match fmap::read_fmap(env.golden().as_bytes()) {
+        Some(areas) => areas,
+        None => return Err(Skip("golden image has no FMAP".into()).into()),
+    };
+    env.ensure_golden()?;
+
+    // flashrom must find the same FMAP on the chip.
+    let from_chip = fmap::fmap_regions(&env.cmd, &env.scratch_file("fmap.bin"))?;
+    if from_chip != areas {
+        return Err(format!(
+            "flashrom read FMAP areas {:?} from the chip, but the image has {:?}",
+            from_chip, areas
+        )
+        .into());
+    }
+
+    let region_file = env.scratch_file("fmap_region.bin");
+    let mut failed = Vec::new();
+    for area in areas {
+        let expected = match env
+            .golden()
+            .as_bytes()
+            .get(area.offset..area.offset + area.size)
+        {
+            Some(expected) => expected,
+            None => {
+                info!("Skipping region {}: not within the golden image", area.name);
+                continue;
+            }
+        };
+
+        match verify_region(&env.cmd, &region_file, &area.name, expected) {
+            Ok(()) => info!("Region {}: pass", area.name),
+            Err(e) => {
+                warn!("Region {}: FAIL ({})", area.name, e);
+                failed.push(area.name);
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("FMAP regions failed verification: {}", failed.join(", ")).into())
+    }
+}
+
+/// Read the whole chip and compare each region of the user-supplied layout
+/// with the same region of the golden image, reporting which regions differ.
+fn layout_regions_test(env: &mut TestEnv) -> TestResult {
+    if env.user_layout().is_empty() {
+        return Err(Skip("no layout file given".into()).into());
+    }
+    env.ensure_golden()?;
+
+    let actual = env
+        .cmd
+        .read_to_vec(&env.scratch_file("layout_readback.bin"))?;
+    let expected = env.golden().as_bytes();
+
+    let mut failed = Vec::new();
+    for region in env.user_layout() {
+        let range = region.start as usize..region.end as usize + 1;
+        match actual
+            .get(range.clone())
+            .zip(expected.get(range))
+            .map(|(a, e)| a.iter().zip(e).position(|(a, e)| a != e))
+        {
+            Some(None) => info!("Region {}: pass", region.name),
+            Some(Some(offset)) => {
+                warn!(
+                    "Region {}: FAIL (first difference at region offset {:#x})",
+                    region.name, offset
+                );
+                failed.push(region.name.as_str());
+            }
+            None => {
+                warn!("Region {}: FAIL (not within the data read)", region.name);
+                failed.push(region.name.as_str());
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Layout regions failed verification: {}", failed.join(", ")).into())
+    }
+}
+
+/// Read the named region into `file` and compare it with `expected`.
+fn verify_region(
+    cmd: &dyn Flashrom,
+    file: &str,
+    name: &str,
+    expected: &[u8],
+) -> Result<(), String> {
+    cmd.read_region_into_file(file, name)
+        .map_err(|e| e.to_string())?;
+    let actual = fs::read(file).map_err(|e| e.to_string())?;
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "read {:#x} bytes, expected {:#x}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    match actual.iter().zip(expected).position(|(a, e)| a != e) {
+        Some(offset) => Err(format!("first difference at region offset {:#x}", offset)),
+        None => Ok(()),
+    }
+}
+
 /// Ad-hoc parsing of os-release(5); mostly according to the spec,
 /// but ignores quotes and escaping.
 fn parse_os_release<I: IntoIterator<Item = String>>(lines: I) -> HashMap<String, String> {
@@ -379,6 +1700,81 @@ fn test_parse_os_release() {
     assert_eq!(get(&map, ""), None);
 }
 
+#[test]
+fn test_find_erase_boundary() {
+    let golden = [0x12, 0x34, 0x56, 0xFF, 0x78, 0x9A];
+
+    // Nothing erased
+    assert_eq!(find_erase_boundary(&golden, &golden), Ok(0));
+    // Bottom half erased
+    assert_eq!(
+        find_erase_boundary(&golden, &[0xFF, 0xFF, 0xFF, 0xFF, 0x78, 0x9A]),
+        Ok(3)
+    );
+    // An erased byte that was already 0xFF is indistinguishable from intact
+    assert_eq!(
+        find_erase_boundary(&golden, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x9A]),
+        Ok(5)
+    );
+    // Modified but not erased
+    assert!(find_erase_boundary(&golden, &[0xFF, 0x00, 0x56, 0xFF, 0x78, 0x9A]).is_err());
+    // Size mismatch
+    assert!(find_erase_boundary(&golden, &golden[..4]).is_err());
+}
+
+#[test]
+fn test_list_tests() {
+    let mut out = Vec::new();
+    list_tests(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let line = |name: &str| {
+        out.lines()
+            .find(|l| l.split_whitespace().next() == Some(name))
+            .unwrap_or_else(|| panic!("{} not listed in:\n{}", name, out))
+            .to_string()
+    };
+
+    assert_eq!(
+        line("Get_device_name"),
+        format!("{:<24} read-only", "Get_device_name")
+    );
+    assert!(line("Erase_and_Write").contains(" destructive"));
+    assert!(line("Lock_top_quad").ends_with("only when named; skipped on ec"));
+    assert!(line("IFD_ME_locked").ends_with("skipped on ec, servo, dediprog"));
+    assert!(line("Chip_size").ends_with("preflight only"));
+    let opts = RunOptions::default();
+    let registry = registered_tests(&opts);
+    assert_eq!(
+        out.lines().count(),
+        registry.all.len() + registry.by_name_only.len() + 2
+    );
+}
+
+#[test]
+fn test_registered_names_unique() {
+    let opts = RunOptions::default();
+    let registry = registered_tests(&opts);
+    // Names are selected case-insensitively, so they must differ in more than
+    // case.
+    let mut names = HashSet::new();
+    for t in registry.all.iter().chain(&registry.by_name_only) {
+        assert!(
+            names.insert(t.get_name().to_ascii_lowercase()),
+            "{} registered twice",
+            t.get_name()
+        );
+    }
+    // Preflight checks may also be in the suite, but only once each.
+    let mut preflight = HashSet::new();
+    for t in &registry.preflight {
+        assert!(
+            preflight.insert(t.get_name().to_ascii_lowercase()),
+            "{} registered twice",
+            t.get_name()
+        );
+    }
+}
+
 #[test]
 fn test_name_filter() {
     let test_one = ("Test One", |_: &mut TestEnv| Ok(()));
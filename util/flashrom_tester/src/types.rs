@@ -33,6 +33,8 @@
 // Software Foundation.
 //
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub const BOLD: &str = "\x1b[1m";
 
 pub const RESET: &str = "\x1b[0m";
@@ -41,13 +43,70 @@ pub const YELLOW: &str = "\x1b[33m";
 pub const GREEN: &str = "\x1b[92m";
 pub const RED: &str = "\x1b[31m";
 
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Turn the style macros' escape sequences on or off for the whole process.
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Whether stdout is a terminal, which decides the default for colors.
+pub fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Remove the ANSI escape sequences that the colors above produce.
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip "[", parameters and the final byte in '@'..='~'.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
 macro_rules! style_dbg {
     ($s: expr, $c: expr) => {
-        format!("{}{:?}{}", $c, $s, types::RESET)
+        if types::color() {
+            format!("{}{:?}{}", $c, $s, types::RESET)
+        } else {
+            format!("{:?}", $s)
+        }
     };
 }
 macro_rules! style {
     ($s: expr, $c: expr) => {
-        format!("{}{}{}", $c, $s, types::RESET)
+        if types::color() {
+            format!("{}{}{}", $c, $s, types::RESET)
+        } else {
+            format!("{}", $s)
+        }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn strip_ansi() {
+        use super::*;
+
+        let styled = format!("{}bold{} {}red{}", BOLD, RESET, RED, RESET);
+        assert_eq!(strip_ansi(&styled), "bold red");
+        assert_eq!(strip_ansi("plain\n"), "plain\n");
+    }
+}
@@ -33,8 +33,12 @@
 // Software Foundation.
 //
 
+use super::fmap::{self, FmapArea};
+use flashrom::{FlashChip, Flashrom, FlashromError};
+use std::collections::BTreeMap;
 use std::io::prelude::*;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum LayoutNames {
@@ -45,6 +49,14 @@ pub enum LayoutNames {
 }
 
 impl LayoutNames {
+    /// Every section of the test layout.
+    pub const ALL: [LayoutNames; 4] = [
+        LayoutNames::TopQuad,
+        LayoutNames::BottomQuad,
+        LayoutNames::BottomHalf,
+        LayoutNames::TopHalf,
+    ];
+
     // Return a section that does not overlap
     pub fn get_non_overlapping_section(&self) -> LayoutNames {
         match self {
@@ -66,13 +78,53 @@ pub struct LayoutSizes {
     top_quad_bottom: i64,
 }
 
-pub fn get_layout_sizes(rom_sz: i64) -> Result<LayoutSizes, String> {
+/// The smallest chip the tests support.
+///
+/// The partial lock tests need each quarter of the test layout to span at
+/// least one erase block, so that writing one quarter never erases part of
+/// another. With the common 4 KiB erase block that means chips of at least
+/// 16 KiB.
+pub const MIN_CHIP_SIZE: i64 = 16 * 1024;
+
+/// Granularity that section boundaries are rounded to on chips whose size is
+/// not a power of two, matching the common 4 KiB erase block.
+pub const LAYOUT_ALIGN: i64 = 4 << 10;
+
+/// Check that a chip of `rom_sz` bytes can be divided evenly into the halves
+/// and quarters of the test layout: it must be a power of two no smaller than
+/// `MIN_CHIP_SIZE`.
+pub fn check_rom_size(rom_sz: i64) -> Result<(), String> {
     if rom_sz <= 0 {
         return Err("invalid rom size provided".into());
     }
-    if rom_sz & (rom_sz - 1) != 0 {
-        return Err("invalid rom size, not a power of 2".into());
+    if !(rom_sz as u64).is_power_of_two() {
+        return Err(format!("invalid rom size {:#x}, not a power of 2", rom_sz));
+    }
+    if rom_sz < MIN_CHIP_SIZE {
+        return Err(format!(
+            "invalid rom size, smaller than the minimum of {} bytes",
+            MIN_CHIP_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// Divide a chip of `rom_sz` bytes into the sections of the test layout.
+///
+/// Given the chip's erase block size, every section boundary is rounded down
+/// to a block edge so that writing one section never erases part of another.
+/// Without one, power of two sizes are split exactly into halves and quarters
+/// and other sizes are rounded to `LAYOUT_ALIGN`. Rounding may leave the top
+/// sections slightly larger than the bottom ones, but every section still ends
+/// within the chip.
+pub fn get_layout_sizes(rom_sz: i64, erase_block: Option<i64>) -> Result<LayoutSizes, String> {
+    if let Some(block) = erase_block {
+        return aligned_layout_sizes(rom_sz, block);
+    }
+    if rom_sz > 0 && !(rom_sz as u64).is_power_of_two() {
+        return aligned_layout_sizes(rom_sz, LAYOUT_ALIGN);
     }
+    check_rom_size(rom_sz)?;
     Ok(LayoutSizes {
         half_sz: rom_sz / 2,
         quad_sz: rom_sz / 4,
@@ -83,10 +135,38 @@ pub fn get_layout_sizes(rom_sz: i64) -> Result<LayoutSizes, String> {
     })
 }
 
+fn aligned_layout_sizes(rom_sz: i64, align: i64) -> Result<LayoutSizes, String> {
+    if align <= 0 {
+        return Err(format!("invalid block size {:#x}, must be positive", align));
+    }
+    if rom_sz < 4 * align {
+        return Err(format!(
+            "invalid rom size {:#x}, too small to divide into {:#x}-byte blocks",
+            rom_sz, align
+        ));
+    }
+    let align_down = |x: i64| x - x % align;
+    let half_sz = align_down(rom_sz / 2);
+    let quad_sz = align_down(rom_sz / 4);
+    let top_quad_bottom = align_down(rom_sz - rom_sz / 4);
+    Ok(LayoutSizes {
+        half_sz,
+        quad_sz,
+        rom_top: rom_sz - 1,
+        bottom_half_top: half_sz - 1,
+        bottom_quad_top: quad_sz - 1,
+        top_quad_bottom,
+    })
+}
+
 pub fn layout_section(ls: &LayoutSizes, ln: LayoutNames) -> (&'static str, i64, i64) {
     match ln {
-        LayoutNames::TopQuad => ("TOP_QUAD", ls.top_quad_bottom, ls.quad_sz),
-        LayoutNames::TopHalf => ("TOP_HALF", ls.half_sz, ls.half_sz),
+        LayoutNames::TopQuad => (
+            "TOP_QUAD",
+            ls.top_quad_bottom,
+            ls.rom_top + 1 - ls.top_quad_bottom,
+        ),
+        LayoutNames::TopHalf => ("TOP_HALF", ls.half_sz, ls.rom_top + 1 - ls.half_sz),
         LayoutNames::BottomHalf => ("BOTTOM_HALF", 0, ls.half_sz),
         LayoutNames::BottomQuad => ("BOTTOM_QUAD", 0, ls.quad_sz),
     }
@@ -99,13 +179,296 @@ pub fn construct_layout_file<F: Write>(mut target: F, ls: &LayoutSizes) -> std::
     writeln!(target, "{:x}:{:x} TOP_QUAD", ls.top_quad_bottom, ls.rom_top)
 }
 
-pub fn toggle_hw_wp(dis: bool) -> Result<(), String> {
+/// A region described by a line of a flashrom layout file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LayoutRegion {
+    pub name: String,
+    pub start: i64,
+    /// The last byte of the region, inclusive as written in the layout file.
+    pub end: i64,
+}
+
+/// Parse a flashrom layout file: one `start:end name` line per region, with
+/// hex addresses. Blank lines and lines starting with '#' are ignored.
+pub fn parse_layout(text: &str) -> Result<Vec<LayoutRegion>, String> {
+    let mut regions = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |why: &str| format!("layout line {}: {} in {:?}", lineno + 1, why, line);
+
+        let (range, name) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| bad("missing region name"))?;
+        let (start, end) = range
+            .split_once(':')
+            .ok_or_else(|| bad("expected start:end"))?;
+        let addr = |s: &str| {
+            i64::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| bad(&e.to_string()))
+        };
+        let region = LayoutRegion {
+            name: name.trim().to_string(),
+            start: addr(start)?,
+            end: addr(end)?,
+        };
+        if region.end < region.start {
+            return Err(bad("region ends before it starts"));
+        }
+        regions.push(region);
+    }
+    Ok(regions)
+}
+
+/// Check that the regions of a user-supplied layout lie within the chip and
+/// that no two of them overlap, listing every conflict found.
+pub fn check_layout(regions: &[LayoutRegion], rom_sz: i64) -> Result<(), String> {
+    let mut problems = Vec::new();
+    for r in regions.iter().filter(|r| r.end >= rom_sz) {
+        problems.push(format!(
+            "{} ({:#x}:{:#x}) extends past the end of the {:#x}-byte chip",
+            r.name, r.start, r.end, rom_sz
+        ));
+    }
+    for (i, a) in regions.iter().enumerate() {
+        for b in &regions[i + 1..] {
+            if a.start <= b.end && b.start <= a.end {
+                problems.push(format!(
+                    "{} ({:#x}:{:#x}) overlaps {} ({:#x}:{:#x})",
+                    a.name, a.start, a.end, b.name, b.start, b.end
+                ));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid layout: {}", problems.join("; ")))
+    }
+}
+
+/// Decide from the result of `--wp-list` whether the chip's protection ranges
+/// can be listed, returning the reason if not. The Linux kernel SPI driver
+/// can't report (or set) ranges, and lists none at all.
+pub fn wp_list_support(list: Result<String, FlashromError>) -> Result<String, String> {
+    match list {
+        Ok(list) if list.trim().is_empty() => Err(
+            "the programmer lists no protection ranges, as with the Linux kernel SPI driver".into(),
+        ),
+        Ok(list) => Ok(list),
+        Err(e) => Err(format!("protection ranges can't be listed: {}", e)),
+    }
+}
+
+/// Parse the protection ranges from `flashrom --wp-list` output into
+/// (start, len) pairs.
+///
+/// Lines that don't describe a range (headers and the like) are ignored.
+/// Ranges look like "start=0x00000000 length=0x00001000", possibly followed
+/// by a description.
+pub fn parse_wp_list(list: &str) -> Result<Vec<(i64, i64)>, String> {
+    fn parse_field(line: &str, key: &str) -> Result<Option<i64>, String> {
+        let value = match line.split_whitespace().find_map(|w| w.strip_prefix(key)) {
+            Some(v) => v.trim_end_matches(','),
+            None => return Ok(None),
+        };
+        i64::from_str_radix(value.trim_start_matches("0x"), 16)
+            .map(Some)
+            .map_err(|e| format!("Bad {}{:?} in wp-list line {:?}: {}", key, value, line, e))
+    }
+
+    let mut ranges = Vec::new();
+    for line in list.lines() {
+        match (parse_field(line, "start=")?, parse_field(line, "length=")?) {
+            (Some(start), Some(len)) => ranges.push((start, len)),
+            (None, None) => continue,
+            _ => return Err(format!("Incomplete range in wp-list line {:?}", line)),
+        }
+    }
+    Ok(ranges)
+}
+
+/// Check that `range` is one of the protection `ranges` listed by --wp-list.
+/// Some chips silently ignore requests to protect any other range.
+pub fn check_wp_range_listed(ranges: &[(i64, i64)], range: (i64, i64)) -> Result<(), String> {
+    if ranges.contains(&range) {
+        return Ok(());
+    }
+    Err(format!(
+        "Protection range {:#x}+{:#x} is not one of the {} ranges the chip supports",
+        range.0,
+        range.1,
+        ranges.len()
+    ))
+}
+
+/// Parse a number of bytes, either decimal or 0x-prefixed hex.
+fn parse_bytes(s: &str) -> Result<i64, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => s.parse::<i64>(),
+    }
+}
+
+/// Parse a chip size given in bytes, either decimal or 0x-prefixed hex, which
+/// must be a positive multiple of `LAYOUT_ALIGN` so the test layout can be
+/// built for it.
+pub fn parse_chip_size(s: &str) -> Result<i64, String> {
+    let size = parse_bytes(s).map_err(|e| format!("invalid chip size {:?}: {}", s, e))?;
+    if size <= 0 || size % LAYOUT_ALIGN != 0 {
+        return Err(format!(
+            "invalid chip size {:?}: must be a positive multiple of {:#x}",
+            s, LAYOUT_ALIGN
+        ));
+    }
+    Ok(size)
+}
+
+/// Parse an erase block size given in bytes, with the same syntax as
+/// `parse_chip_size`, which must be a positive power of two.
+pub fn parse_erase_block(s: &str) -> Result<i64, String> {
+    let size = parse_bytes(s).map_err(|e| format!("invalid erase block size {:?}: {}", s, e))?;
+    if size <= 0 || !(size as u64).is_power_of_two() {
+        return Err(format!(
+            "invalid erase block size {:?}: must be a positive power of 2",
+            s
+        ));
+    }
+    Ok(size)
+}
+
+/// Find the spans of bytes that differ between two images, as (start, len)
+/// pairs in ascending order.
+///
+/// If one image is longer, its excess is reported as differing.
+pub fn diff_spans(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for i in 0..a.len().max(b.len()) {
+        if a.get(i) != b.get(i) {
+            start.get_or_insert(i);
+        } else if let Some(s) = start.take() {
+            spans.push((s, i - s));
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, a.len().max(b.len()) - s));
+    }
+    spans
+}
+
+/// Find the spans of bytes that differ between the images in two files.
+pub fn diff_images(a: &str, b: &str) -> std::io::Result<Vec<(usize, usize)>> {
+    Ok(diff_spans(&std::fs::read(a)?, &std::fs::read(b)?))
+}
+
+/// Return the directory intermediate files go in: `work_dir` if given,
+/// otherwise the system temporary directory.
+pub fn work_dir_or_temp(work_dir: Option<&Path>) -> PathBuf {
+    work_dir.map_or_else(std::env::temp_dir, Path::to_path_buf)
+}
+
+/// Read the chip and compare it with the image at `reference`, describing
+/// each span that differs along with the FMAP areas of the reference image it
+/// falls in. The chip is read through a file in `work_dir`, if given.
+pub fn diff_chip(
+    cmd: &dyn Flashrom,
+    reference: &str,
+    work_dir: Option<&Path>,
+) -> Result<Vec<String>, FlashromError> {
+    let scratch = tempfile::NamedTempFile::new_in(work_dir_or_temp(work_dir))
+        .map_err(|e| format!("I/O error creating temporary file: {:#}", e))?;
+    let current = cmd.read_to_vec(&scratch.path().to_string_lossy())?;
+    let image = std::fs::read(reference)
+        .map_err(|e| format!("I/O error reading {}: {:#}", reference, e))?;
+    let spans = diff_spans(&current, &image);
+    drop(current);
+
+    let areas = fmap::read_fmap(&image).unwrap_or_default();
+    Ok(describe_diff(&spans, &areas))
+}
+
+/// Verify the chip against the image at `reference` without modifying it. If
+/// they differ, describe where as `diff_chip` does; an empty list means the
+/// chip matches.
+pub fn verify_chip(
+    cmd: &dyn Flashrom,
+    reference: &str,
+    work_dir: Option<&Path>,
+) -> Result<Vec<String>, FlashromError> {
+    let err = match cmd.verify_from_file(reference) {
+        Ok(()) => return Ok(Vec::new()),
+        Err(e) => e,
+    };
+    info!("Verification failed ({}); finding what differs", err);
+    let spans = diff_chip(cmd, reference, work_dir)?;
+    if spans.is_empty() {
+        // The contents match, so verification failed for some other reason.
+        return Err(err);
+    }
+    Ok(spans)
+}
+
+/// Describe each differing span, naming the FMAP areas it overlaps.
+pub fn describe_diff(spans: &[(usize, usize)], areas: &[FmapArea]) -> Vec<String> {
+    spans
+        .iter()
+        .map(|&(start, len)| {
+            let names: Vec<&str> = areas
+                .iter()
+                .filter(|a| a.offset < start + len && start < a.offset + a.size)
+                .map(|a| a.name.as_str())
+                .collect();
+            if names.is_empty() {
+                format!("{:#x}+{:#x}", start, len)
+            } else {
+                format!("{:#x}+{:#x} ({})", start, len, names.join(", "))
+            }
+        })
+        .collect()
+}
+
+/// Whether the operator may be asked to change hardware write protect by hand.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ManualWp {
+    /// Prompt the operator and wait for them to do it.
+    #[default]
+    Prompt,
+    /// Fail instead, for unattended runs.
+    Refuse,
+}
+
+impl std::str::FromStr for ManualWp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("prompt") {
+            Ok(ManualWp::Prompt)
+        } else if s.eq_ignore_ascii_case("refuse") {
+            Ok(ManualWp::Refuse)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Change the hardware write protect, which on a host without servo can only
+/// be done by physical action. Fails without prompting if `policy` refuses it.
+pub fn toggle_hw_wp(dis: bool, policy: ManualWp) -> Result<(), String> {
     // The easist way to toggle the hardware write-protect is
     // to {dis}connect the battery (and/or open the WP screw).
     let s = if dis { "dis" } else { "" };
     // Print a failure message, but not on the first try.
     let mut fail_msg = None;
     while dis == get_hardware_wp()? {
+        if policy == ManualWp::Refuse {
+            return Err(format!(
+                "Hardware write protect must be {}abled by hand, but manual write protect is refused",
+                if dis { "dis" } else { "en" }
+            ));
+        }
         if let Some(msg) = fail_msg {
             eprintln!("{msg}");
         }
@@ -118,6 +481,43 @@ pub fn toggle_hw_wp(dis: bool) -> Result<(), String> {
     Ok(())
 }
 
+/// Ask the operator to change the hardware write protect by hand, for a DUT
+/// other than the one running the tests where the change can't be checked.
+/// Fails without prompting if `policy` refuses it.
+pub fn prompt_hw_wp(enable: bool, policy: ManualWp) -> Result<(), String> {
+    let s = if enable { "en" } else { "dis" };
+    if policy == ManualWp::Refuse {
+        return Err(format!(
+            "Hardware write protect must be {}abled by hand, but manual write protect is refused",
+            s
+        ));
+    }
+    info!("Prompt for hardware WP {}able", s);
+    eprintln!(" > {}able hardware write protect on the DUT", s);
+    pause();
+    Ok(())
+}
+
+/// Reset whatever state the programmer keeps between flashrom runs, as close to
+/// a power cycle of the flash as is possible without rebooting the tester.
+///
+/// Only Servo can be reset, by cycling its write protect lines through
+/// dut-control. Returns false without doing anything for other programmers:
+/// the host chip can't be reset from within the process, and there is no way
+/// to reset the EC or a Dediprog short of reinitialising them as every
+/// flashrom run does anyway.
+pub fn reset_programmer(fc: FlashChip) -> Result<bool, String> {
+    match fc {
+        FlashChip::SERVO => {
+            // Tests run with fw_wp deasserted, so leave it that way afterwards.
+            flashrom::dut_ctrl_toggle_wp(true).map_err(|e| e.to_string())?;
+            flashrom::dut_ctrl_toggle_wp(false).map_err(|e| e.to_string())?;
+            Ok(true)
+        }
+        FlashChip::HOST | FlashChip::EC | FlashChip::DEDIPROG => Ok(false),
+    }
+}
+
 pub fn ac_power_warning() {
     info!("*****************************");
     info!("AC power *must be* connected!");
@@ -139,18 +539,59 @@ fn pause() {
 }
 
 pub fn get_hardware_wp() -> std::result::Result<bool, String> {
-    let wp_s_val = collect_crosssystem(&["wpsw_cur"])?.parse::<u32>();
-    match wp_s_val {
-        Ok(v) => {
-            if v == 1 {
-                return Ok(true);
-            } else if v == 0 {
-                return Ok(false);
-            } else {
-                return Err("Unknown write protect value".into());
-            }
+    CrossystemInfo::collect()?.wpsw_cur()
+}
+
+/// The values crossystem reports, from output lines of the form
+/// `key = value # comment`.
+#[derive(Debug, Default, PartialEq)]
+pub struct CrossystemInfo {
+    values: BTreeMap<String, String>,
+}
+
+impl CrossystemInfo {
+    /// Run crossystem and parse everything it reports.
+    pub fn collect() -> Result<Self, String> {
+        Ok(Self::parse(&collect_crosssystem(&[])?))
+    }
+
+    /// Parse crossystem's output, ignoring lines that aren't `key = value`.
+    pub fn parse(output: &str) -> Self {
+        let values = output
+            .lines()
+            .filter_map(|line| {
+                let (key, rest) = line.split_once('=')?;
+                // The comment is separated from the value by whitespace, which
+                // is absent when the value is empty.
+                let value = match rest.find(" #") {
+                    Some(end) => &rest[..end],
+                    None => rest,
+                };
+                Some((key.trim().to_string(), value.trim().to_string()))
+            })
+            .filter(|(key, _)| !key.is_empty())
+            .collect();
+        CrossystemInfo { values }
+    }
+
+    /// Return the value of `key` as crossystem printed it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Return the current position of the hardware write protect switch.
+    pub fn wpsw_cur(&self) -> Result<bool, String> {
+        match self.get("wpsw_cur") {
+            Some("1") => Ok(true),
+            Some("0") => Ok(false),
+            Some(v) => Err(format!("Unknown write protect value {:?}", v)),
+            None => Err("crossystem did not report wpsw_cur".into()),
         }
-        Err(_) => return Err("Cannot parse write protect value".into()),
+    }
+
+    /// Return which main firmware slot (A, B or recovery) is running.
+    pub fn mainfw_act(&self) -> Option<&str> {
+        self.get("mainfw_act")
     }
 }
 
@@ -167,6 +608,158 @@ pub fn collect_crosssystem(args: &[&str]) -> Result<String, String> {
     Ok(String::from_utf8_lossy(&cmd.stdout).into_owned())
 }
 
+/// Read the kernel log with dmesg, which usually needs root.
+pub fn collect_dmesg() -> Result<String, String> {
+    let output = Command::new("dmesg")
+        .output()
+        .map_err(|e| format!("Failed to run dmesg: {}", e))?;
+    if !output.status.success() {
+        return Err(translate_command_error(&output).to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Return the lines of the kernel log `after` that follow everything already
+/// in `before`. If the log wrapped so that none of `before` is left, all of
+/// `after` is new.
+pub fn new_dmesg_lines<'a>(before: &str, after: &'a str) -> Vec<&'a str> {
+    let lines: Vec<&str> = after.lines().collect();
+    let start = match before.lines().last() {
+        Some(last) => lines.iter().rposition(|&l| l == last).map_or(0, |i| i + 1),
+        None => 0,
+    };
+    lines[start..].to_vec()
+}
+
+/// Run a user-supplied shell command, such as a --pre-run hook, logging what
+/// it prints.
+pub fn run_hook(command: &str) -> Result<(), String> {
+    info!("Running hook: {}", command);
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| format!("Failed to run {:?}: {}", command, e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        info!("{}", stdout.trim_end());
+    }
+    if !output.status.success() {
+        return Err(format!(
+            "{:?} failed: {}",
+            command,
+            translate_command_error(&output)
+        ));
+    }
+    Ok(())
+}
+
+/// Environment variables recorded in the report when no allowlist is given.
+pub const DEFAULT_REPORT_ENV: &[&str] = &["FLASHROM_TESTER_LOG", "PATH"];
+
+/// The environment a test run happened in, recorded so archived reports are
+/// self-describing.
+#[derive(Debug, Default, PartialEq)]
+pub struct EnvironmentInfo {
+    /// Output of `uname -srvm`.
+    pub kernel: String,
+    /// Values of the recorded environment variables; unset ones are omitted.
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Collect the kernel version and the values of the named environment
+/// variables. Nothing is redacted, so callers choose which variables to record.
+pub fn collect_environment<S: AsRef<str>>(vars: &[S]) -> EnvironmentInfo {
+    let kernel = match Command::new("uname").arg("-srvm").output() {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).trim().to_string(),
+        Ok(out) => {
+            warn!("uname failed: {}", translate_command_error(&out));
+            "<Unknown kernel>".to_string()
+        }
+        Err(e) => {
+            warn!("Failed to run uname: {}", e);
+            "<Unknown kernel>".to_string()
+        }
+    };
+
+    let variables = vars
+        .iter()
+        .filter_map(|var| {
+            let var = var.as_ref();
+            std::env::var_os(var).map(|v| (var.to_string(), v.to_string_lossy().into_owned()))
+        })
+        .collect();
+
+    EnvironmentInfo { kernel, variables }
+}
+
+/// POST a JSON document to `url` with curl, adding each of `headers`
+/// (formatted as "Name: value") to the request.
+///
+/// HTTP error statuses are reported as errors.
+pub fn post_json(url: &str, headers: &[String], body: &serde_json::Value) -> Result<(), String> {
+    post_json_with("curl", url, headers, body)
+}
+
+/// Check that `url` is an http or https URL that results can be posted to.
+pub fn check_post_url(url: &str) -> Result<(), String> {
+    let scheme = url.split("://").next().unwrap_or_default();
+    if url.contains("://")
+        && (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+    {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not an http:// or https:// URL", url))
+    }
+}
+
+fn post_json_with(
+    curl: &str,
+    url: &str,
+    headers: &[String],
+    body: &serde_json::Value,
+) -> Result<(), String> {
+    check_post_url(url)?;
+    let mut args = vec![
+        "--silent",
+        "--show-error",
+        "--fail",
+        "-X",
+        "POST",
+        "-H",
+        "Content-Type: application/json",
+    ];
+    for header in headers {
+        args.push("-H");
+        args.push(header);
+    }
+    // The URL follows "--" so curl can never take it for an option.
+    args.extend(&["--data-binary", "@-", "--", url]);
+
+    let mut child = Command::new(curl)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    // Scoped so stdin is closed before waiting, otherwise curl never sees EOF.
+    {
+        let mut stdin = child.stdin.take().expect("curl stdin should be piped");
+        stdin
+            .write_all(body.to_string().as_bytes())
+            .map_err(|e| format!("Failed to send report to curl: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait for curl: {}", e))?;
+
+    if !output.status.success() {
+        return Err(translate_command_error(&output).to_string());
+    }
+    Ok(())
+}
+
 pub fn translate_command_error(output: &std::process::Output) -> std::io::Error {
     use std::io::{Error, ErrorKind};
     // There is two cases on failure;
@@ -192,6 +785,134 @@ pub fn translate_command_error(output: &std::process::Output) -> std::io::Error
 mod tests {
     use super::*;
 
+    #[test]
+    fn post_json() {
+        use super::post_json_with;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let curl = dir.path().join("curl");
+        let args = dir.path().join("args");
+        let body = dir.path().join("body");
+        std::fs::write(
+            &curl,
+            format!(
+                "#!/bin/sh\nprintf '%s\\n' \"$@\" > {}\ncat > {}\n",
+                args.display(),
+                body.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&curl, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let curl = curl.to_str().unwrap();
+
+        let report = serde_json::json!({"pass": true});
+        post_json_with(
+            curl,
+            "https://example.com/results",
+            &["X-Run: 1".to_string()],
+            &report,
+        )
+        .unwrap();
+        let args = std::fs::read_to_string(&args).unwrap();
+        let args: Vec<&str> = args.lines().collect();
+        assert_eq!(
+            args[args.len() - 2..],
+            ["--", "https://example.com/results"]
+        );
+        assert!(args.windows(2).any(|w| w == ["-H", "X-Run: 1"]));
+        assert_eq!(std::fs::read_to_string(&body).unwrap(), report.to_string());
+
+        // Anything that isn't an http(s) URL is rejected without running curl.
+        std::fs::remove_file(dir.path().join("body")).unwrap();
+        for url in &["-K/etc/passwd", "file:///etc/passwd", "example.com"] {
+            assert!(post_json_with(curl, url, &[], &report).is_err(), "{}", url);
+        }
+        assert!(!dir.path().join("body").exists());
+    }
+
+    #[test]
+    fn new_dmesg_lines() {
+        use super::new_dmesg_lines;
+
+        let before = "[    1.000] a\n[    2.000] b\n";
+        assert_eq!(
+            new_dmesg_lines(before, "[    1.000] a\n[    2.000] b\n[    3.000] c\n"),
+            vec!["[    3.000] c"]
+        );
+        assert!(new_dmesg_lines(before, before).is_empty());
+        // The start of the log was dropped to make room.
+        assert_eq!(
+            new_dmesg_lines(before, "[    2.000] b\n[    3.000] c\n[    4.000] d\n"),
+            vec!["[    3.000] c", "[    4.000] d"]
+        );
+        // The log wrapped entirely, or was cleared.
+        assert_eq!(
+            new_dmesg_lines(before, "[    5.000] e\n"),
+            vec!["[    5.000] e"]
+        );
+        assert_eq!(
+            new_dmesg_lines("", "[    1.000] a\n"),
+            vec!["[    1.000] a"]
+        );
+    }
+
+    #[test]
+    fn run_hook() {
+        assert_eq!(super::run_hook("echo hello"), Ok(()));
+        let err = super::run_hook("echo oops >&2; exit 3").unwrap_err();
+        assert!(err.contains("oops"), "{}", err);
+        assert!(err.contains("error code: 3"), "{}", err);
+    }
+
+    #[test]
+    fn parse_crossystem() {
+        let info = CrossystemInfo::parse(
+            "arch                    = x86                            # [RO/str] Platform architecture\n\
+             fwid                    = Google_Volteer.13672.224.0     # [RO/str] Active firmware ID\n\
+             kernkey_vfy             = sig                            # [RO/str] Type of verification done on kernel key block\n\
+             mainfw_act              = A                              # [RO/str] Active main firmware\n\
+             vdat_flags              =                                # [RO/int] Flags from VbSharedData\n\
+             wpsw_boot               = (error)                        # [RO/int] Firmware write protect hardware switch position at boot\n\
+             wpsw_cur                = 1                              # [RO/int] Firmware write protect hardware switch current position\n",
+        );
+        assert_eq!(info.get("arch"), Some("x86"));
+        assert_eq!(info.get("fwid"), Some("Google_Volteer.13672.224.0"));
+        assert_eq!(info.get("vdat_flags"), Some(""));
+        assert_eq!(info.get("wpsw_boot"), Some("(error)"));
+        assert_eq!(info.get("missing"), None);
+        assert_eq!(info.mainfw_act(), Some("A"));
+        assert_eq!(info.wpsw_cur(), Ok(true));
+
+        assert_eq!(
+            CrossystemInfo::parse("wpsw_cur = 0 # [RO/int] Firmware write protect\n").wpsw_cur(),
+            Ok(false)
+        );
+        assert!(CrossystemInfo::parse("wpsw_cur = (error) # [RO/int]\n")
+            .wpsw_cur()
+            .is_err());
+    }
+
+    #[test]
+    fn crossystem_without_wpsw_cur() {
+        let info = CrossystemInfo::parse(
+            "arch                    = arm                            # [RO/str] Platform architecture\n\
+             mainfw_act              = A                              # [RO/str] Active main firmware\n",
+        );
+        assert_eq!(
+            info.wpsw_cur(),
+            Err("crossystem did not report wpsw_cur".to_string())
+        );
+        assert_eq!(CrossystemInfo::parse("").wpsw_cur(), info.wpsw_cur());
+    }
+
+    #[test]
+    fn parse_manual_wp() {
+        assert_eq!("prompt".parse(), Ok(ManualWp::Prompt));
+        assert_eq!("Refuse".parse(), Ok(ManualWp::Refuse));
+        assert_eq!("never".parse::<ManualWp>(), Err(()));
+    }
+
     #[test]
     fn construct_layout_file() {
         use super::{construct_layout_file, get_layout_sizes};
@@ -199,7 +920,7 @@ mod tests {
         let mut buf = Vec::new();
         construct_layout_file(
             &mut buf,
-            &get_layout_sizes(0x10000).expect("64k is a valid chip size"),
+            &get_layout_sizes(0x10000, None).expect("64k is a valid chip size"),
         )
         .expect("no I/O errors expected");
 
@@ -212,22 +933,201 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_wp_list() {
+        use super::parse_wp_list;
+
+        assert_eq!(
+            parse_wp_list(
+                "Available protection ranges:\n\
+                 \tstart=0x00000000 length=0x00000000 (none)\n\
+                 \tstart=0x00000000 length=0x00001000 (lower 1/2048)\n\
+                 \tstart=0x007ff000 length=0x00001000 (upper 1/2048)\n\
+                 \tstart=0x00000000 length=0x00800000 (all)\n"
+            ),
+            Ok(vec![(0, 0), (0, 0x1000), (0x7ff000, 0x1000), (0, 0x800000)])
+        );
+        assert_eq!(parse_wp_list(""), Ok(vec![]));
+        assert!(parse_wp_list("start=0x1000").is_err());
+        assert!(parse_wp_list("start=0xZZ length=0x1000").is_err());
+    }
+
+    #[test]
+    fn wp_list_support() {
+        use super::wp_list_support;
+
+        let list = "start=0x00000000 length=0x00001000\n";
+        assert_eq!(wp_list_support(Ok(list.into())), Ok(list.into()));
+        assert!(wp_list_support(Ok(String::new()))
+            .unwrap_err()
+            .contains("Linux kernel SPI driver"));
+        assert!(wp_list_support(Ok(" \n".into())).is_err());
+        assert_eq!(
+            wp_list_support(Err(
+                "wp_list isn't supported on platforms using the Linux kernel SPI driver wp_list"
+                    .into()
+            )),
+            Err(
+                "protection ranges can't be listed: wp_list isn't supported on platforms \
+                 using the Linux kernel SPI driver wp_list"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn check_wp_range_listed() {
+        use super::check_wp_range_listed;
+
+        let ranges = [(0, 0), (0, 0x1000), (0x7ff000, 0x1000), (0, 0x800000)];
+        assert_eq!(check_wp_range_listed(&ranges, (0x7ff000, 0x1000)), Ok(()));
+        assert_eq!(
+            check_wp_range_listed(&ranges, (0x400000, 0x400000)),
+            Err(
+                "Protection range 0x400000+0x400000 is not one of the 4 ranges the chip supports"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn collect_environment() {
+        use super::collect_environment;
+
+        std::env::set_var("FLASHROM_TESTER_UTILS_TEST", "present");
+        let env = collect_environment(&[
+            "FLASHROM_TESTER_UTILS_TEST",
+            "FLASHROM_TESTER_UTILS_TEST_UNSET",
+        ]);
+        assert!(!env.kernel.is_empty());
+        assert_eq!(
+            env.variables.into_iter().collect::<Vec<_>>(),
+            vec![(
+                "FLASHROM_TESTER_UTILS_TEST".to_string(),
+                "present".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_layout() {
+        use super::{parse_layout, LayoutRegion};
+
+        assert_eq!(
+            parse_layout("# comment\n\n00000000:00000fff RO\n0x1000:0x1fff RW_A\n"),
+            Ok(vec![
+                LayoutRegion {
+                    name: "RO".into(),
+                    start: 0,
+                    end: 0xfff
+                },
+                LayoutRegion {
+                    name: "RW_A".into(),
+                    start: 0x1000,
+                    end: 0x1fff
+                },
+            ])
+        );
+        assert!(parse_layout("0:fff").is_err());
+        assert!(parse_layout("0-fff RO").is_err());
+        assert!(parse_layout("fff:0 RO").is_err());
+        assert!(parse_layout("0:xyz RO").is_err());
+    }
+
+    #[test]
+    fn check_layout() {
+        use super::{check_layout, parse_layout};
+
+        let clean = parse_layout("0:fff RO\n1000:1fff RW_A\n2000:3fff RW_B").unwrap();
+        assert_eq!(check_layout(&clean, 0x4000), Ok(()));
+        assert!(check_layout(&clean, 0x2000).is_err());
+
+        let overlapping = parse_layout("0:fff RO\n800:17ff RW_A\n1000:1fff RW_B").unwrap();
+        let err = check_layout(&overlapping, 0x4000).unwrap_err();
+        assert!(err.contains("RO (0x0:0xfff) overlaps RW_A"), "{}", err);
+        assert!(err.contains("RW_A (0x800:0x17ff) overlaps RW_B"), "{}", err);
+        assert!(!err.contains("RO (0x0:0xfff) overlaps RW_B"), "{}", err);
+    }
+
+    #[test]
+    fn parse_chip_size() {
+        use super::parse_chip_size;
+
+        assert_eq!(parse_chip_size("65536"), Ok(64 << 10));
+        assert_eq!(parse_chip_size("0x800000"), Ok(8 << 20));
+        // Sizes that aren't a power of two are fine if they can be aligned.
+        assert_eq!(parse_chip_size("3145728"), Ok(3 << 20));
+        assert_eq!(parse_chip_size("0x3000"), Ok(12 << 10));
+        assert!(parse_chip_size("0").is_err());
+        assert!(parse_chip_size("-4096").is_err());
+        assert_eq!(
+            parse_chip_size("6000"),
+            Err("invalid chip size \"6000\": must be a positive multiple of 0x1000".into())
+        );
+        assert!(parse_chip_size("8M").is_err());
+    }
+
+    #[test]
+    fn diff_spans() {
+        use super::diff_spans;
+
+        assert_eq!(diff_spans(&[1, 2, 3], &[1, 2, 3]), []);
+        assert_eq!(
+            diff_spans(&[0, 1, 1, 0, 0, 1], &[0, 0, 0, 0, 0, 0]),
+            [(1, 2), (5, 1)]
+        );
+        assert_eq!(diff_spans(&[9, 9], &[0, 0]), [(0, 2)]);
+        // The excess of the longer image differs.
+        assert_eq!(diff_spans(&[0, 0, 1, 1], &[0, 0]), [(2, 2)]);
+        assert_eq!(diff_spans(&[0], &[1, 2, 3]), [(0, 3)]);
+    }
+
+    #[test]
+    fn describe_diff() {
+        use super::describe_diff;
+        use crate::fmap::FmapArea;
+
+        let area = |name: &str, offset, size| FmapArea {
+            name: name.into(),
+            offset,
+            size,
+        };
+        let areas = [area("RO", 0, 0x1000), area("RW", 0x1000, 0x1000)];
+        assert_eq!(
+            describe_diff(&[(0x10, 4), (0xff0, 0x20), (0x3000, 1)], &areas),
+            ["0x10+0x4 (RO)", "0xff0+0x20 (RO, RW)", "0x3000+0x1"]
+        );
+    }
+
+    #[test]
+    fn check_rom_size() {
+        use super::check_rom_size;
+
+        assert_eq!(check_rom_size(8 << 20), Ok(()));
+        // Even but not a power of two.
+        assert_eq!(
+            check_rom_size(12 << 20),
+            Err("invalid rom size 0xc00000, not a power of 2".into())
+        );
+        assert_eq!(check_rom_size(0), Err("invalid rom size provided".into()));
+    }
+
     #[test]
     fn get_layout_sizes() {
         use super::get_layout_sizes;
 
         assert_eq!(
-            get_layout_sizes(-128).err(),
+            get_layout_sizes(-128, None).err(),
             Some("invalid rom size provided".into())
         );
 
         assert_eq!(
-            get_layout_sizes(3 << 20).err(),
-            Some("invalid rom size, not a power of 2".into())
+            get_layout_sizes(0x3000, None).err(),
+            Some("invalid rom size 0x3000, too small to divide into 0x1000-byte blocks".into())
         );
 
         assert_eq!(
-            get_layout_sizes(64 << 10).unwrap(),
+            get_layout_sizes(64 << 10, None).unwrap(),
             LayoutSizes {
                 half_sz: 0x8000,
                 quad_sz: 0x4000,
@@ -237,5 +1137,108 @@ mod tests {
                 top_quad_bottom: 0xC000,
             }
         );
+
+        assert_eq!(
+            get_layout_sizes(16 << 10, None).unwrap(),
+            LayoutSizes {
+                half_sz: 0x2000,
+                quad_sz: 0x1000,
+                rom_top: 0x3FFF,
+                bottom_half_top: 0x1FFF,
+                bottom_quad_top: 0xFFF,
+                top_quad_bottom: 0x3000,
+            }
+        );
+
+        assert_eq!(
+            get_layout_sizes(4 << 10, None).err(),
+            Some("invalid rom size, smaller than the minimum of 16384 bytes".into())
+        );
+        // Quarters must span at least one whole block.
+        assert!(get_layout_sizes(4 << 10, Some(4 << 10)).is_err());
+    }
+
+    #[test]
+    fn layout_sections_fit_odd_sizes() {
+        use super::{get_layout_sizes, layout_section, LayoutNames, LAYOUT_ALIGN};
+
+        for &rom_sz in &[12 << 20, 3 << 20, (12 << 20) + 0x1800, 0x5000] {
+            let ls = get_layout_sizes(rom_sz, None).unwrap();
+            let section = |ln| {
+                let (name, start, len) = layout_section(&ls, ln);
+                assert!(len > 0, "{} is empty for {:#x}", name, rom_sz);
+                assert!(
+                    start + len <= rom_sz,
+                    "{} passes the end of a {:#x}-byte chip",
+                    name,
+                    rom_sz
+                );
+                assert_eq!(start % LAYOUT_ALIGN, 0, "{} is misaligned", name);
+                start..start + len
+            };
+
+            let bottom_quad = section(LayoutNames::BottomQuad);
+            let top_quad = section(LayoutNames::TopQuad);
+            assert!(bottom_quad.end <= top_quad.start);
+            assert_eq!(top_quad.end, rom_sz);
+            let bottom_half = section(LayoutNames::BottomHalf);
+            let top_half = section(LayoutNames::TopHalf);
+            assert_eq!(bottom_half.end, top_half.start);
+            assert_eq!(top_half.end, rom_sz);
+        }
+
+        assert_eq!(
+            layout_section(
+                &get_layout_sizes(12 << 20, None).unwrap(),
+                LayoutNames::TopQuad
+            ),
+            ("TOP_QUAD", 0x900000, 0x300000)
+        );
+    }
+
+    #[test]
+    fn layout_sections_block_aligned() {
+        use super::{get_layout_sizes, layout_section, LayoutNames};
+
+        let block = 4 << 10;
+        for &rom_sz in &[8 << 20, 12 << 20, 0x5000, 0x7000, 64 << 10] {
+            let ls = get_layout_sizes(rom_sz, Some(block)).unwrap();
+            for &ln in LayoutNames::ALL.iter() {
+                let (name, start, len) = layout_section(&ls, ln);
+                assert_eq!(
+                    start % block,
+                    0,
+                    "{} of {:#x} starts mid-block",
+                    name,
+                    rom_sz
+                );
+                assert_eq!(
+                    (start + len) % block,
+                    0,
+                    "{} of {:#x} ends mid-block",
+                    name,
+                    rom_sz
+                );
+            }
+        }
+        // Block sizes that can't be aligned to are errors, not panics.
+        assert!(get_layout_sizes(64 << 10, Some(0)).is_err());
+        assert!(get_layout_sizes(64 << 10, Some(-4096)).is_err());
+        assert!(get_layout_sizes(-4096, Some(4 << 10)).is_err());
+    }
+
+    #[test]
+    fn parse_erase_block() {
+        use super::parse_erase_block;
+
+        assert_eq!(parse_erase_block("0x1000"), Ok(4 << 10));
+        assert_eq!(
+            parse_erase_block("3000"),
+            Err("invalid erase block size \"3000\": must be a positive power of 2".into())
+        );
+        assert_eq!(
+            parse_erase_block("4k"),
+            Err("invalid erase block size \"4k\": invalid digit found in string".into())
+        );
     }
 }
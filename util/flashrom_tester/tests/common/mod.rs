@@ -0,0 +1,331 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! An in-memory stand-in for a flash chip, for running the test suite without
+//! hardware.
+
+use flashrom::{ErrorKind, FlashChip, Flashrom, FlashromError, ROMWriteSpecifics, WpStatus};
+use flashrom_tester::tester::TestRun;
+use flashrom_tester::tests::{self, RunOptions, RunOutcome};
+use std::cell::RefCell;
+use std::fs;
+use std::time::Duration;
+
+/// How long a write that changes the chip takes; writes that change nothing
+/// complete immediately, like flashrom skipping blocks that already match.
+const WRITE_DELAY: Duration = Duration::from_millis(20);
+
+pub struct FakeFlashrom {
+    state: RefCell<FakeChip>,
+}
+
+struct FakeChip {
+    contents: Vec<u8>,
+    /// Software write protect, and the (start, len) range it covers.
+    protected: Option<(usize, usize)>,
+    /// Whether --wp-list reports any ranges, which it doesn't through the
+    /// Linux kernel SPI driver.
+    lists_ranges: bool,
+}
+
+impl FakeFlashrom {
+    /// Create a chip of `size` bytes holding a recognizable pattern that
+    /// contains no erased (0xFF) bytes.
+    pub fn new(size: usize) -> FakeFlashrom {
+        FakeFlashrom {
+            state: RefCell::new(FakeChip {
+                contents: (0..size).map(|i| (i % 251) as u8).collect(),
+                protected: None,
+                lists_ranges: true,
+            }),
+        }
+    }
+
+    /// Behave like a chip behind the Linux kernel SPI driver, which lists no
+    /// protection ranges.
+    pub fn without_wp_list(self) -> FakeFlashrom {
+        self.state.borrow_mut().lists_ranges = false;
+        self
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.state.borrow().contents.clone()
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.state.borrow().protected.is_some()
+    }
+
+    /// Copy `data[start..start + len]` onto the chip, failing without changing
+    /// anything if the range is write protected.
+    fn program(&self, data: &[u8], start: usize, len: usize) -> Result<(), FlashromError> {
+        let mut chip = self.state.borrow_mut();
+        if data.len() != chip.contents.len() {
+            return Err(FlashromError::new(
+                ErrorKind::SizeMismatch,
+                format!(
+                    "Image size {} doesn't match chip size {}",
+                    data.len(),
+                    chip.contents.len()
+                ),
+            ));
+        }
+        if let Some((p_start, p_len)) = chip.protected {
+            if start < p_start + p_len && p_start < start + len {
+                return Err(FlashromError::new(
+                    ErrorKind::WriteProtected,
+                    "Write protected region cannot be written",
+                ));
+            }
+        }
+
+        let range = start..start + len;
+        if chip.contents[range.clone()] != data[range.clone()] {
+            std::thread::sleep(WRITE_DELAY);
+            chip.contents[range.clone()].copy_from_slice(&data[range]);
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, FlashromError> {
+    fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e).into())
+}
+
+/// Find the inclusive byte range of `name` in a flashrom layout file.
+fn layout_region(layout: &str, name: &str) -> Result<(usize, usize), FlashromError> {
+    for line in layout.lines() {
+        let mut fields = line.split_whitespace();
+        let (range, region) = match (fields.next(), fields.next()) {
+            (Some(range), Some(region)) => (range, region),
+            _ => continue,
+        };
+        if region != name {
+            continue;
+        }
+
+        let mut bounds = range
+            .splitn(2, ':')
+            .map(|x| usize::from_str_radix(x, 16).map_err(|e| e.to_string()));
+        match (bounds.next(), bounds.next()) {
+            (Some(start), Some(end)) => return Ok((start?, end?)),
+            _ => return Err(format!("Malformed layout line {:?}", line).into()),
+        }
+    }
+    Err(format!("No region {:?} in layout", name).into())
+}
+
+impl Flashrom for FakeFlashrom {
+    fn get_size(&self) -> Result<i64, FlashromError> {
+        Ok(self.state.borrow().contents.len() as i64)
+    }
+
+    fn name(&self) -> Result<(String, String), FlashromError> {
+        Ok(("Fake".into(), "FAKE_CHIP".into()))
+    }
+
+    fn probe_chips(&self) -> Result<Vec<String>, FlashromError> {
+        Ok(vec!["FAKE_CHIP".into()])
+    }
+
+    fn voltage(&self) -> Result<String, FlashromError> {
+        Err("Fake chips have no voltage".into())
+    }
+
+    fn write_file_with_layout(&self, rws: &ROMWriteSpecifics) -> Result<bool, FlashromError> {
+        let layout = String::from_utf8_lossy(&read_file(rws.layout_file.unwrap())?).into_owned();
+        let (start, end) = layout_region(&layout, rws.name_file.unwrap())?;
+        let data = read_file(rws.write_file.unwrap())?;
+        self.program(&data, start, end - start + 1)?;
+        Ok(true)
+    }
+
+    fn wp_range(&self, range: (i64, i64), wp_enable: bool) -> Result<bool, FlashromError> {
+        self.state.borrow_mut().protected = if wp_enable {
+            Some((range.0 as usize, range.1 as usize))
+        } else {
+            None
+        };
+        Ok(true)
+    }
+
+    fn wp_region(
+        &self,
+        layout_file: &str,
+        region: &str,
+        wp_enable: bool,
+    ) -> Result<bool, FlashromError> {
+        let layout = String::from_utf8_lossy(&read_file(layout_file)?).into_owned();
+        let (start, end) = layout_region(&layout, region)?;
+        self.wp_range((start as i64, (end - start + 1) as i64), wp_enable)
+    }
+
+    fn wp_list(&self) -> Result<String, FlashromError> {
+        if !self.state.borrow().lists_ranges {
+            return Ok(String::new());
+        }
+        let size = self.state.borrow().contents.len();
+        // Ranges from both the bottom and the top, like a real chip.
+        Ok([0, 0x1000, size / 4, size / 2, size]
+            .iter()
+            .flat_map(|&len| [(0, len), (size - len, len)])
+            .map(|(start, len)| format!("start=0x{:08x} length=0x{:08x}\n", start, len))
+            .collect())
+    }
+
+    fn wp_status(&self, en: bool) -> Result<bool, FlashromError> {
+        Ok(self.is_protected() == en)
+    }
+
+    fn wp_get_state(&self) -> Result<WpStatus, FlashromError> {
+        let protected = self.state.borrow().protected;
+        Ok(WpStatus {
+            enabled: protected.is_some(),
+            range: protected.map_or((0, 0), |(start, len)| (start as i64, len as i64)),
+            mode: if protected.is_some() {
+                "hardware"
+            } else {
+                "disabled"
+            }
+            .into(),
+        })
+    }
+
+    fn wp_toggle(&self, en: bool) -> Result<bool, FlashromError> {
+        let size = self.state.borrow().contents.len();
+        self.wp_range((0, size as i64), en)
+    }
+
+    fn read_into_file(&self, path: &str) -> Result<(), FlashromError> {
+        fs::write(path, &self.state.borrow().contents).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn read_region_into_file(&self, _path: &str, region: &str) -> Result<(), FlashromError> {
+        Err(format!("No FMAP, so no region {:?}", region).into())
+    }
+
+    fn write_from_file(&self, path: &str) -> Result<(), FlashromError> {
+        let data = read_file(path)?;
+        self.program(&data, 0, data.len())
+    }
+
+    fn verify_from_file(&self, path: &str) -> Result<(), FlashromError> {
+        if read_file(path)? != self.state.borrow().contents {
+            return Err("Verify failed".into());
+        }
+        Ok(())
+    }
+
+    fn verify_file_with_layout(
+        &self,
+        layout_file: &str,
+        region: &str,
+        path: &str,
+    ) -> Result<(), FlashromError> {
+        let layout = String::from_utf8_lossy(&read_file(layout_file)?).into_owned();
+        let (start, end) = layout_region(&layout, region)?;
+        let data = read_file(path)?;
+        if data.get(start..=end) != self.state.borrow().contents.get(start..=end) {
+            return Err("Verify failed".into());
+        }
+        Ok(())
+    }
+
+    fn erase(&self) -> Result<(), FlashromError> {
+        let size = self.state.borrow().contents.len();
+        self.program(&vec![0xFF; size], 0, size)
+    }
+
+    fn reinit_programmer(&self) -> Result<(), FlashromError> {
+        Ok(())
+    }
+
+    fn can_control_hw_wp(&self) -> bool {
+        false
+    }
+}
+
+/// The size of the chip most tests run against.
+pub const CHIP_SIZE: usize = 64 << 10;
+
+/// Run the tests called `names`, or every test if None, on `chip` as a chip
+/// of type `fc`, asserting the run leaves its contents and write protect
+/// configuration as it found them whether or not it succeeds.
+pub fn run_on(
+    chip: &FakeFlashrom,
+    fc: FlashChip,
+    opts: &RunOptions,
+    names: Option<&[&str]>,
+) -> Result<RunOutcome, Box<dyn std::error::Error>> {
+    let contents = chip.contents();
+    let wp = chip.wp_get_state().unwrap();
+    let outcome = tests::generic(
+        chip,
+        fc,
+        opts,
+        names.map(|names| names.iter().copied()),
+        None,
+        String::new(),
+    );
+    assert_eq!(chip.contents(), contents, "run left the chip modified");
+    assert_eq!(
+        chip.wp_get_state().unwrap(),
+        wp,
+        "run left write protect changed"
+    );
+    outcome
+}
+
+/// Run the tests called `names`, or every test if None, on a new dediprog
+/// chip of `CHIP_SIZE` bytes, panicking if the run fails internally or leaves
+/// the chip changed.
+pub fn assert_run_leaves_chip_unchanged(opts: &RunOptions, names: Option<&[&str]>) -> Vec<TestRun> {
+    run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        FlashChip::DEDIPROG,
+        opts,
+        names,
+    )
+    .expect("run should not fail internally")
+    .runs
+}
+
+/// Return the result of the test called `name`, panicking if it isn't there.
+pub fn find_run<'a>(runs: &'a [TestRun], name: &str) -> &'a TestRun {
+    runs.iter()
+        .find(|r| r.name == name)
+        .unwrap_or_else(|| panic!("{} missing from the report", name))
+}
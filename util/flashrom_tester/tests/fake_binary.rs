@@ -0,0 +1,185 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+use flashrom::{FlashChip, FlashromCmd};
+use flashrom_tester::tester::TestConclusion;
+use flashrom_tester::tests::{self, RunOptions};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Install the fake flashrom in `dir` emulating a chip of `size` bytes, and
+/// return a FlashromCmd that runs it.
+fn fake_flashrom(dir: &Path, size: usize) -> FlashromCmd {
+    let path = dir.join("flashrom");
+    fs::copy(
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/fake_flashrom.sh"
+        ),
+        &path,
+    )
+    .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        dir.join("chip.bin"),
+        (0..size).map(|i| i as u8).collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    FlashromCmd {
+        path: path.to_string_lossy().into_owned(),
+        fc: FlashChip::DEDIPROG,
+        probe_filter: None,
+        trace_args: false,
+        transcript: None,
+        max_runtime: Some(Duration::from_secs(30)),
+        retries: 0,
+        retry_delay: Duration::default(),
+        size: Default::default(),
+        programmer: None,
+        programmer_params: Vec::new(),
+        write_verify: Default::default(),
+        progress: false,
+        verbosity: 0,
+        heartbeat: None,
+        flashrom_log: None,
+    }
+}
+
+/// Tests the fake supports; those needing layouts and regions are left out.
+const SUPPORTED_TESTS: &[&str] = &[
+    "Get_device_name",
+    "Unambiguous_detection",
+    "Toggle_WP",
+    "Redundant_WP_disable",
+    "Name_under_WP",
+    "Size_under_WP",
+    "Erase_and_Write",
+    "Oversize_write",
+    "Fail_to_verify",
+    "Reinit_read_consistency",
+    "Erase_under_WP",
+];
+
+/// The suite runs end to end against a flashrom binary, passing every test the
+/// fake supports and leaving the chip as it found it.
+#[test]
+fn suite_passes_against_fake_binary() {
+    let dir = tempfile::tempdir().unwrap();
+    let cmd = fake_flashrom(dir.path(), 64 << 10);
+    let initial = fs::read(dir.path().join("chip.bin")).unwrap();
+    let report_path = dir.path().join("report.txt");
+
+    let runs = tests::generic(
+        &cmd,
+        FlashChip::DEDIPROG,
+        &RunOptions {
+            allow_destructive: true,
+            report_file: Some(report_path.to_string_lossy().into_owned()),
+            ..RunOptions::default()
+        },
+        Some(SUPPORTED_TESTS.iter().copied()),
+        None,
+        String::new(),
+    )
+    .expect("suite should run without internal errors")
+    .runs;
+
+    for run in &runs {
+        assert_eq!(run.conclusion, TestConclusion::Pass, "{}", run.name);
+    }
+    assert_eq!(runs.len(), SUPPORTED_TESTS.len());
+    assert_eq!(
+        fs::read(dir.path().join("chip.bin")).unwrap(),
+        initial,
+        "suite left the chip modified"
+    );
+    assert!(!dir.path().join("wp").exists(), "suite left WP enabled");
+
+    let report = fs::read_to_string(&report_path).unwrap();
+    assert!(
+        report.contains(r#"vendor="Fake" name="FAKE25""#),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("<+> Erase_and_Write test: Pass"),
+        "{}",
+        report
+    );
+}
+
+/// Preflight probes the chip through the binary without writing it.
+#[test]
+fn preflight_against_fake_binary() {
+    let dir = tempfile::tempdir().unwrap();
+    let cmd = fake_flashrom(dir.path(), 64 << 10);
+
+    let runs = tests::generic(
+        &cmd,
+        FlashChip::DEDIPROG,
+        &RunOptions {
+            preflight_only: true,
+            ..RunOptions::default()
+        },
+        None::<std::iter::Empty<&str>>,
+        None,
+        String::new(),
+    )
+    .expect("preflight should run without internal errors")
+    .runs;
+
+    assert!(!runs.is_empty());
+    for run in &runs {
+        assert_eq!(run.conclusion, TestConclusion::Pass, "{}", run.name);
+    }
+}
+
+/// Reading into memory returns the chip contents and removes the scratch file
+/// the binary read into.
+#[test]
+fn read_to_vec_removes_scratch() {
+    use flashrom::Flashrom;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cmd = fake_flashrom(dir.path(), 64 << 10);
+    let scratch = dir.path().join("scratch.bin");
+
+    let contents = cmd.read_to_vec(&scratch.to_string_lossy()).unwrap();
+    assert_eq!(contents, fs::read(dir.path().join("chip.bin")).unwrap());
+    assert!(!scratch.exists());
+}
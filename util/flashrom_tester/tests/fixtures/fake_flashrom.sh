@@ -0,0 +1,93 @@
+#!/bin/sh
+#
+# A stand-in for the flashrom binary, emulating a chip in chip.bin beside this
+# script so the command line handling and output parsing of FlashromCmd can be
+# tested end to end. Write protect state is kept in wp as "start len" while
+# enabled. Layouts and regions aren't supported.
+
+dir=$(dirname "$0")
+chip="$dir/chip.bin"
+wp="$dir/wp"
+size=$(wc -c < "$chip")
+
+op=
+file=
+range=
+while [ $# -gt 0 ]; do
+	case "$1" in
+	-p) shift ;;
+	-r | -w | -v) op=$1; file=$2; shift ;;
+	-E | --flash-size | --flash-name | --wp-status | --wp-list | --wp-enable | --wp-disable) op=$1 ;;
+	--wp-range) range=$2; shift ;;
+	-V | --progress | --noverify | --noverify-all) ;;
+	*)
+		echo "fake flashrom: unsupported argument $1" >&2
+		exit 1
+		;;
+	esac
+	shift
+done
+
+echo "Found Fake flash chip \"FAKE25\" ($((size / 1024)) kB, SPI) on fake."
+
+# Writes fail while any of the chip is protected, as with status register
+# protection on a real chip.
+check_unprotected() {
+	if [ -s "$wp" ] && [ "$(cut -d' ' -f2 "$wp")" -ne 0 ]; then
+		echo "Block protection could not be disabled!" >&2
+		exit 1
+	fi
+}
+
+check_size() {
+	image_size=$(wc -c < "$1")
+	if [ "$image_size" -ne "$size" ]; then
+		echo "Error: Image size ($image_size B) doesn't match the flash chip's size ($size B)!" >&2
+		exit 1
+	fi
+}
+
+case "$op" in
+-r) cp "$chip" "$file" ;;
+-w)
+	check_size "$file"
+	check_unprotected
+	cp "$file" "$chip"
+	;;
+-v)
+	check_size "$file"
+	if ! cmp -s "$file" "$chip"; then
+		echo "VERIFY FAILED" >&2
+		exit 1
+	fi
+	;;
+-E)
+	check_unprotected
+	tr '\000' '\377' < /dev/zero | head -c "$size" > "$chip"
+	;;
+--flash-size) echo "$size" ;;
+--flash-name) echo "vendor=\"Fake\" name=\"FAKE25\"" ;;
+--wp-status)
+	if [ -s "$wp" ]; then
+		read -r start len < "$wp"
+		echo "WP: write protect is enabled."
+	else
+		start=0
+		len=0
+		echo "WP: write protect is disabled."
+	fi
+	printf 'WP: write protect range: start=0x%08x, len=0x%08x\n' "$start" "$len"
+	;;
+--wp-list)
+	echo "Available protection ranges:"
+	printf 'start=0x%08x length=0x%08x\n' 0 0 0 $((size / 2)) $((size / 2)) $((size / 2)) 0 "$size"
+	;;
+--wp-enable)
+	if [ -n "$range" ]; then
+		echo "$(($(echo "$range" | cut -d, -f1))) $(($(echo "$range" | cut -d, -f2)))" > "$wp"
+	elif [ ! -s "$wp" ]; then
+		echo "0 0" > "$wp"
+	fi
+	;;
+--wp-disable) rm -f "$wp" ;;
+esac
@@ -0,0 +1,589 @@
+//
+// Copyright 2026, Google Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//    * Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above
+// copyright notice, this list of conditions and the following disclaimer
+// in the documentation and/or other materials provided with the
+// distribution.
+//    * Neither the name of Google Inc. nor the names of its
+// contributors may be used to endorse or promote products derived from
+// this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//
+// Alternatively, this software may be distributed under the terms of the
+// GNU General Public License ("GPL") version 2 as published by the Free
+// Software Foundation.
+//
+
+//! Runs of the test suite against an in-memory chip. Every run is checked to
+//! leave the chip as it found it, so each case only asserts what it is about.
+
+mod common;
+
+use common::{assert_run_leaves_chip_unchanged, find_run, run_on, FakeFlashrom, CHIP_SIZE};
+use flashrom::{FlashChip, Flashrom};
+use flashrom_tester::tester::{TestConclusion, TestRun};
+use flashrom_tester::tests::RunOptions;
+use flashrom_tester::utils;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+fn destructive() -> RunOptions {
+    RunOptions {
+        allow_destructive: true,
+        ..RunOptions::default()
+    }
+}
+
+fn conclusions(runs: Vec<TestRun>) -> Vec<(String, TestConclusion)> {
+    runs.into_iter().map(|r| (r.name, r.conclusion)).collect()
+}
+
+/// Running the whole suite twice in one process gives the same conclusions,
+/// so no state leaks from one run to the next.
+#[test]
+fn suite_runs_twice() {
+    let chip = FakeFlashrom::new(CHIP_SIZE);
+    let run = || {
+        run_on(&chip, FlashChip::DEDIPROG, &destructive(), None)
+            .expect("run should not fail internally")
+            .runs
+            .into_iter()
+            .map(|r| (r.name, r.conclusion, r.modified_flash))
+            .collect::<Vec<_>>()
+    };
+
+    let first = run();
+    assert!(!first.is_empty());
+    assert_eq!(first, run());
+}
+
+/// Only tests that write or erase the flash are reported as modifying it.
+#[test]
+fn modified_flash_reported() {
+    let runs = assert_run_leaves_chip_unchanged(&destructive(), None);
+    assert!(!find_run(&runs, "Get_device_name").modified_flash);
+    assert!(find_run(&runs, "Erase_and_Write").modified_flash);
+}
+
+/// Without opting in to destructive tests, only the read-only ones run and the
+/// rest are reported as skipped.
+#[test]
+fn destructive_tests_skipped_by_default() {
+    let runs = assert_run_leaves_chip_unchanged(&RunOptions::default(), None);
+
+    for name in &["Get_device_name", "Fail_to_verify"] {
+        assert_eq!(
+            find_run(&runs, name).conclusion,
+            TestConclusion::Pass,
+            "{}",
+            name
+        );
+    }
+    for name in &["Erase_and_Write", "Toggle_WP", "Lock_sections"] {
+        let r = find_run(&runs, name);
+        assert_eq!(r.conclusion, TestConclusion::Skipped, "{}", name);
+        assert!(!r.modified_flash, "{}", name);
+        assert_eq!(
+            r.error.as_ref().unwrap().to_string(),
+            "destructive, not enabled"
+        );
+    }
+}
+
+/// A preflight run performs only the quick checks, all of which pass on a
+/// healthy chip.
+#[test]
+fn preflight_only() {
+    let runs = assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            preflight_only: true,
+            ..RunOptions::default()
+        },
+        None,
+    );
+
+    let names: Vec<&str> = runs.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["Get_device_name", "Chip_size", "Not_blank"]);
+    for r in &runs {
+        assert_eq!(r.conclusion, TestConclusion::Pass, "{} failed", r.name);
+        assert!(!r.modified_flash);
+    }
+}
+
+/// The report file gets the same report as stdout, without colors.
+#[test]
+fn report_file_is_plain() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("report.txt");
+    assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            preflight_only: true,
+            report_file: Some(path.to_string_lossy().into_owned()),
+            ..RunOptions::default()
+        },
+        None,
+    );
+
+    let report = fs::read_to_string(&path).unwrap();
+    assert!(report.contains("<+> Not_blank test: Pass"), "{}", report);
+    assert!(report.contains("Summary: 3 passed"), "{}", report);
+    assert!(!report.contains('\x1b'), "{}", report);
+}
+
+/// The EC runs through the generic suite, skipping the section locks it can't
+/// do instead of failing them.
+#[test]
+fn ec_runs_generic_suite() {
+    let runs = run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        FlashChip::EC,
+        &destructive(),
+        None,
+    )
+    .expect("run should not fail internally")
+    .runs;
+
+    for name in &["Get_device_name", "Unambiguous_detection", "Toggle_WP"] {
+        assert_eq!(
+            find_run(&runs, name).conclusion,
+            TestConclusion::Pass,
+            "{}",
+            name
+        );
+    }
+    let r = find_run(&runs, "Lock_sections");
+    assert_eq!(r.conclusion, TestConclusion::Skipped);
+    assert!(!r.modified_flash);
+}
+
+/// Without a list of protection ranges, as through the Linux kernel SPI
+/// driver, the tests that depend on one are skipped with the reason rather
+/// than failing confusingly.
+#[test]
+fn range_tests_skip_without_wp_list() {
+    let chip = FakeFlashrom::new(CHIP_SIZE).without_wp_list();
+    let runs = run_on(&chip, FlashChip::DEDIPROG, &destructive(), None)
+        .expect("run should not fail internally")
+        .runs;
+
+    for name in &["Lock", "WP_granularity", "Lock_sections"] {
+        let r = find_run(&runs, name);
+        assert_eq!(r.conclusion, TestConclusion::Skipped, "{}", name);
+        let reason = r.error.as_ref().map(|e| e.to_string()).unwrap_or_default();
+        assert!(
+            reason.contains("Linux kernel SPI driver"),
+            "{}: {}",
+            name,
+            reason
+        );
+    }
+    assert_eq!(
+        find_run(&runs, "Erase_and_Write").conclusion,
+        TestConclusion::Pass
+    );
+}
+
+/// The layout arithmetic and partial lock tests work the same on the smallest
+/// supported chip as on a larger one.
+#[test]
+fn smallest_chip_matches_large() {
+    let run = |size| {
+        run_on(
+            &FakeFlashrom::new(size),
+            FlashChip::DEDIPROG,
+            &destructive(),
+            None,
+        )
+        .expect("run should not fail internally")
+        .runs
+    };
+    assert_eq!(
+        conclusions(run(utils::MIN_CHIP_SIZE as usize)),
+        conclusions(run(1 << 20))
+    );
+}
+
+/// A chip too small for each quarter to span an erase block is refused before
+/// any test runs.
+#[test]
+fn chip_below_minimum_fails() {
+    let err = run_on(
+        &FakeFlashrom::new(4 << 10),
+        FlashChip::DEDIPROG,
+        &destructive(),
+        None,
+    )
+    .err()
+    .expect("run should fail on a 4 KiB chip");
+    assert!(
+        err.to_string().contains("smaller than the minimum"),
+        "{}",
+        err
+    );
+}
+
+/// The partial lock tests pass when protecting layout regions by name, just as
+/// they do when protecting by offset.
+#[test]
+fn partial_locks_by_region() {
+    let runs = assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            wp_by_region: true,
+            test_filter: Some(regex::Regex::new("^Lock_(top|bottom)_(quad|half)$").unwrap()),
+            ..destructive()
+        },
+        None,
+    );
+
+    assert_eq!(runs.len(), 4);
+    for r in &runs {
+        assert_eq!(r.conclusion, TestConclusion::Pass, "{} failed", r.name);
+    }
+}
+
+fn run_persistence(fc: FlashChip) -> TestConclusion {
+    let runs = run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        fc,
+        &destructive(),
+        Some(&["Reboot_WP_persistence"]),
+    )
+    .expect("run should not fail internally")
+    .runs;
+    assert_eq!(runs.len(), 1);
+    runs[0].conclusion
+}
+
+/// Resetting a Dediprog does nothing, so WP persistence can't be checked.
+#[test]
+fn reboot_wp_dediprog_is_skipped() {
+    assert_eq!(
+        run_persistence(FlashChip::DEDIPROG),
+        TestConclusion::Skipped
+    );
+}
+
+/// The host flash can't be reset from within the process, so WP persistence
+/// is skipped.
+#[test]
+fn reboot_wp_host_is_skipped() {
+    assert_eq!(run_persistence(FlashChip::HOST), TestConclusion::Skipped);
+}
+
+/// A partial protection range set before the run is lost when the tests
+/// toggle software WP, unless --restore-on-exit puts it back.
+#[test]
+fn restore_on_exit_restores_wp_range() {
+    let chip = FakeFlashrom::new(CHIP_SIZE);
+    chip.wp_range((0, 0x1000), true).unwrap();
+
+    run_on(
+        &chip,
+        FlashChip::DEDIPROG,
+        &RunOptions {
+            restore_on_exit: true,
+            ..destructive()
+        },
+        None,
+    )
+    .expect("run should not fail internally");
+}
+
+/// Each selected test runs once per iteration and reports how often it passed.
+#[test]
+fn repeat_counts_passes() {
+    let runs = assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            repeat: 3,
+            // No chip contents have this digest, so Expected_digest fails every time.
+            expected_digest: Some("crc32:00000000".parse().unwrap()),
+            ..destructive()
+        },
+        Some(&["Lock_top_quad", "Expected_digest"]),
+    );
+
+    let results: Vec<_> = runs
+        .iter()
+        .map(|r| (r.name.as_str(), r.conclusion, r.passes))
+        .collect();
+    assert_eq!(
+        results,
+        [
+            (
+                "Expected_digest",
+                TestConclusion::UnexpectedFail,
+                Some((0, 3))
+            ),
+            ("Lock_top_quad", TestConclusion::Pass, Some((3, 3))),
+        ]
+    );
+}
+
+/// With no time to spare, every test is reported as not run.
+#[test]
+fn exhausted_budget_runs_nothing() {
+    let runs = assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            time_budget: Some(Duration::from_secs(0)),
+            ..destructive()
+        },
+        None,
+    );
+
+    assert!(!runs.is_empty());
+    for r in &runs {
+        assert_eq!(r.conclusion, TestConclusion::NotRun, "{} was run", r.name);
+        assert!(!r.modified_flash);
+    }
+}
+
+/// The hooks run exactly once around the whole run, however many tests and
+/// iterations it has.
+#[test]
+fn hooks_run_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    assert_run_leaves_chip_unchanged(
+        &RunOptions {
+            repeat: 2,
+            pre_run: Some(format!("echo pre >> {}", log.display())),
+            post_run: Some(format!("echo post >> {}", log.display())),
+            ..destructive()
+        },
+        Some(&["Get_device_name", "Erase_and_Write"]),
+    );
+    assert_eq!(fs::read_to_string(&log).unwrap(), "pre\npost\n");
+}
+
+/// A failing pre-run hook aborts the run before any test, but the post-run
+/// hook still runs to clean up.
+#[test]
+fn failed_pre_run_aborts() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("hooks.log");
+    let err = run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        FlashChip::DEDIPROG,
+        &RunOptions {
+            pre_run: Some("exit 1".into()),
+            post_run: Some(format!("echo post >> {}", log.display())),
+            ..destructive()
+        },
+        None,
+    )
+    .err()
+    .expect("run should fail when the pre-run hook does");
+    assert!(err.to_string().contains("Pre-run hook failed"), "{}", err);
+    assert_eq!(fs::read_to_string(&log).unwrap(), "post\n");
+}
+
+fn run_filtered(
+    re: &str,
+    exclude: &[&str],
+) -> Result<Vec<(String, TestConclusion)>, Box<dyn std::error::Error>> {
+    let opts = RunOptions {
+        test_filter: Some(
+            regex::RegexBuilder::new(re)
+                .case_insensitive(true)
+                .build()
+                .unwrap(),
+        ),
+        exclude_tests: exclude.iter().map(|s| s.to_string()).collect(),
+        ..destructive()
+    };
+    let outcome = run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        FlashChip::DEDIPROG,
+        &opts,
+        None,
+    )?;
+    Ok(conclusions(outcome.runs))
+}
+
+/// Only the tests whose names match the filter are run, ignoring case.
+#[test]
+fn filter_selects_matching_tests() {
+    assert_eq!(
+        run_filtered("^lock_(top|bottom)_quad$", &[]).unwrap(),
+        [
+            ("Lock_top_quad".into(), TestConclusion::Pass),
+            ("Lock_bottom_quad".into(), TestConclusion::Pass)
+        ]
+    );
+}
+
+/// Excluded tests are not run but still appear in the report, as skipped.
+#[test]
+fn exclude_reports_skipped() {
+    assert_eq!(
+        run_filtered("^lock_(top|bottom)_quad$", &["LOCK_TOP_QUAD"]).unwrap(),
+        [
+            ("Lock_top_quad".into(), TestConclusion::Skipped),
+            ("Lock_bottom_quad".into(), TestConclusion::Pass)
+        ]
+    );
+}
+
+/// A filter that excludes every test is an error, not an empty report.
+#[test]
+fn filter_matching_nothing_fails() {
+    let err = run_filtered("no_such_test", &[]).unwrap_err();
+    assert!(err.to_string().contains("No tests match"), "{}", err);
+}
+
+/// The individual section lock tests aren't run by default, but a filter can
+/// still select them alongside Lock_sections.
+#[test]
+fn filter_selects_individual_sections() {
+    assert_eq!(
+        run_filtered("^lock_", &[]).unwrap(),
+        [
+            ("Lock_sections".into(), TestConclusion::Pass),
+            ("Lock_top_quad".into(), TestConclusion::Pass),
+            ("Lock_bottom_quad".into(), TestConclusion::Pass),
+            ("Lock_bottom_half".into(), TestConclusion::Pass),
+            ("Lock_top_half".into(), TestConclusion::Pass)
+        ]
+    );
+}
+
+fn run_in(work_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = RunOptions {
+        work_dir: Some(work_dir.to_path_buf()),
+        ..destructive()
+    };
+    run_on(
+        &FakeFlashrom::new(CHIP_SIZE),
+        FlashChip::DEDIPROG,
+        &opts,
+        Some(&["Lock_top_quad"]),
+    )
+    .map(|_| ())
+}
+
+/// Generated files go under the work directory and are removed after the run.
+#[test]
+fn files_removed_from_work_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    run_in(dir.path()).unwrap();
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+/// A work directory that can't be used fails the run before any test starts.
+#[test]
+fn missing_work_dir_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = run_in(&dir.path().join("missing")).unwrap_err();
+    assert!(
+        err.to_string().contains("temporary directory"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+/// Comparing the chip with an image reads it through the work directory, and
+/// leaves nothing behind there.
+#[test]
+fn diff_chip_uses_work_dir() {
+    let chip = FakeFlashrom::new(CHIP_SIZE);
+    let images = tempfile::tempdir().unwrap();
+    let reference = images.path().join("reference.bin");
+    let mut image = chip.contents();
+    image[0x100] ^= 0xff;
+    fs::write(&reference, &image).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let spans = utils::diff_chip(&chip, &reference.to_string_lossy(), Some(dir.path())).unwrap();
+    assert_eq!(spans.len(), 1, "{:?}", spans);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+/// Verifying against a matching image reports nothing, and a mismatch reports
+/// where the chip differs, without modifying it either way.
+#[test]
+fn verify_chip_against_image() {
+    use utils::verify_chip;
+
+    let chip = FakeFlashrom::new(CHIP_SIZE);
+    let initial = chip.contents();
+    let dir = tempfile::tempdir().unwrap();
+    let reference = dir.path().join("reference.bin");
+
+    fs::write(&reference, &initial).unwrap();
+    let reference = reference.to_string_lossy().into_owned();
+    assert_eq!(
+        verify_chip(&chip, &reference, None).unwrap(),
+        Vec::<String>::new()
+    );
+
+    let mut image = initial.clone();
+    image[0x2000..0x2010].iter_mut().for_each(|b| *b ^= 0xff);
+    fs::write(&reference, &image).unwrap();
+    assert_eq!(
+        verify_chip(&chip, &reference, None).unwrap(),
+        vec!["0x2000+0x10"]
+    );
+    assert_eq!(chip.contents(), initial, "verification modified the chip");
+}
+
+/// Dropping a TestEnv removes the files it created, even if the run using it
+/// stopped partway.
+#[test]
+fn test_env_drop_removes_files() {
+    use flashrom_tester::hw_wp::Unsupported;
+    use flashrom_tester::tester::TestEnv;
+    use std::rc::Rc;
+
+    let chip = FakeFlashrom::new(CHIP_SIZE);
+    let dir = tempfile::tempdir().unwrap();
+    let env = TestEnv::create(
+        FlashChip::DEDIPROG,
+        &chip,
+        CHIP_SIZE as i64,
+        Vec::new(),
+        true,
+        Rc::new(Unsupported),
+        false,
+        Some(dir.path()),
+        None,
+        0,
+    )
+    .unwrap();
+    let scratch = env.scratch_file("scratch.bin");
+    fs::write(&scratch, b"scratch").unwrap();
+    let paths = [
+        env.golden_file().to_string(),
+        env.random_data_file().to_string(),
+        env.layout_file().to_string(),
+        scratch,
+    ];
+    for path in &paths {
+        assert!(Path::new(path).exists(), "{} missing", path);
+    }
+
+    drop(env);
+    for path in &paths {
+        assert!(!Path::new(path).exists(), "{} left behind", path);
+    }
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}